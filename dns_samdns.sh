@@ -0,0 +1,55 @@
+#!/usr/bin/env sh
+# shellcheck disable=SC2034
+dns_samdns_info='sam-dns-tools
+Site: github.com/TheQuantumPhysicist/sam-dns-tools
+Options:
+ SAMDNS_DIR The directory where sam-dns-tools is cloned (with config.yaml inside)
+ SAMDNS_PROXY Optional proxy for the DNS provider API calls, e.g., socks5://1.2.3.4:1080
+'
+
+# Copy this file to acme.sh's dnsapi directory and use it with `acme.sh --issue --dns dns_samdns ...`
+
+########  Public functions #####################
+
+# Usage: dns_samdns_add _acme-challenge.www.example.com "XKrxpRBosdIKFzxW_CT3KLZNf6q0HG9i01zxXp5CPBs"
+dns_samdns_add() {
+  fulldomain=$1
+  txtvalue=$2
+
+  _samdns_run add "$fulldomain" "$txtvalue"
+}
+
+# Usage: dns_samdns_rm _acme-challenge.www.example.com "XKrxpRBosdIKFzxW_CT3KLZNf6q0HG9i01zxXp5CPBs"
+dns_samdns_rm() {
+  fulldomain=$1
+  txtvalue=$2
+
+  _samdns_run rm "$fulldomain" "$txtvalue"
+}
+
+####################  Private functions below ##################################
+
+_samdns_run() {
+  operation=$1
+  fulldomain=$2
+  txtvalue=$3
+
+  SAMDNS_DIR="${SAMDNS_DIR:-$(_readaccountconf_mutable SAMDNS_DIR)}"
+  SAMDNS_PROXY="${SAMDNS_PROXY:-$(_readaccountconf_mutable SAMDNS_PROXY)}"
+
+  if [ -z "$SAMDNS_DIR" ]; then
+    _err "SAMDNS_DIR is not set. Set it to the directory where sam-dns-tools is cloned."
+    return 1
+  fi
+
+  _saveaccountconf_mutable SAMDNS_DIR "$SAMDNS_DIR"
+  _saveaccountconf_mutable SAMDNS_PROXY "$SAMDNS_PROXY"
+
+  _info "Running sam-dns-tools acme-sh $operation for $fulldomain"
+
+  if [ -z "$SAMDNS_PROXY" ]; then
+    (cd "$SAMDNS_DIR" && cargo run -- acme-sh "$operation" "$fulldomain" "$txtvalue")
+  else
+    (cd "$SAMDNS_DIR" && cargo run -- acme-sh --proxy="$SAMDNS_PROXY" "$operation" "$fulldomain" "$txtvalue")
+  fi
+}
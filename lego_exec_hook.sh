@@ -0,0 +1,24 @@
+#!/bin/bash
+
+# lego calls this script (as EXEC_PATH) with one of:
+#   present|cleanup <fqdn> <value>
+#   present|cleanup -- <domain> <token> <key-authorization>    (with EXEC_MODE=RAW)
+# EXEC_MODE is passed on to the program through the environment.
+
+SCRIPT_DIR="$(cd "$(dirname "${BASH_SOURCE[0]}")" && pwd)"
+cd "$SCRIPT_DIR" || exit 1
+
+echo "Running lego exec hook with args: $*"
+
+# the env var PROXY_FOR_CERTBOT_DNS_HOOK is the proxy to be used
+if [ -z "$PROXY_FOR_CERTBOT_DNS_HOOK" ]; then
+    echo "No proxy provided."
+    cargo run -- lego-exec "$@" || exit 1
+else
+    echo "Proxy provided: $PROXY_FOR_CERTBOT_DNS_HOOK"
+    cargo run -- lego-exec --proxy="$PROXY_FOR_CERTBOT_DNS_HOOK" "$@" || exit 1
+fi
+
+echo "Done running lego exec hook with args: $*"
+
+exit 0
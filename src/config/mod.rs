@@ -5,7 +5,15 @@ use std::{
 
 use serde::{Deserialize, Serialize};
 
-use crate::{dns_providers::epik::Epik, traits::domain_control::DomainController};
+use crate::{
+    dns_providers::epik::Epik,
+    features::{
+        dhcp_leases::DhcpLeasesConfig,
+        dyndns::{ip_detection::IpDetectionConfig, jobs::DynDnsConfig},
+        serve_dyndns2::DynDns2ServerConfig,
+    },
+    traits::domain_control::DomainController,
+};
 
 #[derive(thiserror::Error, Debug)]
 pub enum ConfigError {
@@ -15,6 +23,8 @@ pub enum ConfigError {
     FileExistsButCannotBeReadToString(std::io::Error),
     #[error("Could not parse file to config; either invalid yaml or missing config: {0}")]
     FileFormatCouldNotBeParsed(serde_yml::Error),
+    #[error("Invalid config: {0}")]
+    InvalidConfig(String),
 }
 
 /// The configs of different providers
@@ -31,6 +41,18 @@ pub enum ConfigError {
 pub struct Config {
     // The list of Epik services to be used
     pub epik_configs: Vec<Epik>,
+    // How the public address is detected in dyndns mode
+    #[serde(default)]
+    pub ip_detection: IpDetectionConfig,
+    // The dyndns jobs, run when dyndns is started without a domain name and a subdomain
+    #[serde(default)]
+    pub dyndns: DynDnsConfig,
+    // The users of the `serve-dyndns2` server, and the hostnames each of them may update
+    #[serde(default)]
+    pub dyndns2_server: DynDns2ServerConfig,
+    // The DHCP lease files whose hosts are published by `dhcp-leases`
+    #[serde(default)]
+    pub dhcp_leases: Option<DhcpLeasesConfig>,
 }
 
 impl Config {
@@ -47,9 +69,18 @@ impl Config {
         let config: Config = serde_yml::from_str(&config_file_data)
             .map_err(ConfigError::FileFormatCouldNotBeParsed)?;
 
+        config.check().map_err(ConfigError::InvalidConfig)?;
+
         Ok(config)
     }
 
+    /// Checks what the parsing alone can't, e.g., settings that contradict each other
+    pub fn check(&self) -> Result<(), String> {
+        self.ip_detection.check()?;
+
+        Ok(())
+    }
+
     pub fn into_domain_controllers(
         self,
     ) -> std::collections::BTreeMap<String, Box<dyn DomainController>> {
@@ -1,7 +1,9 @@
 use reqwest::Method;
 use serde::{Deserialize, Serialize};
 
-use crate::traits::domain_control::{DnsRecord, DnsRecordType, DomainController};
+use crate::traits::domain_control::{
+    DnsRecord, DnsRecordType, DomainController, ProviderCapabilities,
+};
 
 use super::helpers::compare_dns_txt_value;
 
@@ -62,6 +64,34 @@ impl Epik {
         Ok(resp_json)
     }
 
+    fn create_dns_record(
+        &self,
+        client_maker: &dyn Fn() -> reqwest::blocking::Client,
+        payload: CreateHostRecordsPayload,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let url = format!(
+            "https://usersapiv2.epik.com/v2/domains/{}/records?SIGNATURE={}",
+            self.domain_name, self.signature
+        );
+
+        let body = serde_json::to_string(&payload)
+            .expect("Serializing CreateHostRecordsPayload to JSON should never fail");
+        // requests are wrapped in this "create_host_records_payload" key
+        let body = format!("{{ \"create_host_records_payload\": {body} }}");
+
+        let client = client_maker();
+
+        let resp = client.post(url).body(body).send()?;
+
+        if !resp.status().is_success() {
+            return Err(Box::new(Error::Reqwest(
+                resp.error_for_status().unwrap_err(),
+            )));
+        }
+
+        Ok(())
+    }
+
     fn delete_dns_record(
         &self,
         client_maker: &dyn Fn() -> reqwest::blocking::Client,
@@ -112,10 +142,18 @@ impl TryFrom<EpikDnsEntry> for DnsRecord {
     type Error = String;
 
     fn try_from(value: EpikDnsEntry) -> Result<Self, Self::Error> {
+        // `aux` is the priority of MX and SRV records, and meaningless for the others
+        let priority = match value.record_type {
+            DnsRecordType::MX | DnsRecordType::SRV => Some(value.aux),
+            _ => None,
+        };
+
         Ok(Self {
             name: value.name,
             record_type: value.record_type,
             value: value.data,
+            ttl: Some(value.ttl),
+            priority,
         })
     }
 }
@@ -135,6 +173,25 @@ struct CreateHostRecordsPayload {
 }
 
 impl DomainController for Epik {
+    fn provider_name(&self) -> &'static str {
+        "epik"
+    }
+
+    fn capabilities(&self) -> ProviderCapabilities {
+        ProviderCapabilities {
+            record_types: vec![
+                DnsRecordType::A,
+                DnsRecordType::AAAA,
+                DnsRecordType::CAA,
+                DnsRecordType::CNAME,
+                DnsRecordType::MX,
+                DnsRecordType::TXT,
+            ],
+            ttl: true,
+            priority: true,
+        }
+    }
+
     fn add_dns_record(
         &self,
         client_maker: &dyn Fn() -> reqwest::blocking::Client,
@@ -142,33 +199,48 @@ impl DomainController for Epik {
         record_type: DnsRecordType,
         value: &str,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        let url = format!(
-            "https://usersapiv2.epik.com/v2/domains/{}/records?SIGNATURE={}",
-            self.domain_name, self.signature
-        );
-
-        let body = serde_json::to_string(&CreateHostRecordsPayload {
-            host: name.to_string(),
-            record_type,
-            data: value.to_string(),
-            aux: DEFAULT_AUX,
-            ttl: DEFAULT_TTL,
-        })
-        .expect("Serializing CreateHostRecordsPayload to JSON should never fail");
-        // requests are wrapped in this "create_host_records_payload" key
-        let body = format!("{{ \"create_host_records_payload\": {body} }}");
-
-        let client = client_maker();
-
-        let resp = client.post(url).body(body).send()?;
+        self.add_dns_record_with_ttl(client_maker, name, record_type, value, DEFAULT_TTL)
+    }
 
-        if !resp.status().is_success() {
-            return Err(Box::new(Error::Reqwest(
-                resp.error_for_status().unwrap_err(),
-            )));
-        }
+    fn add_dns_record_with_ttl(
+        &self,
+        client_maker: &dyn Fn() -> reqwest::blocking::Client,
+        name: &str,
+        record_type: DnsRecordType,
+        value: &str,
+        ttl: u32,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.create_dns_record(
+            client_maker,
+            CreateHostRecordsPayload {
+                host: name.to_string(),
+                record_type,
+                data: value.to_string(),
+                aux: DEFAULT_AUX,
+                ttl,
+            },
+        )
+    }
 
-        Ok(())
+    fn add_dns_record_with_priority(
+        &self,
+        client_maker: &dyn Fn() -> reqwest::blocking::Client,
+        name: &str,
+        record_type: DnsRecordType,
+        value: &str,
+        priority: u32,
+        ttl: Option<u32>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.create_dns_record(
+            client_maker,
+            CreateHostRecordsPayload {
+                host: name.to_string(),
+                record_type,
+                data: value.to_string(),
+                aux: priority,
+                ttl: ttl.unwrap_or(DEFAULT_TTL),
+            },
+        )
     }
 
     fn remove_dns_record(
@@ -269,6 +341,8 @@ mod tests {
         assert_eq!(dns_record.name, "www");
         assert_eq!(dns_record.record_type, DnsRecordType::A);
         assert_eq!(dns_record.value, "1.2.3.4");
+        assert_eq!(dns_record.ttl, Some(300));
+        assert_eq!(dns_record.priority, None);
     }
 
     #[test]
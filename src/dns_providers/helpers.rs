@@ -1,3 +1,7 @@
+use std::net::IpAddr;
+
+use crate::traits::domain_control::DnsRecordType;
+
 /// DNS TXT records may or may not have quotes. Quotes shouldn't matter.
 /// If provided is None, it means that the value won't be compared.
 pub fn compare_dns_txt_value(current: &str, provided: Option<&str>) -> bool {
@@ -7,13 +11,93 @@ pub fn compare_dns_txt_value(current: &str, provided: Option<&str>) -> bool {
     }
 }
 
+/// Values are compared the way providers may return them: addresses as addresses, TXT values with or
+/// without quotes, CAA values field by field, and names without the trailing dot and case-insensitively
+pub fn record_value_matches(record_type: DnsRecordType, record_value: &str, value: &str) -> bool {
+    match record_type {
+        DnsRecordType::A | DnsRecordType::AAAA => value
+            .parse::<IpAddr>()
+            .is_ok_and(|ip_address| ip_record_value_matches(record_value, &ip_address)),
+        DnsRecordType::TXT => compare_dns_txt_value(record_value, Some(value)),
+        // E.g., `0 issue "letsencrypt.org"`, which providers may return with or without the quotes
+        DnsRecordType::CAA => {
+            let fields = |v: &str| {
+                v.split_whitespace()
+                    .map(|field| field.trim_matches('"').to_lowercase())
+                    .collect::<Vec<_>>()
+            };
+            fields(record_value) == fields(value)
+        }
+        _ => record_value
+            .trim()
+            .trim_end_matches('.')
+            .eq_ignore_ascii_case(value.trim().trim_end_matches('.')),
+    }
+}
+
+/// IPv6 addresses have many textual representations (e.g., `2001:db8::1` and `2001:0db8:0:0:0:0:0:1`),
+/// so the values are compared as addresses, not as strings
+fn ip_record_value_matches(record_value: &str, ip_address: &IpAddr) -> bool {
+    record_value
+        .trim()
+        .parse::<IpAddr>()
+        .is_ok_and(|v| v == *ip_address)
+}
+
 pub fn build_client(proxy_address: Option<String>) -> reqwest::blocking::Client {
+    client_builder(proxy_address)
+        .build()
+        .expect("Client builder failed")
+}
+
+/// Checks that the proxy address is usable, so that options can reject it instead of the client builder panicking
+pub fn validate_proxy(proxy_address: &str) -> Result<(), String> {
+    reqwest::Proxy::all(proxy_address)
+        .map(|_| ())
+        .map_err(|e| format!("Invalid proxy URL {}: {}", proxy_address, e))
+}
+
+/// A client builder that connects through the proxy, if provided, so that callers can customize the client further
+pub fn client_builder(proxy_address: Option<String>) -> reqwest::blocking::ClientBuilder {
     let builder = reqwest::blocking::ClientBuilder::new();
     match proxy_address {
         Some(proxy) => builder
-            .proxy(reqwest::Proxy::all(proxy).unwrap_or_else(|e| panic!("Invalid proxy URL: {e}")))
-            .build()
-            .expect("Client builder with proxy failed"),
-        None => builder.build().expect("Client builder failed"),
+            .proxy(reqwest::Proxy::all(proxy).unwrap_or_else(|e| panic!("Invalid proxy URL: {e}"))),
+        None => builder,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_value_matches() {
+        assert!(record_value_matches(
+            DnsRecordType::CAA,
+            "0 issue letsencrypt.org",
+            "0 issue \"letsencrypt.org\""
+        ));
+        assert!(!record_value_matches(
+            DnsRecordType::CAA,
+            "0 issuewild \"letsencrypt.org\"",
+            "0 issue \"letsencrypt.org\""
+        ));
+        assert!(record_value_matches(
+            DnsRecordType::CNAME,
+            "Example.com.",
+            "example.com"
+        ));
+        assert!(record_value_matches(DnsRecordType::TXT, "\"abc\"", "abc"));
+    }
+
+    #[test]
+    fn test_ip_record_value_matches() {
+        let ipv6: IpAddr = "2001:db8::1".parse().unwrap();
+
+        assert!(ip_record_value_matches("2001:0db8:0:0:0:0:0:1", &ipv6));
+        assert!(ip_record_value_matches("2001:DB8::1", &ipv6));
+        assert!(!ip_record_value_matches("2001:db8::2", &ipv6));
+        assert!(!ip_record_value_matches("not an ip", &ipv6));
     }
 }
@@ -0,0 +1,149 @@
+use std::sync::{
+    Arc, Mutex,
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+use crate::traits::domain_control::{
+    DnsRecord, DnsRecordType, DomainController, ProviderCapabilities,
+};
+
+use super::helpers::compare_dns_txt_value;
+
+/// A domain controller that keeps its records in memory. Only used in tests.
+/// Clones share the same records, so a test can inspect the records after handing a clone over.
+#[derive(Debug, Clone, Default)]
+pub struct InMemory {
+    records: Arc<Mutex<Vec<DnsRecord>>>,
+    list_calls: Arc<AtomicUsize>,
+    failing_removals: Arc<AtomicBool>,
+}
+
+impl InMemory {
+    fn push(&self, record: DnsRecord) {
+        self.records.lock().expect("Poisoned mutex").push(record);
+    }
+
+    pub fn records(&self) -> Vec<DnsRecord> {
+        self.records.lock().expect("Poisoned mutex").clone()
+    }
+
+    /// How many times the records were listed through the DomainController trait, i.e., API calls to a real provider
+    pub fn list_calls(&self) -> usize {
+        self.list_calls.load(Ordering::SeqCst)
+    }
+
+    /// Makes every later removal fail, like a provider whose API errors out
+    pub fn fail_removals(&self) {
+        self.failing_removals.store(true, Ordering::SeqCst);
+    }
+}
+
+impl DomainController for InMemory {
+    fn provider_name(&self) -> &'static str {
+        "in-memory"
+    }
+
+    fn capabilities(&self) -> ProviderCapabilities {
+        ProviderCapabilities {
+            record_types: vec![
+                DnsRecordType::A,
+                DnsRecordType::AAAA,
+                DnsRecordType::CAA,
+                DnsRecordType::CNAME,
+                DnsRecordType::MX,
+                DnsRecordType::TXT,
+            ],
+            ttl: true,
+            priority: true,
+        }
+    }
+
+    fn add_dns_record(
+        &self,
+        _client_maker: &dyn Fn() -> reqwest::blocking::Client,
+        name: &str,
+        record_type: DnsRecordType,
+        value: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.push(DnsRecord {
+            name: name.to_string(),
+            record_type,
+            value: value.to_string(),
+            ttl: None,
+            priority: None,
+        });
+
+        Ok(())
+    }
+
+    fn add_dns_record_with_ttl(
+        &self,
+        _client_maker: &dyn Fn() -> reqwest::blocking::Client,
+        name: &str,
+        record_type: DnsRecordType,
+        value: &str,
+        ttl: u32,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.push(DnsRecord {
+            name: name.to_string(),
+            record_type,
+            value: value.to_string(),
+            ttl: Some(ttl),
+            priority: None,
+        });
+
+        Ok(())
+    }
+
+    fn add_dns_record_with_priority(
+        &self,
+        _client_maker: &dyn Fn() -> reqwest::blocking::Client,
+        name: &str,
+        record_type: DnsRecordType,
+        value: &str,
+        priority: u32,
+        ttl: Option<u32>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.push(DnsRecord {
+            name: name.to_string(),
+            record_type,
+            value: value.to_string(),
+            ttl,
+            priority: Some(priority),
+        });
+
+        Ok(())
+    }
+
+    fn remove_dns_record(
+        &self,
+        _client_maker: &dyn Fn() -> reqwest::blocking::Client,
+        name: &str,
+        record_type: DnsRecordType,
+        value: Option<&str>,
+    ) -> Result<usize, Box<dyn std::error::Error>> {
+        if self.failing_removals.load(Ordering::SeqCst) {
+            return Err(format!("Failed to remove the {} record `{}`", record_type, name).into());
+        }
+
+        let mut records = self.records.lock().expect("Poisoned mutex");
+
+        let size_before = records.len();
+        records.retain(|r| {
+            !(r.name == name
+                && r.record_type == record_type
+                && compare_dns_txt_value(&r.value, value))
+        });
+
+        Ok(size_before - records.len())
+    }
+
+    fn list_dns_records(
+        &self,
+        _client_maker: &dyn Fn() -> reqwest::blocking::Client,
+    ) -> Result<Vec<DnsRecord>, Box<dyn std::error::Error>> {
+        self.list_calls.fetch_add(1, Ordering::SeqCst);
+
+        Ok(self.records())
+    }
+}
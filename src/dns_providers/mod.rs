@@ -1,2 +1,5 @@
 pub mod epik;
 pub mod helpers;
+
+#[cfg(test)]
+pub mod in_memory;
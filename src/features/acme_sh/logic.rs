@@ -0,0 +1,74 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    features::certbot::logic::{cleanup, resolve_zone, set_record},
+    run_options::acme_sh_run_options::{AcmeShOperation, SimplifiedAcmeShRunOptions},
+    traits::domain_control::DomainController,
+};
+
+pub fn run_regular(
+    client_maker: &dyn Fn() -> reqwest::blocking::Client,
+    args: SimplifiedAcmeShRunOptions,
+    domain_controllers: BTreeMap<String, Box<dyn DomainController>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let resolved = resolve_zone(&args.fulldomain, &domain_controllers)?;
+
+    let change = match args.operation {
+        AcmeShOperation::Add => set_record(client_maker, &resolved, &args.txtvalue)?,
+        AcmeShOperation::Rm => cleanup(client_maker, &resolved, &args.txtvalue)?,
+    };
+
+    println!("{}", change);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        dns_providers::{helpers::build_client, in_memory::InMemory},
+        traits::domain_control::DnsRecordType,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_add_and_rm() {
+        let zone = InMemory::default();
+        let domain_controllers = || {
+            let mut domain_controllers: BTreeMap<String, Box<dyn DomainController>> =
+                BTreeMap::new();
+            domain_controllers.insert("example.com".to_string(), Box::new(zone.clone()));
+            domain_controllers
+        };
+
+        let client_maker = || build_client(None);
+        let options = |operation| SimplifiedAcmeShRunOptions {
+            operation,
+            fulldomain: "_acme-challenge.www.example.com".to_string(),
+            txtvalue: "-some-value".to_string(),
+        };
+
+        run_regular(
+            &client_maker,
+            options(AcmeShOperation::Add),
+            domain_controllers(),
+        )
+        .unwrap();
+
+        let records = zone.records();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].name, "_acme-challenge.www");
+        assert_eq!(records[0].record_type, DnsRecordType::TXT);
+        assert_eq!(records[0].value, "-some-value");
+
+        run_regular(
+            &client_maker,
+            options(AcmeShOperation::Rm),
+            domain_controllers(),
+        )
+        .unwrap();
+
+        assert!(zone.records().is_empty());
+    }
+}
@@ -1,75 +1,415 @@
-use certbot_run_options::SimplifiedCertbotRunOptions;
+use std::fmt::Display;
+
+use base64::Engine;
+use certbot_run_options::{CleanupMode, SimplifiedCertbotRunOptions};
+use reqwest::StatusCode;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
 pub use std::collections::BTreeMap;
-use traits::domain_control::DomainController;
+use traits::domain_control::{DnsRecordType, DomainController};
 
 use crate::{run_options::certbot_run_options, traits};
 
-const ACME_CHALLENGE_SUBDOMAIN: &str = "_acme-challenge";
+pub const ACME_CHALLENGE_SUBDOMAIN: &str = "_acme-challenge";
+
+#[derive(thiserror::Error, Debug)]
+pub enum ZoneResolutionError {
+    #[error("No domain controller in the config file manages a zone that contains `{0}`")]
+    NoMatchingZone(String),
+    #[error("The name `{0}` is the apex of its zone, but a record name under the zone is required")]
+    NameIsZoneApex(String),
+}
+
+/// The result of finding which configured zone (domain controller) is responsible for a fully qualified name
+pub struct ResolvedRecord<'a> {
+    /// The zone (as in the config file) that contains the fully qualified name
+    pub zone: &'a str,
+    /// The name of the record relative to the zone, e.g., `_acme-challenge.www` for `_acme-challenge.www.example.com`
+    pub record_name: String,
+    pub domain_controller: &'a dyn DomainController,
+}
+
+/// Finds the configured zone that contains the provided fully qualified domain name.
+/// The FQDN may have a trailing dot (as lego provides it) and a leading wildcard label (as in `*.example.com`).
+/// If more than one zone matches (e.g., `example.com` and `sub.example.com`), the longest (most specific) one is used.
+pub fn resolve_zone<'a>(
+    fqdn: &str,
+    domain_controllers: &'a BTreeMap<String, Box<dyn DomainController>>,
+) -> Result<ResolvedRecord<'a>, ZoneResolutionError> {
+    let fqdn = normalize_fqdn(fqdn);
+
+    let (zone, domain_controller) = domain_controllers
+        .iter()
+        .filter(|(zone, _)| {
+            let zone = normalize_fqdn(zone);
+            fqdn == zone || fqdn.ends_with(&format!(".{zone}"))
+        })
+        .max_by_key(|(zone, _)| zone.len())
+        .ok_or_else(|| ZoneResolutionError::NoMatchingZone(fqdn.clone()))?;
+
+    let record_name = fqdn
+        .strip_suffix(&normalize_fqdn(zone))
+        .and_then(|n| n.strip_suffix('.'))
+        .filter(|n| !n.is_empty())
+        .ok_or_else(|| ZoneResolutionError::NameIsZoneApex(fqdn.clone()))?
+        .to_string();
+
+    Ok(ResolvedRecord {
+        zone,
+        record_name,
+        domain_controller: domain_controller.as_ref(),
+    })
+}
+
+fn normalize_fqdn(name: &str) -> String {
+    let name = name.trim().trim_end_matches('.');
+    let name = name.strip_prefix("*.").unwrap_or(name);
+    name.to_lowercase()
+}
+
+/// The value of the DNS-01 TXT record for a key authorization, as per RFC 8555, section 8.4:
+/// the base64url encoding (without padding) of the SHA-256 digest of the key authorization
+pub fn dns01_txt_value(key_authorization: &str) -> String {
+    let digest = Sha256::digest(key_authorization.as_bytes());
+
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(digest)
+}
+
+/// What a set or cleanup operation changed in a zone
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RecordChange {
+    pub zone: String,
+    pub record_name: String,
+    pub record_type: DnsRecordType,
+    pub value: String,
+    pub provider: String,
+    pub records_added: usize,
+    pub records_removed: usize,
+}
+
+impl Display for RecordChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Zone {} ({}): {} {}={} - records added: {}, records removed: {}",
+            self.zone,
+            self.provider,
+            self.record_type,
+            self.record_name,
+            self.value,
+            self.records_added,
+            self.records_removed
+        )
+    }
+}
+
+/// The errors of the certbot mode, each with its own documented exit code, so that hooks can tell them apart
+#[derive(thiserror::Error, Debug)]
+pub enum CertbotError {
+    #[error("Invalid arguments: {0}")]
+    InvalidArguments(String),
+    #[error("Configuration error: {0}")]
+    Config(String),
+    #[error("Authentication with the DNS provider failed: {0}")]
+    Auth(String),
+    #[error("DNS provider failure: {0}")]
+    Provider(String),
+    #[error("Strict cleanup failed, no record was found to remove: {0}")]
+    RecordNotFound(String),
+}
+
+impl CertbotError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            CertbotError::InvalidArguments(_) => 2,
+            CertbotError::Config(_) => 10,
+            CertbotError::Auth(_) => 11,
+            CertbotError::Provider(_) => 12,
+            CertbotError::RecordNotFound(_) => 13,
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            CertbotError::InvalidArguments(_) => "invalid-arguments",
+            CertbotError::Config(_) => "config",
+            CertbotError::Auth(_) => "auth",
+            CertbotError::Provider(_) => "provider",
+            CertbotError::RecordNotFound(_) => "record-not-found",
+        }
+    }
+
+    /// Classifies an error returned by a domain controller. HTTP 401 and 403 responses are authentication errors.
+    pub fn from_provider_error(error: Box<dyn std::error::Error>) -> Self {
+        let mut source: Option<&(dyn std::error::Error + 'static)> = Some(error.as_ref());
+
+        while let Some(e) = source {
+            if let Some(status) = e.downcast_ref::<reqwest::Error>().and_then(|e| e.status())
+                && (status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN)
+            {
+                return CertbotError::Auth(error.to_string());
+            }
+
+            source = e.source();
+        }
+
+        CertbotError::Provider(error.to_string())
+    }
+}
+
+impl From<ZoneResolutionError> for CertbotError {
+    fn from(e: ZoneResolutionError) -> Self {
+        CertbotError::Config(e.to_string())
+    }
+}
 
 pub fn run_regular(
     client_maker: &dyn Fn() -> reqwest::blocking::Client,
     args: SimplifiedCertbotRunOptions,
     domain_controllers: BTreeMap<String, Box<dyn DomainController>>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let domain_controller = domain_controllers
-        .get(&args.domain_name)
-        .unwrap_or_else(|| {
-            panic!(
-                "Domain controller for domain {} not found in config",
-                &args.domain_name
-            )
-        });
+) -> Result<RecordChange, CertbotError> {
+    let challenge_fqdn = format!(
+        "{}.{}",
+        ACME_CHALLENGE_SUBDOMAIN,
+        normalize_fqdn(&args.domain_name)
+    );
+
+    let resolved = resolve_zone(&challenge_fqdn, &domain_controllers)?;
 
     let value = &args.validation_string;
 
-    match args.operation {
-        certbot_run_options::Operation::SetRecord => {
-            set_record(client_maker, domain_controller.as_ref(), value)?
-        }
-        certbot_run_options::Operation::Cleanup => {
-            cleanup(client_maker, domain_controller.as_ref(), value)?
-        }
+    let change =
+        match args.operation {
+            certbot_run_options::Operation::SetRecord => set_record(client_maker, &resolved, value)
+                .map_err(CertbotError::from_provider_error)?,
+            certbot_run_options::Operation::Cleanup => cleanup(client_maker, &resolved, value)
+                .map_err(CertbotError::from_provider_error)?,
+        };
+
+    if args.operation == certbot_run_options::Operation::Cleanup
+        && args.cleanup_mode == CleanupMode::Strict
+        && change.records_removed == 0
+    {
+        return Err(CertbotError::RecordNotFound(format!(
+            "{}={} in zone {}",
+            change.record_name, change.value, change.zone
+        )));
     }
 
-    Ok(())
+    Ok(change)
 }
 
-fn set_record(
+/// Adds the TXT record with the provided value to the resolved zone
+pub fn set_record(
     client_maker: &dyn Fn() -> reqwest::blocking::Client,
-    domain_controller: &dyn DomainController,
+    resolved: &ResolvedRecord,
     value: &str,
-) -> Result<(), Box<dyn std::error::Error>> {
-    domain_controller.add_dns_record(
+) -> Result<RecordChange, Box<dyn std::error::Error>> {
+    resolved.domain_controller.add_dns_record(
         client_maker,
-        ACME_CHALLENGE_SUBDOMAIN,
-        traits::domain_control::DnsRecordType::TXT,
+        &resolved.record_name,
+        DnsRecordType::TXT,
         value,
     )?;
 
-    println!(
-        "Record set end reached: {}={}",
-        ACME_CHALLENGE_SUBDOMAIN, value
-    );
-
-    Ok(())
+    Ok(record_change(resolved, value, 1, 0))
 }
 
-fn cleanup(
+/// Removes the TXT record with the provided value from the resolved zone.
+/// Finding no record to remove is not an error here; the report says how many were removed.
+pub fn cleanup(
     client_maker: &dyn Fn() -> reqwest::blocking::Client,
-    domain_controller: &dyn DomainController,
+    resolved: &ResolvedRecord,
     value: &str,
-) -> Result<(), Box<dyn std::error::Error>> {
-    domain_controller.remove_dns_record(
+) -> Result<RecordChange, Box<dyn std::error::Error>> {
+    let removed_count = resolved.domain_controller.remove_dns_record(
         client_maker,
-        ACME_CHALLENGE_SUBDOMAIN,
-        traits::domain_control::DnsRecordType::TXT,
+        &resolved.record_name,
+        DnsRecordType::TXT,
         Some(value),
     )?;
 
-    println!(
-        "Cleanup end reached for: {}={}",
-        ACME_CHALLENGE_SUBDOMAIN, value
-    );
+    Ok(record_change(resolved, value, 0, removed_count))
+}
+
+fn record_change(
+    resolved: &ResolvedRecord,
+    value: &str,
+    records_added: usize,
+    records_removed: usize,
+) -> RecordChange {
+    RecordChange {
+        zone: resolved.zone.to_string(),
+        record_name: resolved.record_name.clone(),
+        record_type: DnsRecordType::TXT,
+        value: value.to_string(),
+        provider: resolved.domain_controller.provider_name().to_string(),
+        records_added,
+        records_removed,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+    };
+
+    use crate::{
+        dns_providers::{helpers::build_client, in_memory::InMemory},
+        run_options::certbot_run_options::Operation,
+    };
+
+    use super::*;
+
+    fn controllers(zones: &[&str]) -> BTreeMap<String, Box<dyn DomainController>> {
+        zones
+            .iter()
+            .map(|z| {
+                (
+                    z.to_string(),
+                    Box::new(InMemory::default()) as Box<dyn DomainController>,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_resolve_zone() {
+        let domain_controllers = controllers(&["example.com", "sub.example.com", "example.net"]);
 
-    Ok(())
+        let resolved = resolve_zone("_acme-challenge.example.com.", &domain_controllers).unwrap();
+        assert_eq!(resolved.zone, "example.com");
+        assert_eq!(resolved.record_name, "_acme-challenge");
+
+        let resolved =
+            resolve_zone("_acme-challenge.www.Example.com", &domain_controllers).unwrap();
+        assert_eq!(resolved.zone, "example.com");
+        assert_eq!(resolved.record_name, "_acme-challenge.www");
+
+        let resolved =
+            resolve_zone("_acme-challenge.sub.example.com", &domain_controllers).unwrap();
+        assert_eq!(resolved.zone, "sub.example.com");
+        assert_eq!(resolved.record_name, "_acme-challenge");
+
+        assert!(matches!(
+            resolve_zone("_acme-challenge.notexample.com", &domain_controllers),
+            Err(ZoneResolutionError::NoMatchingZone(_))
+        ));
+        assert!(matches!(
+            resolve_zone("example.net.", &domain_controllers),
+            Err(ZoneResolutionError::NameIsZoneApex(_))
+        ));
+    }
+
+    #[test]
+    fn test_set_and_cleanup() {
+        let zone = InMemory::default();
+        let domain_controllers = || {
+            let mut domain_controllers: BTreeMap<String, Box<dyn DomainController>> =
+                BTreeMap::new();
+            domain_controllers.insert("example.com".to_string(), Box::new(zone.clone()));
+            domain_controllers
+        };
+        let options = |operation, cleanup_mode| SimplifiedCertbotRunOptions {
+            domain_name: "*.example.com".to_string(),
+            operation,
+            validation_string: "abc".to_string(),
+            cleanup_mode,
+        };
+
+        let client_maker = || build_client(None);
+
+        let change = run_regular(
+            &client_maker,
+            options(Operation::SetRecord, CleanupMode::Strict),
+            domain_controllers(),
+        )
+        .unwrap();
+        assert_eq!(
+            change,
+            RecordChange {
+                zone: "example.com".to_string(),
+                record_name: "_acme-challenge".to_string(),
+                record_type: DnsRecordType::TXT,
+                value: "abc".to_string(),
+                provider: "in-memory".to_string(),
+                records_added: 1,
+                records_removed: 0,
+            }
+        );
+
+        let change = run_regular(
+            &client_maker,
+            options(Operation::Cleanup, CleanupMode::Strict),
+            domain_controllers(),
+        )
+        .unwrap();
+        assert_eq!(change.records_removed, 1);
+
+        // The record is gone now
+        let change = run_regular(
+            &client_maker,
+            options(Operation::Cleanup, CleanupMode::Idempotent),
+            domain_controllers(),
+        )
+        .unwrap();
+        assert_eq!(change.records_removed, 0);
+
+        let error = run_regular(
+            &client_maker,
+            options(Operation::Cleanup, CleanupMode::Strict),
+            domain_controllers(),
+        )
+        .unwrap_err();
+        assert!(matches!(error, CertbotError::RecordNotFound(_)));
+        assert_eq!(error.exit_code(), 13);
+    }
+
+    #[test]
+    fn test_provider_error_classification() {
+        let error_with_status = |status_line: &'static str| {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let url = format!("http://{}", listener.local_addr().unwrap());
+
+            let server = std::thread::spawn(move || {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request_line = String::new();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                while reader.read_line(&mut request_line).unwrap() > 2 {
+                    request_line.clear();
+                }
+                write!(
+                    stream,
+                    "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    status_line
+                )
+                .unwrap();
+            });
+
+            let error = build_client(None)
+                .get(url)
+                .send()
+                .unwrap()
+                .error_for_status()
+                .unwrap_err();
+            server.join().unwrap();
+
+            Box::new(crate::dns_providers::epik::Error::Reqwest(error))
+                as Box<dyn std::error::Error>
+        };
+
+        let error = CertbotError::from_provider_error(error_with_status("401 Unauthorized"));
+        assert!(matches!(error, CertbotError::Auth(_)));
+        assert_eq!(error.exit_code(), 11);
+
+        let error =
+            CertbotError::from_provider_error(error_with_status("500 Internal Server Error"));
+        assert!(matches!(error, CertbotError::Provider(_)));
+        assert_eq!(error.exit_code(), 12);
+
+        let error = CertbotError::from_provider_error(Box::new(std::io::Error::other("down")));
+        assert!(matches!(error, CertbotError::Provider(_)));
+    }
 }
@@ -1,31 +1,113 @@
-mod logic;
+pub mod logic;
 
 use crate::{
-    certbot::logic::run_regular, config::Config, dns_providers::helpers::build_client,
-    run_options::certbot_run_options::CertbotRunOptions,
+    certbot::logic::{CertbotError, RecordChange, run_regular},
+    config::Config,
+    dns_providers::helpers::build_client,
+    run_options::certbot_run_options::{CertbotRunOptions, OutputFormat},
 };
 
-pub fn run(options: CertbotRunOptions) -> Result<(), Box<dyn std::error::Error>> {
-    options
-        .check()
-        .unwrap_or_else(|e| panic!("Arguments provided are not correct: {}", e));
+pub fn run(options: CertbotRunOptions) -> Result<(), CertbotError> {
+    let output = options.output;
 
-    println!("Starting in certbot mode with args: {:?}", &options);
+    let result = run_inner(options);
 
-    let config = Config::from_file_or_default(&options.config_file_path)?;
+    match output {
+        OutputFormat::Text => {
+            if let Ok(change) = &result {
+                println!("{}", change);
+            }
+        }
+        OutputFormat::Json => println!("{}", json_report(&result)),
+    }
 
-    println!("Starting with config: {:?}", config);
+    result.map(|_| ())
+}
+
+fn run_inner(options: CertbotRunOptions) -> Result<RecordChange, CertbotError> {
+    options.check().map_err(CertbotError::InvalidArguments)?;
+
+    // In JSON mode, stdout is reserved for the report
+    let output = options.output;
+    let log = |message: String| match output {
+        OutputFormat::Text => println!("{}", message),
+        OutputFormat::Json => eprintln!("{}", message),
+    };
+
+    log(format!(
+        "Starting in certbot mode with args: {:?}",
+        &options
+    ));
+
+    let config = Config::from_file_or_default(&options.config_file_path)
+        .map_err(|e| CertbotError::Config(e.to_string()))?;
+
+    log(format!("Starting with config: {:?}", config));
 
     let domain_controllers = config.into_domain_controllers();
 
     let proxy = options.proxy.clone();
     let client_maker = Box::new(|| build_client(proxy.clone()));
 
-    run_regular(
+    let operation = options.operation.clone();
+
+    let change = run_regular(
         client_maker.as_ref(),
         options.into_simplified(),
         domain_controllers,
     )?;
 
-    Ok(())
+    log(format!("Certbot operation {:?} done", operation));
+
+    Ok(change)
+}
+
+fn json_report(result: &Result<RecordChange, CertbotError>) -> serde_json::Value {
+    match result {
+        Ok(change) => {
+            let mut report = serde_json::to_value(change)
+                .expect("Serializing RecordChange to JSON should never fail");
+            report["status"] = "success".into();
+            report["exit_code"] = 0.into();
+            report
+        }
+        Err(e) => serde_json::json!({
+            "status": "error",
+            "error_kind": e.kind(),
+            "message": e.to_string(),
+            "exit_code": e.exit_code(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::run_options::certbot_run_options::Operation;
+
+    use super::*;
+
+    #[test]
+    fn test_invalid_proxy_is_an_invalid_argument() {
+        let options = CertbotRunOptions {
+            domain_name: Some("example.com".to_string()),
+            operation: Some(Operation::SetRecord),
+            validation_string: Some("validation".to_string()),
+            proxy: Some("http://[::1".to_string()),
+            output: OutputFormat::Json,
+            ..Default::default()
+        };
+
+        let result = run_inner(options);
+        assert!(matches!(result, Err(CertbotError::InvalidArguments(_))));
+
+        let report = json_report(&result);
+        assert_eq!(report["error_kind"], "invalid-arguments");
+        assert_eq!(report["exit_code"], 2);
+        assert!(
+            report["message"]
+                .as_str()
+                .unwrap()
+                .contains("Invalid proxy URL")
+        );
+    }
 }
@@ -0,0 +1,293 @@
+//! Parsers of the lease files of dnsmasq, ISC dhcpd and Kea (memfile CSV)
+
+use std::{collections::BTreeMap, net::IpAddr, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::utils::calendar::days_from_civil;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LeaseFileFormat {
+    /// dnsmasq's `dnsmasq.leases`, IPv4 and IPv6
+    Dnsmasq,
+    /// ISC dhcpd's `dhcpd.leases`, IPv4
+    Isc,
+    /// Kea's memfile lease CSV, e.g., `kea-leases4.csv` or `kea-leases6.csv`
+    Kea,
+}
+
+/// A lease file in the `dhcp_leases` section of the config file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LeaseFile {
+    pub format: LeaseFileFormat,
+    pub path: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lease {
+    /// The hostname the client sent, as in the file (not validated)
+    pub hostname: String,
+    pub address: IpAddr,
+    /// When the lease expires, in seconds since the Unix epoch. None if it never expires.
+    pub expires: Option<u64>,
+}
+
+impl Lease {
+    pub fn is_active(&self, now: u64) -> bool {
+        self.expires.is_none_or(|expires| expires > now)
+    }
+}
+
+pub fn read_lease_file(file: &LeaseFile) -> Result<Vec<Lease>, Box<dyn std::error::Error>> {
+    let data = std::fs::read_to_string(Path::new(&file.path))
+        .map_err(|e| format!("Could not read the lease file {}: {}", file.path, e))?;
+
+    let leases = match file.format {
+        LeaseFileFormat::Dnsmasq => parse_dnsmasq_leases(&data),
+        LeaseFileFormat::Isc => parse_isc_leases(&data),
+        LeaseFileFormat::Kea => parse_kea_leases(&data)?,
+    };
+
+    Ok(leases)
+}
+
+/// Every line is `<expiry> <mac or iaid> <address> <hostname> <client id>`, with `*` for an unknown hostname
+/// and an expiry of 0 for infinite leases. The `duid` line of DHCPv6 is skipped.
+fn parse_dnsmasq_leases(data: &str) -> Vec<Lease> {
+    data.lines()
+        .filter_map(|line| {
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            if fields.len() < 4 || fields[0] == "duid" || fields[3] == "*" {
+                return None;
+            }
+
+            let expires = fields[0].parse::<u64>().ok()?;
+
+            Some(Lease {
+                hostname: fields[3].to_string(),
+                address: fields[2].parse().ok()?,
+                expires: (expires != 0).then_some(expires),
+            })
+        })
+        .collect()
+}
+
+/// The file is a log of `lease <address> { ... }` blocks, in which later blocks of an address replace the earlier ones.
+/// Only leases in the `active` binding state are kept.
+fn parse_isc_leases(data: &str) -> Vec<Lease> {
+    let mut leases: BTreeMap<IpAddr, Option<Lease>> = BTreeMap::new();
+
+    let mut current: Option<(IpAddr, Option<String>, Option<u64>, bool)> = None;
+
+    for line in data.lines() {
+        let line = line.trim();
+
+        if let Some(rest) = line.strip_prefix("lease ") {
+            current = rest
+                .trim_end_matches('{')
+                .trim()
+                .parse::<IpAddr>()
+                .ok()
+                .map(|address| (address, None, None, true));
+            continue;
+        }
+
+        let Some((address, hostname, expires, active)) = current.as_mut() else {
+            continue;
+        };
+
+        if line == "}" {
+            let lease = match (hostname.take(), *active) {
+                (Some(hostname), true) => Some(Lease {
+                    hostname,
+                    address: *address,
+                    expires: *expires,
+                }),
+                _ => None,
+            };
+            leases.insert(*address, lease);
+            current = None;
+        } else if let Some(value) = line.strip_prefix("client-hostname ") {
+            *hostname = Some(value.trim_end_matches(';').trim_matches('"').to_string());
+        } else if let Some(value) = line.strip_prefix("ends ") {
+            *expires = parse_isc_time(value.trim_end_matches(';'));
+        } else if let Some(value) = line.strip_prefix("binding state ") {
+            *active = value.trim_end_matches(';') == "active";
+        }
+    }
+
+    leases.into_values().flatten().collect()
+}
+
+/// `ends` is either `never`, `epoch <seconds>` or `<weekday> <yyyy/mm/dd> <hh:mm:ss>` in UTC
+fn parse_isc_time(value: &str) -> Option<u64> {
+    let fields = value.split_whitespace().collect::<Vec<_>>();
+
+    match fields.as_slice() {
+        ["never"] => None,
+        ["epoch", seconds] => seconds.parse().ok(),
+        [_weekday, date, time] => {
+            let date = date
+                .split('/')
+                .map(|v| v.parse::<i64>().ok())
+                .collect::<Option<Vec<_>>>()?;
+            let time = time
+                .split(':')
+                .map(|v| v.parse::<i64>().ok())
+                .collect::<Option<Vec<_>>>()?;
+            let ([year, month, day], [hour, minute, second]) = (date.as_slice(), time.as_slice())
+            else {
+                return None;
+            };
+
+            let days = days_from_civil(*year, *month, *day);
+            u64::try_from(days * 86400 + hour * 3600 + minute * 60 + second).ok()
+        }
+        // Unparseable: treated as never expiring, so that a lease isn't removed by mistake
+        _ => None,
+    }
+}
+
+/// The columns are named in the header. Like ISC's, the file is a log, in which later lines of an address
+/// replace the earlier ones. Only leases in the default state (0) are kept, not declined or reclaimed ones.
+fn parse_kea_leases(data: &str) -> Result<Vec<Lease>, Box<dyn std::error::Error>> {
+    let mut lines = data.lines();
+
+    let header = lines
+        .next()
+        .ok_or("The Kea lease file is empty, without even a header")?
+        .split(',')
+        .collect::<Vec<_>>();
+    let column = |name: &str| {
+        header
+            .iter()
+            .position(|c| *c == name)
+            .ok_or_else(|| format!("No `{}` column in the Kea lease file", name))
+    };
+    let (address_column, expire_column, hostname_column, state_column) = (
+        column("address")?,
+        column("expire")?,
+        column("hostname")?,
+        column("state")?,
+    );
+
+    let mut leases: BTreeMap<IpAddr, Option<Lease>> = BTreeMap::new();
+
+    for line in lines {
+        let fields = line.split(',').collect::<Vec<_>>();
+        let field = |index: usize| fields.get(index).copied().unwrap_or_default();
+
+        let Ok(address) = field(address_column).parse::<IpAddr>() else {
+            continue;
+        };
+
+        let hostname = field(hostname_column);
+        let lease = (field(state_column) == "0" && !hostname.is_empty()).then(|| Lease {
+            hostname: hostname.to_string(),
+            address,
+            expires: field(expire_column).parse().ok(),
+        });
+
+        leases.insert(address, lease);
+    }
+
+    Ok(leases.into_values().flatten().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lease(hostname: &str, address: &str, expires: Option<u64>) -> Lease {
+        Lease {
+            hostname: hostname.to_string(),
+            address: address.parse().unwrap(),
+            expires,
+        }
+    }
+
+    #[test]
+    fn test_parse_dnsmasq_leases() {
+        let data = "\
+1760900000 aa:bb:cc:dd:ee:01 192.168.1.10 nas 01:aa:bb:cc:dd:ee:01
+0 aa:bb:cc:dd:ee:02 192.168.1.11 printer *
+1760900000 aa:bb:cc:dd:ee:03 192.168.1.12 * *
+duid 00:01:00:01:2c:00:00:00:aa:bb:cc:dd:ee:ff
+1760900100 12345678 2001:db8::10 nas 00:01:00:01:2c:00:00:00:aa:bb:cc:dd:ee:01
+";
+
+        assert_eq!(
+            parse_dnsmasq_leases(data),
+            vec![
+                lease("nas", "192.168.1.10", Some(1760900000)),
+                lease("printer", "192.168.1.11", None),
+                lease("nas", "2001:db8::10", Some(1760900100)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_isc_leases() {
+        let data = r#"
+# The format of this file is documented in the dhcpd.leases(5) manual page.
+lease 192.168.1.20 {
+  starts 3 2026/10/14 10:00:00;
+  ends 3 2026/10/14 22:00:00;
+  binding state active;
+  hardware ethernet aa:bb:cc:dd:ee:20;
+  client-hostname "laptop";
+}
+lease 192.168.1.21 {
+  ends never;
+  binding state active;
+  client-hostname "tv";
+}
+lease 192.168.1.20 {
+  starts 4 2026/10/15 10:00:00;
+  ends epoch 1760572800;
+  binding state active;
+  client-hostname "laptop";
+}
+lease 192.168.1.22 {
+  ends 4 2026/10/15 10:00:00;
+  binding state free;
+  client-hostname "phone";
+}
+"#;
+
+        assert_eq!(
+            parse_isc_leases(data),
+            vec![
+                lease("laptop", "192.168.1.20", Some(1760572800)),
+                lease("tv", "192.168.1.21", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_isc_time() {
+        assert_eq!(parse_isc_time("4 2026/10/15 10:00:00"), Some(1792058400));
+        assert_eq!(parse_isc_time("4 1970/01/01 00:00:01"), Some(1));
+        assert_eq!(parse_isc_time("never"), None);
+        assert_eq!(parse_isc_time("epoch 1760572800"), Some(1760572800));
+    }
+
+    #[test]
+    fn test_parse_kea_leases() {
+        let data = "\
+address,hwaddr,client_id,valid_lifetime,expire,subnet_id,fqdn_fwd,fqdn_rev,hostname,state,user_context,pool_id
+192.168.1.30,aa:bb:cc:dd:ee:30,,3600,1760900000,1,0,0,camera,0,,0
+192.168.1.31,aa:bb:cc:dd:ee:31,,3600,1760900000,1,0,0,,0,,0
+192.168.1.32,aa:bb:cc:dd:ee:32,,3600,1760900000,1,0,0,doorbell,1,,0
+192.168.1.30,aa:bb:cc:dd:ee:30,,3600,1760903600,1,0,0,camera,0,,0
+";
+
+        assert_eq!(
+            parse_kea_leases(data).unwrap(),
+            vec![lease("camera", "192.168.1.30", Some(1760903600))]
+        );
+
+        assert!(parse_kea_leases("address,hwaddr\n").is_err());
+    }
+}
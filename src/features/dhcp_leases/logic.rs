@@ -0,0 +1,399 @@
+use std::{collections::BTreeMap, fmt::Display, net::IpAddr};
+
+use crate::{
+    features::dyndns::{
+        daemon::DynDnsState,
+        ip_detection::IpFamily,
+        logic::{RecordChange, sync_ip_record},
+    },
+    traits::domain_control::DomainController,
+};
+
+use super::leases::Lease;
+
+/// The records to publish, by record name and family
+pub type DesiredRecords = BTreeMap<(String, IpFamily), IpAddr>;
+
+/// What a reconcile did, for the summary
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct LeasesReport {
+    pub added: usize,
+    pub updated: usize,
+    pub unchanged: usize,
+    pub removed: usize,
+    /// Records of expired leases that were kept, because removal was not requested
+    pub expired: usize,
+    /// Records that exist but are not managed by this tool, so they are left as they are
+    pub skipped: Vec<String>,
+    pub failures: Vec<String>,
+}
+
+impl Display for LeasesReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} added, {} updated, {} unchanged, {} removed, {} expired but kept",
+            self.added, self.updated, self.unchanged, self.removed, self.expired
+        )?;
+
+        if !self.skipped.is_empty() {
+            write!(f, ", not managed: {}", self.skipped.join("; "))?;
+        }
+        if !self.failures.is_empty() {
+            write!(f, ", failures: {}", self.failures.join("; "))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The record name of a lease's hostname, i.e., its first label under the subdomain.
+/// None if the hostname can't be a DNS label.
+fn record_name(hostname: &str, subdomain: &str) -> Option<String> {
+    let label = hostname.split('.').next()?.to_lowercase();
+
+    let valid = !label.is_empty()
+        && label.len() <= 63
+        && !label.starts_with('-')
+        && !label.ends_with('-')
+        && label
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+    if !valid {
+        return None;
+    }
+
+    match subdomain {
+        "" => Some(label),
+        subdomain => Some(format!("{}.{}", label, subdomain)),
+    }
+}
+
+/// The records for the active leases. When a host has more than one lease of a family,
+/// e.g., in two lease files, the one that expires last is used.
+pub fn desired_records(leases: &[Lease], subdomain: &str, now: u64) -> DesiredRecords {
+    let mut chosen: BTreeMap<(String, IpFamily), &Lease> = BTreeMap::new();
+
+    for lease in leases.iter().filter(|l| l.is_active(now)) {
+        let Some(name) = record_name(&lease.hostname, subdomain) else {
+            eprintln!(
+                "Skipping the lease of {}: `{}` is not a valid hostname",
+                lease.address, lease.hostname
+            );
+            continue;
+        };
+
+        let key = (name, IpFamily::of(&lease.address));
+        let expires_later = |current: &Lease| {
+            lease.expires.unwrap_or(u64::MAX) > current.expires.unwrap_or(u64::MAX)
+        };
+
+        if chosen
+            .get(&key)
+            .is_none_or(|current| expires_later(current))
+        {
+            chosen.insert(key, lease);
+        }
+    }
+
+    chosen
+        .into_iter()
+        .map(|(key, lease)| (key, lease.address))
+        .collect()
+}
+
+/// Publishes the desired records, and removes (if requested) the records of leases that are gone.
+/// Only the records in the state are managed: an existing record with another value is never overwritten,
+/// and a record is only removed if this tool created it.
+pub fn reconcile(
+    client_maker: &dyn Fn() -> reqwest::blocking::Client,
+    domain_controller: &dyn DomainController,
+    zone: &str,
+    desired: &DesiredRecords,
+    state: &mut DynDnsState,
+    remove_expired: bool,
+    ttl: Option<u32>,
+) -> Result<LeasesReport, Box<dyn std::error::Error>> {
+    let records = domain_controller.list_dns_records(client_maker)?;
+    let mut report = LeasesReport::default();
+
+    for ((name, family), address) in desired {
+        let record_type = family.record_type();
+        let key = DynDnsState::key(zone, name, record_type);
+
+        let foreign_value = records
+            .iter()
+            .filter(|r| r.name == *name && r.record_type == record_type)
+            .any(|r| r.value.parse::<IpAddr>().ok() != Some(*address));
+        if foreign_value && !state.published.contains_key(&key) {
+            report.skipped.push(format!("{} ({})", name, record_type));
+            continue;
+        }
+
+        match sync_ip_record(
+            client_maker,
+            domain_controller,
+            &records,
+            name,
+            *address,
+            ttl,
+        ) {
+            Ok(change) => {
+                let published = match change {
+                    // A record set by hand to the same address stays unmanaged, so it's never removed
+                    RecordChange::Unchanged => {
+                        report.unchanged += 1;
+                        state.published.contains_key(&key)
+                    }
+                    RecordChange::Created => {
+                        report.added += 1;
+                        true
+                    }
+                    RecordChange::Updated { .. } => {
+                        report.updated += 1;
+                        true
+                    }
+                };
+                if published {
+                    state.published.insert(key, *address);
+                }
+            }
+            Err(e) => report
+                .failures
+                .push(format!("{} ({}): {}", name, record_type, e)),
+        }
+    }
+
+    let zone_prefix = format!("{}/", zone);
+    let gone = state
+        .published
+        .iter()
+        .filter_map(|(key, address)| {
+            let (name, _) = key.strip_prefix(&zone_prefix)?.rsplit_once('/')?;
+            let desired_key = (name.to_string(), IpFamily::of(address));
+
+            (!desired.contains_key(&desired_key)).then(|| (key.clone(), name.to_string(), *address))
+        })
+        .collect::<Vec<_>>();
+
+    for (key, name, address) in gone {
+        if !remove_expired {
+            report.expired += 1;
+            continue;
+        }
+
+        let record_type = IpFamily::of(&address).record_type();
+        match domain_controller.remove_dns_record(
+            client_maker,
+            &name,
+            record_type,
+            Some(&address.to_string()),
+        ) {
+            Ok(_) => {
+                report.removed += 1;
+                state.published.remove(&key);
+            }
+            Err(e) => report
+                .failures
+                .push(format!("Removing {} ({}): {}", name, record_type, e)),
+        }
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        dns_providers::{helpers::build_client, in_memory::InMemory},
+        traits::domain_control::{DnsRecord, DnsRecordType},
+    };
+
+    use super::*;
+
+    fn lease(hostname: &str, address: &str, expires: Option<u64>) -> Lease {
+        Lease {
+            hostname: hostname.to_string(),
+            address: address.parse().unwrap(),
+            expires,
+        }
+    }
+
+    fn record(name: &str, record_type: DnsRecordType, value: &str) -> DnsRecord {
+        DnsRecord {
+            name: name.to_string(),
+            record_type,
+            value: value.to_string(),
+            ttl: None,
+            priority: None,
+        }
+    }
+
+    #[test]
+    fn test_record_name() {
+        assert_eq!(record_name("NAS", "lan"), Some("nas.lan".to_string()));
+        assert_eq!(
+            record_name("nas.home.arpa", "lan"),
+            Some("nas.lan".to_string())
+        );
+        assert_eq!(record_name("nas", ""), Some("nas".to_string()));
+        assert_eq!(record_name("John's iPhone", "lan"), None);
+        assert_eq!(record_name("-bad", "lan"), None);
+    }
+
+    #[test]
+    fn test_desired_records() {
+        let leases = vec![
+            lease("nas", "192.168.1.10", Some(2000)),
+            lease("nas", "192.168.1.11", Some(3000)),
+            lease("nas", "2001:db8::10", None),
+            lease("old", "192.168.1.12", Some(500)),
+            lease("bad name", "192.168.1.13", None),
+        ];
+
+        assert_eq!(
+            desired_records(&leases, "lan", 1000),
+            BTreeMap::from([
+                (
+                    ("nas.lan".to_string(), IpFamily::V4),
+                    "192.168.1.11".parse().unwrap()
+                ),
+                (
+                    ("nas.lan".to_string(), IpFamily::V6),
+                    "2001:db8::10".parse().unwrap()
+                ),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_reconcile_only_touches_managed_records() {
+        let zone = InMemory::default();
+        let client_maker = || build_client(None);
+        let mut state = DynDnsState::default();
+
+        // Set by hand, so not managed
+        zone.add_dns_record(&client_maker, "router.lan", DnsRecordType::A, "192.168.1.1")
+            .unwrap();
+
+        let desired = desired_records(
+            &[
+                lease("nas", "192.168.1.10", None),
+                lease("router", "192.168.1.99", None),
+            ],
+            "lan",
+            0,
+        );
+        let report = reconcile(
+            &client_maker,
+            &zone,
+            "example.com",
+            &desired,
+            &mut state,
+            true,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(report.added, 1);
+        assert_eq!(report.skipped, vec!["router.lan (A)".to_string()]);
+        assert_eq!(
+            zone.records(),
+            vec![
+                record("router.lan", DnsRecordType::A, "192.168.1.1"),
+                record("nas.lan", DnsRecordType::A, "192.168.1.10"),
+            ]
+        );
+
+        // The address of the managed record changed
+        let desired = desired_records(&[lease("nas", "192.168.1.20", None)], "lan", 0);
+        let report = reconcile(
+            &client_maker,
+            &zone,
+            "example.com",
+            &desired,
+            &mut state,
+            true,
+            None,
+        )
+        .unwrap();
+        assert_eq!(report.updated, 1);
+        assert_eq!(
+            state.published.get("example.com/nas.lan/A"),
+            Some(&"192.168.1.20".parse().unwrap())
+        );
+
+        // The lease expired: kept without removal, removed with it. The unmanaged record stays.
+        let report = reconcile(
+            &client_maker,
+            &zone,
+            "example.com",
+            &BTreeMap::new(),
+            &mut state,
+            false,
+            None,
+        )
+        .unwrap();
+        assert_eq!(report.expired, 1);
+        assert_eq!(zone.records().len(), 2);
+
+        let report = reconcile(
+            &client_maker,
+            &zone,
+            "example.com",
+            &BTreeMap::new(),
+            &mut state,
+            true,
+            None,
+        )
+        .unwrap();
+        assert_eq!(report.removed, 1);
+        assert!(state.published.is_empty());
+        assert_eq!(
+            zone.records(),
+            vec![record("router.lan", DnsRecordType::A, "192.168.1.1")]
+        );
+    }
+
+    #[test]
+    fn test_reconcile_keeps_hand_set_record_with_the_same_address() {
+        let zone = InMemory::default();
+        let client_maker = || build_client(None);
+        let mut state = DynDnsState::default();
+
+        // Set by hand, to the address the lease has
+        zone.add_dns_record(&client_maker, "nas.lan", DnsRecordType::A, "192.168.1.10")
+            .unwrap();
+
+        let desired = desired_records(&[lease("nas", "192.168.1.10", None)], "lan", 0);
+        let report = reconcile(
+            &client_maker,
+            &zone,
+            "example.com",
+            &desired,
+            &mut state,
+            true,
+            None,
+        )
+        .unwrap();
+        assert_eq!(report.unchanged, 1);
+        assert!(state.published.is_empty());
+
+        // The lease expired, but the record wasn't published by us, so it stays
+        let report = reconcile(
+            &client_maker,
+            &zone,
+            "example.com",
+            &BTreeMap::new(),
+            &mut state,
+            true,
+            None,
+        )
+        .unwrap();
+        assert_eq!(report.removed, 0);
+        assert_eq!(
+            zone.records(),
+            vec![record("nas.lan", DnsRecordType::A, "192.168.1.10")]
+        );
+    }
+}
@@ -0,0 +1,102 @@
+pub mod leases;
+mod logic;
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::Config,
+    dhcp_leases::{
+        leases::{LeaseFile, read_lease_file},
+        logic::{desired_records, reconcile},
+    },
+    dns_providers::helpers::build_client,
+    features::dyndns::daemon::DynDnsState,
+    run_options::dhcp_leases_run_options::DhcpLeasesRunOptions,
+};
+
+/// The `dhcp_leases` section of the config file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DhcpLeasesConfig {
+    /// The domain name (as in account) of the config file where the records are published
+    pub zone: String,
+    /// The subdomain under which the hosts are published, e.g., `lan` for `nas.lan.example.com`.
+    /// If empty, the hosts are published directly under the zone.
+    #[serde(default)]
+    pub subdomain: String,
+    /// The TTL of the records that are created or updated. If not provided, the provider's default is used.
+    #[serde(default)]
+    pub ttl: Option<u32>,
+    pub lease_files: Vec<LeaseFile>,
+}
+
+pub fn run(options: DhcpLeasesRunOptions) -> Result<(), Box<dyn std::error::Error>> {
+    options
+        .check()
+        .unwrap_or_else(|e| panic!("Arguments provided are not correct: {}", e));
+
+    println!("Starting in dhcp-leases mode with args: {:?}", &options);
+
+    let config = Config::from_file_or_default(&options.config_file_path)?;
+
+    let leases_config = config
+        .dhcp_leases
+        .clone()
+        .ok_or("No `dhcp_leases` section in the config file")?;
+
+    let domain_controllers = config.into_domain_controllers();
+    let domain_controller = domain_controllers
+        .get(&leases_config.zone)
+        .ok_or_else(|| format!("Zone {} not found in config", leases_config.zone))?;
+
+    let proxy = options.proxy.clone();
+    let client_maker = Box::new(|| build_client(proxy.clone()));
+
+    let args = options.into_simplified();
+
+    // A lease file that can't be read fails the run, instead of making its hosts look expired
+    let mut leases = Vec::new();
+    for lease_file in &leases_config.lease_files {
+        leases.extend(read_lease_file(lease_file)?);
+    }
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let desired = desired_records(&leases, &leases_config.subdomain, now);
+
+    let mut state = DynDnsState::load(&args.state_file_path)?;
+    let previous_state = state.clone();
+
+    let report = reconcile(
+        client_maker.as_ref(),
+        domain_controller.as_ref(),
+        &leases_config.zone,
+        &desired,
+        &mut state,
+        args.remove_expired,
+        leases_config.ttl,
+    )?;
+
+    if state != previous_state {
+        state.save(&args.state_file_path)?;
+    }
+
+    println!(
+        "DHCP leases of {} hosts published in {}: {}",
+        desired.len(),
+        leases_config.zone,
+        report
+    );
+
+    if !report.failures.is_empty() {
+        return Err(Box::new(std::io::Error::other(format!(
+            "{} records could not be updated",
+            report.failures.len()
+        ))));
+    }
+
+    Ok(())
+}
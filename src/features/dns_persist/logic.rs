@@ -0,0 +1,478 @@
+use std::{collections::BTreeMap, fmt::Display, str::FromStr};
+
+use crate::{
+    features::certbot::logic::{ResolvedRecord, resolve_zone},
+    run_options::dns_persist_run_options::{PersistOperation, SimplifiedDnsPersistRunOptions},
+    traits::domain_control::{DnsRecordType, DomainController},
+};
+
+const VALIDATION_PERSIST_SUBDOMAIN: &str = "_validation-persist";
+
+const ACCOUNT_URI_PARAMETER: &str = "accounturi";
+const POLICY_PARAMETER: &str = "policy";
+const PERSIST_UNTIL_PARAMETER: &str = "persistUntil";
+const WILDCARD_POLICY: &str = "wildcard";
+
+/// The value of a dns-persist-01 TXT record, e.g.,
+/// `letsencrypt.org; accounturi=https://acme-v02.api.letsencrypt.org/acme/acct/1234; policy=wildcard; persistUntil=1767225600`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PersistRecordValue {
+    pub issuer_domain_name: String,
+    pub account_uri: String,
+    pub wildcard: bool,
+    /// UNIX timestamp in seconds
+    pub persist_until: Option<u64>,
+}
+
+impl PersistRecordValue {
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.persist_until.is_some_and(|t| t <= now)
+    }
+
+    fn belongs_to(&self, issuer_domain_name: &str, account_uri: Option<&str>) -> bool {
+        self.issuer_domain_name
+            .eq_ignore_ascii_case(issuer_domain_name)
+            && account_uri.is_none_or(|a| a == self.account_uri)
+    }
+}
+
+impl Display for PersistRecordValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}; {}={}",
+            self.issuer_domain_name, ACCOUNT_URI_PARAMETER, self.account_uri
+        )?;
+
+        if self.wildcard {
+            write!(f, "; {}={}", POLICY_PARAMETER, WILDCARD_POLICY)?;
+        }
+
+        if let Some(persist_until) = self.persist_until {
+            write!(f, "; {}={}", PERSIST_UNTIL_PARAMETER, persist_until)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for PersistRecordValue {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let s = s
+            .strip_prefix('"')
+            .and_then(|s| s.strip_suffix('"'))
+            .unwrap_or(s);
+
+        let mut parts = s.split(';').map(str::trim);
+
+        let issuer_domain_name = parts
+            .next()
+            .filter(|p| !p.is_empty() && !p.contains('='))
+            .ok_or_else(|| format!("No issuer domain name found in `{}`", s))?
+            .to_string();
+
+        let mut account_uri = None;
+        let mut wildcard = false;
+        let mut persist_until = None;
+
+        for parameter in parts.filter(|p| !p.is_empty()) {
+            let (key, value) = parameter
+                .split_once('=')
+                .ok_or_else(|| format!("Invalid parameter `{}` in `{}`", parameter, s))?;
+            let (key, value) = (key.trim(), value.trim());
+
+            if key.eq_ignore_ascii_case(ACCOUNT_URI_PARAMETER) {
+                account_uri = Some(value.to_string());
+            } else if key.eq_ignore_ascii_case(POLICY_PARAMETER) {
+                wildcard = value.eq_ignore_ascii_case(WILDCARD_POLICY);
+            } else if key.eq_ignore_ascii_case(PERSIST_UNTIL_PARAMETER) {
+                persist_until = Some(value.parse::<u64>().map_err(|e| {
+                    format!("Invalid {} `{}`: {}", PERSIST_UNTIL_PARAMETER, value, e)
+                })?);
+            }
+            // Unknown parameters are ignored, as required for forward compatibility
+        }
+
+        Ok(Self {
+            issuer_domain_name,
+            account_uri: account_uri
+                .ok_or_else(|| format!("No {} found in `{}`", ACCOUNT_URI_PARAMETER, s))?,
+            wildcard,
+            persist_until,
+        })
+    }
+}
+
+/// The result of auditing a single persistent validation record
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuditFinding {
+    Valid(PersistRecordValue),
+    Expired(PersistRecordValue),
+    Malformed {
+        value: String,
+        reason: String,
+    },
+    /// No valid record of the expected issuer (and account) exists
+    Missing {
+        issuer_domain_name: String,
+        account_uri: Option<String>,
+    },
+}
+
+impl AuditFinding {
+    pub fn is_problem(&self) -> bool {
+        !matches!(self, AuditFinding::Valid(_))
+    }
+}
+
+impl Display for AuditFinding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AuditFinding::Valid(v) => write!(f, "VALID: {}", v),
+            AuditFinding::Expired(v) => write!(f, "EXPIRED: {}", v),
+            AuditFinding::Malformed { value, reason } => {
+                write!(f, "MALFORMED: `{}` ({})", value, reason)
+            }
+            AuditFinding::Missing {
+                issuer_domain_name,
+                account_uri,
+            } => write!(
+                f,
+                "MISSING: no valid record for issuer {} and account {}",
+                issuer_domain_name,
+                account_uri.as_deref().unwrap_or("(any)")
+            ),
+        }
+    }
+}
+
+fn now_unix_timestamp() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .expect("System time is before the UNIX epoch")
+        .as_secs()
+}
+
+pub fn run_regular(
+    client_maker: &dyn Fn() -> reqwest::blocking::Client,
+    args: SimplifiedDnsPersistRunOptions,
+    domain_controllers: BTreeMap<String, Box<dyn DomainController>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let fqdn = format!(
+        "{}.{}",
+        VALIDATION_PERSIST_SUBDOMAIN,
+        args.domain_name.trim_end_matches('.')
+    );
+
+    let resolved = resolve_zone(&fqdn, &domain_controllers)?;
+
+    match args.operation {
+        PersistOperation::Set => {
+            let value = PersistRecordValue {
+                issuer_domain_name: args.issuer_domain_name.unwrap(),
+                account_uri: args.account_uri.unwrap(),
+                wildcard: args.wildcard,
+                persist_until: args.persist_until,
+            };
+
+            set_persist_record(client_maker, &resolved, &value)?;
+        }
+        PersistOperation::Audit => {
+            let findings = audit_persist_records(
+                client_maker,
+                &resolved,
+                args.issuer_domain_name.as_deref(),
+                args.account_uri.as_deref(),
+                now_unix_timestamp(),
+            )?;
+
+            for finding in &findings {
+                println!("{}", finding);
+            }
+
+            let problems_count = findings.iter().filter(|f| f.is_problem()).count();
+            if problems_count > 0 {
+                return Err(Box::new(std::io::Error::other(format!(
+                    "Audit of {} found {} problem(s)",
+                    fqdn, problems_count
+                ))));
+            }
+
+            println!("Audit of {} found no problems", fqdn);
+        }
+        PersistOperation::Remove => {
+            let removed_count = remove_persist_records(
+                client_maker,
+                &resolved,
+                &args.issuer_domain_name.unwrap(),
+                args.account_uri.as_deref(),
+            )?;
+
+            println!("Removed {} record(s) from {}", removed_count, fqdn);
+        }
+    }
+
+    Ok(())
+}
+
+/// Sets the persistent record. Records of the same issuer and account with other values
+/// (e.g., an older policy or expiry) are replaced, so that there's only one record per account.
+fn set_persist_record(
+    client_maker: &dyn Fn() -> reqwest::blocking::Client,
+    resolved: &ResolvedRecord,
+    value: &PersistRecordValue,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let value_string = value.to_string();
+
+    let current_records = list_persist_records(client_maker, resolved)?;
+
+    let mut already_set = false;
+    for current in current_records {
+        match current.parse::<PersistRecordValue>() {
+            // Duplicates of the value are kept: removing one by value would remove all of them
+            Ok(v) if v == *value => already_set = true,
+            Ok(v) if v.belongs_to(&value.issuer_domain_name, Some(&value.account_uri)) => {
+                println!("Replacing outdated record: {}", v);
+
+                resolved.domain_controller.remove_dns_record(
+                    client_maker,
+                    &resolved.record_name,
+                    DnsRecordType::TXT,
+                    Some(&current),
+                )?;
+            }
+            _ => (),
+        }
+    }
+
+    if already_set {
+        println!(
+            "Record is already set: {}={}",
+            resolved.record_name, value_string
+        );
+        return Ok(());
+    }
+
+    resolved.domain_controller.add_dns_record(
+        client_maker,
+        &resolved.record_name,
+        DnsRecordType::TXT,
+        &value_string,
+    )?;
+
+    println!(
+        "Record set in zone {}: {}={}",
+        resolved.zone, resolved.record_name, value_string
+    );
+
+    Ok(())
+}
+
+fn audit_persist_records(
+    client_maker: &dyn Fn() -> reqwest::blocking::Client,
+    resolved: &ResolvedRecord,
+    issuer_domain_name: Option<&str>,
+    account_uri: Option<&str>,
+    now: u64,
+) -> Result<Vec<AuditFinding>, Box<dyn std::error::Error>> {
+    let mut findings = list_persist_records(client_maker, resolved)?
+        .into_iter()
+        .map(|value| match value.parse::<PersistRecordValue>() {
+            Ok(v) if v.is_expired(now) => AuditFinding::Expired(v),
+            Ok(v) => AuditFinding::Valid(v),
+            Err(reason) => AuditFinding::Malformed { value, reason },
+        })
+        .collect::<Vec<_>>();
+
+    if let Some(issuer_domain_name) = issuer_domain_name {
+        let found = findings.iter().any(|f| match f {
+            AuditFinding::Valid(v) => v.belongs_to(issuer_domain_name, account_uri),
+            _ => false,
+        });
+
+        if !found {
+            findings.push(AuditFinding::Missing {
+                issuer_domain_name: issuer_domain_name.to_string(),
+                account_uri: account_uri.map(|a| a.to_string()),
+            });
+        }
+    }
+
+    Ok(findings)
+}
+
+fn remove_persist_records(
+    client_maker: &dyn Fn() -> reqwest::blocking::Client,
+    resolved: &ResolvedRecord,
+    issuer_domain_name: &str,
+    account_uri: Option<&str>,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let mut removed_count = 0;
+
+    for current in list_persist_records(client_maker, resolved)? {
+        let belongs = current
+            .parse::<PersistRecordValue>()
+            .is_ok_and(|v| v.belongs_to(issuer_domain_name, account_uri));
+
+        if belongs {
+            removed_count += resolved.domain_controller.remove_dns_record(
+                client_maker,
+                &resolved.record_name,
+                DnsRecordType::TXT,
+                Some(&current),
+            )?;
+        }
+    }
+
+    Ok(removed_count)
+}
+
+/// The values of the TXT records with the persistent validation name
+fn list_persist_records(
+    client_maker: &dyn Fn() -> reqwest::blocking::Client,
+    resolved: &ResolvedRecord,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let records = resolved.domain_controller.list_dns_records(client_maker)?;
+
+    Ok(records
+        .into_iter()
+        .filter(|r| {
+            r.record_type == DnsRecordType::TXT
+                && r.name.eq_ignore_ascii_case(&resolved.record_name)
+        })
+        .map(|r| r.value)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dns_providers::{helpers::build_client, in_memory::InMemory};
+
+    use super::*;
+
+    const ACCOUNT_URI: &str = "https://ca.example/acct/1";
+
+    fn value(wildcard: bool, persist_until: Option<u64>) -> PersistRecordValue {
+        PersistRecordValue {
+            issuer_domain_name: "ca.example".to_string(),
+            account_uri: ACCOUNT_URI.to_string(),
+            wildcard,
+            persist_until,
+        }
+    }
+
+    #[test]
+    fn test_record_value_format() {
+        assert_eq!(
+            value(false, None).to_string(),
+            "ca.example; accounturi=https://ca.example/acct/1"
+        );
+        assert_eq!(
+            value(true, Some(1767225600)).to_string(),
+            "ca.example; accounturi=https://ca.example/acct/1; policy=wildcard; persistUntil=1767225600"
+        );
+
+        for v in [value(false, None), value(true, Some(1767225600))] {
+            assert_eq!(v.to_string().parse::<PersistRecordValue>().unwrap(), v);
+        }
+
+        assert_eq!(
+            "\"ca.example;accounturi=https://ca.example/acct/1 ;POLICY=Wildcard;future=1\""
+                .parse::<PersistRecordValue>()
+                .unwrap(),
+            value(true, None)
+        );
+
+        assert!("accounturi=x".parse::<PersistRecordValue>().is_err());
+        assert!(
+            "ca.example; policy=wildcard"
+                .parse::<PersistRecordValue>()
+                .is_err()
+        );
+        assert!(
+            "ca.example; accounturi=x; persistUntil=soon"
+                .parse::<PersistRecordValue>()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_set_audit_and_remove() {
+        let zone = InMemory::default();
+        let mut domain_controllers: BTreeMap<String, Box<dyn DomainController>> = BTreeMap::new();
+        domain_controllers.insert("example.com".to_string(), Box::new(zone.clone()));
+        let resolved =
+            resolve_zone("_validation-persist.example.com", &domain_controllers).unwrap();
+
+        let client_maker = || build_client(None);
+
+        set_persist_record(&client_maker, &resolved, &value(false, Some(100))).unwrap();
+        // Setting again with a new policy replaces the record, instead of adding another one
+        set_persist_record(&client_maker, &resolved, &value(true, Some(200))).unwrap();
+        set_persist_record(&client_maker, &resolved, &value(true, Some(200))).unwrap();
+
+        let records = zone.records();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].name, "_validation-persist");
+        assert_eq!(records[0].value, value(true, Some(200)).to_string());
+
+        let findings =
+            audit_persist_records(&client_maker, &resolved, Some("ca.example"), None, 150).unwrap();
+        assert_eq!(findings, vec![AuditFinding::Valid(value(true, Some(200)))]);
+
+        let findings =
+            audit_persist_records(&client_maker, &resolved, Some("ca.example"), None, 300).unwrap();
+        assert_eq!(findings.len(), 2);
+        assert_eq!(findings[0], AuditFinding::Expired(value(true, Some(200))));
+        assert!(matches!(findings[1], AuditFinding::Missing { .. }));
+
+        let removed_count =
+            remove_persist_records(&client_maker, &resolved, "other.example", None).unwrap();
+        assert_eq!(removed_count, 0);
+
+        let removed_count =
+            remove_persist_records(&client_maker, &resolved, "ca.example", Some(ACCOUNT_URI))
+                .unwrap();
+        assert_eq!(removed_count, 1);
+        assert!(zone.records().is_empty());
+    }
+
+    #[test]
+    fn test_set_with_duplicate_records() {
+        let zone = InMemory::default();
+        let mut domain_controllers: BTreeMap<String, Box<dyn DomainController>> = BTreeMap::new();
+        domain_controllers.insert("example.com".to_string(), Box::new(zone.clone()));
+        let resolved =
+            resolve_zone("_validation-persist.example.com", &domain_controllers).unwrap();
+
+        let client_maker = || build_client(None);
+
+        for v in [
+            value(true, Some(200)),
+            value(true, Some(200)),
+            value(false, None),
+        ] {
+            zone.add_dns_record(
+                &client_maker,
+                "_validation-persist",
+                DnsRecordType::TXT,
+                &v.to_string(),
+            )
+            .unwrap();
+        }
+
+        // The outdated record is replaced, and the zone still holds the value
+        set_persist_record(&client_maker, &resolved, &value(true, Some(200))).unwrap();
+
+        let records = zone.records();
+        assert_eq!(records.len(), 2);
+        assert!(
+            records
+                .iter()
+                .all(|r| r.value == value(true, Some(200)).to_string())
+        );
+    }
+}
@@ -0,0 +1,31 @@
+mod logic;
+
+use crate::{
+    config::Config, dns_persist::logic::run_regular, dns_providers::helpers::build_client,
+    run_options::dns_persist_run_options::DnsPersistRunOptions,
+};
+
+pub fn run(options: DnsPersistRunOptions) -> Result<(), Box<dyn std::error::Error>> {
+    options
+        .check()
+        .unwrap_or_else(|e| panic!("Arguments provided are not correct: {}", e));
+
+    println!("Starting in dns-persist mode with args: {:?}", &options);
+
+    let config = Config::from_file_or_default(&options.config_file_path)?;
+
+    println!("Starting with config: {:?}", config);
+
+    let domain_controllers = config.into_domain_controllers();
+
+    let proxy = options.proxy.clone();
+    let client_maker = Box::new(|| build_client(proxy.clone()));
+
+    run_regular(
+        client_maker.as_ref(),
+        options.into_simplified(),
+        domain_controllers,
+    )?;
+
+    Ok(())
+}
@@ -0,0 +1,519 @@
+//! The long-running dyndns mode. The address is detected every interval, and the provider's API is only
+//! called when it changed since it was last published, or when a periodic full reconcile is due.
+
+use std::{
+    collections::BTreeMap,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    run_options::dyndns_run_options::SimplifiedDynDnsRunOptions,
+    traits::domain_control::{DnsRecordType, DomainController},
+};
+
+use super::{
+    ip_detection::{
+        IpDetectionConfig, IpFamily, detect_publishable_ip_address, special_use::warn_if_behind_nat,
+    },
+    logic::{fully_qualified_name, set_ip_record},
+    netlink::AddressWatcher,
+};
+
+/// How long to sleep at once while waiting, so that termination signals are handled quickly
+const SLEEP_STEP: Duration = Duration::from_millis(200);
+
+static TERMINATION_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DaemonOptions {
+    pub interval: Duration,
+    pub reconcile_interval: Duration,
+    pub max_backoff: Duration,
+    pub state_file_path: PathBuf,
+    /// The interface whose address changes trigger a check right away, besides the interval
+    pub watch_interface: Option<String>,
+    /// How long the watched interface must stay quiet before the check
+    pub debounce: Duration,
+}
+
+/// What was published, kept in the state file between runs
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct DynDnsState {
+    /// The last published address of every record, keyed by `zone/name/type`
+    #[serde(default)]
+    pub published: BTreeMap<String, IpAddr>,
+    /// When all the records were last checked against the provider, in seconds since the Unix epoch
+    #[serde(default)]
+    pub last_reconcile: Option<u64>,
+}
+
+impl DynDnsState {
+    /// Loads the state, or returns an empty one if the file doesn't exist yet
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let data = std::fs::read_to_string(path)?;
+
+        serde_json::from_str(&data).map_err(|e| {
+            format!(
+                "Could not parse the dyndns state file {}: {}",
+                path.display(),
+                e
+            )
+            .into()
+        })
+    }
+
+    /// Writes to a temporary file first, so that the state file is never left half written
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let temporary_path = path.with_extension("tmp");
+
+        std::fs::write(&temporary_path, serde_json::to_string_pretty(self)?)?;
+        std::fs::rename(&temporary_path, path)?;
+
+        Ok(())
+    }
+
+    pub fn key(zone: &str, name: &str, record_type: DnsRecordType) -> String {
+        format!("{}/{}/{}", zone, name, record_type)
+    }
+
+    fn reconcile_due(&self, reconcile_interval: Duration, now: u64) -> bool {
+        self.last_reconcile
+            .is_none_or(|last| now.saturating_sub(last) >= reconcile_interval.as_secs())
+    }
+}
+
+fn now_unix_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+extern "C" fn request_termination(_signal: libc::c_int) {
+    TERMINATION_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+/// SIGTERM (e.g., from systemd or docker) and SIGINT stop the loop after the current iteration
+fn install_termination_handlers() {
+    let handler = request_termination as extern "C" fn(libc::c_int) as libc::sighandler_t;
+
+    // SAFETY: the handler is async-signal-safe, as it only stores to an atomic, and it's a valid
+    // extern "C" function for the whole life of the process
+    unsafe {
+        libc::signal(libc::SIGTERM, handler);
+        libc::signal(libc::SIGINT, handler);
+    }
+}
+
+pub fn termination_requested() -> bool {
+    TERMINATION_REQUESTED.load(Ordering::SeqCst)
+}
+
+/// Sleeps for the duration, or until termination is requested
+pub fn sleep_unless_terminated(duration: Duration) {
+    let start = Instant::now();
+
+    while !termination_requested() {
+        let elapsed = start.elapsed();
+        if elapsed >= duration {
+            break;
+        }
+
+        std::thread::sleep(SLEEP_STEP.min(duration - elapsed));
+    }
+}
+
+/// Waits for the delay, or, with a watcher, until an address of the watched interface changed and then stayed
+/// the same for the debounce duration
+fn wait_for_next_check(delay: Duration, watcher: Option<&AddressWatcher>, debounce: Duration) {
+    let Some(watcher) = watcher else {
+        return sleep_unless_terminated(delay);
+    };
+
+    let changed = watcher.wait_for_change(delay).and_then(|changed| {
+        if changed {
+            watcher.wait_until_quiet(debounce)?;
+        }
+        Ok(changed)
+    });
+
+    match changed {
+        Ok(true) => println!(
+            "The addresses of `{}` changed. Checking the public address now",
+            watcher.interface()
+        ),
+        Ok(false) => {}
+        Err(e) => {
+            eprintln!(
+                "Failed to watch the addresses of `{}`, waiting for the interval instead: {}",
+                watcher.interface(),
+                e
+            );
+            sleep_unless_terminated(delay);
+        }
+    }
+}
+
+/// The interval, doubled for every consecutive failure, up to the maximum
+pub fn backoff_delay(
+    interval: Duration,
+    consecutive_failures: u32,
+    max_backoff: Duration,
+) -> Duration {
+    let factor = 2u32.saturating_pow(consecutive_failures);
+
+    interval
+        .saturating_mul(factor)
+        .min(max_backoff.max(interval))
+}
+
+pub fn run_daemon(
+    client_maker: &dyn Fn() -> reqwest::blocking::Client,
+    ip_detection_client_maker: &dyn Fn(IpFamily) -> reqwest::blocking::Client,
+    args: SimplifiedDynDnsRunOptions,
+    options: DaemonOptions,
+    ip_detection_config: &IpDetectionConfig,
+    domain_controllers: BTreeMap<String, Box<dyn DomainController>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let domain_controller = domain_controllers
+        .get(&args.account_domain_name)
+        .unwrap_or_else(|| {
+            panic!(
+                "Domain controller for domain {} not found in config",
+                &args.account_domain_name
+            )
+        });
+
+    install_termination_handlers();
+
+    // Without a working watcher, the daemon still polls every interval
+    let watcher = options.watch_interface.as_deref().and_then(|interface| {
+        AddressWatcher::new(interface)
+            .inspect_err(|e| {
+                eprintln!(
+                    "Could not watch the addresses of `{}`, polling only: {}",
+                    interface, e
+                )
+            })
+            .ok()
+    });
+
+    let mut state = DynDnsState::load(&options.state_file_path)?;
+    let mut consecutive_failures = 0;
+    let mut nat_checked = false;
+
+    println!(
+        "Dyndns daemon started. Checking every {} seconds, reconciling every {} seconds",
+        options.interval.as_secs(),
+        options.reconcile_interval.as_secs()
+    );
+
+    while !termination_requested() {
+        let previous_state = state.clone();
+        let now = now_unix_secs();
+        let reconcile = state.reconcile_due(options.reconcile_interval, now);
+
+        let families = args.family.families();
+        let addresses = families
+            .iter()
+            .filter_map(|family| {
+                detect_publishable_ip_address(
+                    ip_detection_client_maker,
+                    &args.ip_sources,
+                    *family,
+                    ip_detection_config,
+                    args.allow_bogon,
+                )
+                .inspect_err(|e| eprintln!("Could not detect the public {} address: {}", family, e))
+                .ok()
+            })
+            .collect::<Vec<_>>();
+
+        // Once is enough, instead of repeating the warning every interval
+        if !nat_checked && let Some(address) = addresses.first() {
+            warn_if_behind_nat(address);
+            nat_checked = true;
+        }
+
+        let delay = match publish_changes(
+            client_maker,
+            domain_controller.as_ref(),
+            &args,
+            &addresses,
+            &mut state,
+            reconcile,
+        ) {
+            Ok(()) => {
+                consecutive_failures = 0;
+
+                // A reconcile only counts if every family was checked
+                if reconcile && addresses.len() == families.len() {
+                    state.last_reconcile = Some(now);
+                }
+
+                options.interval
+            }
+            Err(e) => {
+                consecutive_failures += 1;
+
+                let delay =
+                    backoff_delay(options.interval, consecutive_failures, options.max_backoff);
+                eprintln!(
+                    "Failed to update the records ({} failures in a row). Retrying in {} seconds: {}",
+                    consecutive_failures,
+                    delay.as_secs(),
+                    e
+                );
+
+                delay
+            }
+        };
+
+        if state != previous_state {
+            state.save(&options.state_file_path)?;
+        }
+
+        wait_for_next_check(delay, watcher.as_ref(), options.debounce);
+    }
+
+    println!("Termination requested. Dyndns daemon stopped.");
+
+    Ok(())
+}
+
+/// Publishes the addresses that changed since they were last published, or all of them when reconciling.
+/// The state is updated for every record that was published, even if others failed.
+fn publish_changes(
+    client_maker: &dyn Fn() -> reqwest::blocking::Client,
+    domain_controller: &dyn DomainController,
+    args: &SimplifiedDynDnsRunOptions,
+    addresses: &[IpAddr],
+    state: &mut DynDnsState,
+    reconcile: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (zone, subdomain) = (&args.account_domain_name, &args.subdomain);
+    let sources = args
+        .ip_sources
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>();
+    let mut failures = Vec::new();
+
+    for address in addresses {
+        let record_type = IpFamily::of(address).record_type();
+        let key = DynDnsState::key(zone, subdomain, record_type);
+
+        if !reconcile && state.published.get(&key) == Some(address) {
+            continue;
+        }
+
+        let outcome = set_ip_record(client_maker, domain_controller, subdomain, *address);
+
+        if let Some(history) = &args.history {
+            history.record(
+                &fully_qualified_name(subdomain, zone),
+                record_type,
+                &address.to_string(),
+                &sources,
+                &outcome,
+            );
+        }
+
+        match outcome {
+            Ok(_) => {
+                state.published.insert(key, *address);
+            }
+            Err(e) => failures.push(format!("{}: {}", record_type, e)),
+        }
+    }
+
+    if !failures.is_empty() {
+        return Err(Box::new(std::io::Error::other(failures.join("; "))));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        dns_providers::{helpers::build_client, in_memory::InMemory},
+        features::dyndns::{
+            history::{ChangeHistory, ChangeResult, load_history},
+            ip_detection::IpSource,
+        },
+        run_options::dyndns_run_options::FamilySelection,
+        traits::domain_control::DnsRecord,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_backoff_delay() {
+        let interval = Duration::from_secs(60);
+        let max_backoff = Duration::from_secs(600);
+
+        assert_eq!(
+            backoff_delay(interval, 1, max_backoff),
+            Duration::from_secs(120)
+        );
+        assert_eq!(
+            backoff_delay(interval, 3, max_backoff),
+            Duration::from_secs(480)
+        );
+        assert_eq!(backoff_delay(interval, 4, max_backoff), max_backoff);
+        assert_eq!(backoff_delay(interval, 100, max_backoff), max_backoff);
+    }
+
+    #[test]
+    fn test_reconcile_due() {
+        let mut state = DynDnsState::default();
+        let reconcile_interval = Duration::from_secs(3600);
+
+        assert!(state.reconcile_due(reconcile_interval, 10_000));
+
+        state.last_reconcile = Some(10_000);
+        assert!(!state.reconcile_due(reconcile_interval, 10_100));
+        assert!(state.reconcile_due(reconcile_interval, 13_600));
+    }
+
+    #[test]
+    fn test_publish_changes_only_calls_provider_on_change() {
+        let zone = InMemory::default();
+        let client_maker = || build_client(None);
+        let mut state = DynDnsState::default();
+
+        let history_path = std::env::temp_dir().join(format!(
+            "sam-dns-tools-daemon-history-{}.jsonl",
+            std::process::id()
+        ));
+        let args = SimplifiedDynDnsRunOptions {
+            account_domain_name: "example.com".to_string(),
+            subdomain: "home".to_string(),
+            family: FamilySelection::V4,
+            allow_bogon: false,
+            ip_sources: vec![IpSource::Web],
+            daemon: None,
+            history: Some(ChangeHistory::new(history_path.clone())),
+        };
+
+        let address: IpAddr = "203.0.113.7".parse().unwrap();
+        publish_changes(&client_maker, &zone, &args, &[address], &mut state, false).unwrap();
+        assert_eq!(zone.list_calls(), 1);
+        assert_eq!(state.published.get("example.com/home/A"), Some(&address));
+
+        // Unchanged: the provider is not called
+        publish_changes(&client_maker, &zone, &args, &[address], &mut state, false).unwrap();
+        assert_eq!(zone.list_calls(), 1);
+
+        // Someone changed the record behind our back; only a reconcile notices
+        zone.remove_dns_record(&client_maker, "home", DnsRecordType::A, None)
+            .unwrap();
+        publish_changes(&client_maker, &zone, &args, &[address], &mut state, true).unwrap();
+        assert_eq!(zone.list_calls(), 2);
+        assert_eq!(
+            zone.records(),
+            vec![DnsRecord {
+                name: "home".to_string(),
+                record_type: DnsRecordType::A,
+                value: "203.0.113.7".to_string(),
+                ttl: None,
+                priority: None,
+            }]
+        );
+
+        // Changed: the provider is called
+        let new_address: IpAddr = "203.0.113.8".parse().unwrap();
+        publish_changes(
+            &client_maker,
+            &zone,
+            &args,
+            &[new_address],
+            &mut state,
+            false,
+        )
+        .unwrap();
+        assert_eq!(zone.list_calls(), 3);
+        assert_eq!(zone.records()[0].value, "203.0.113.8");
+
+        // Only the changes are in the history
+        let history = load_history(&history_path).unwrap();
+        std::fs::remove_file(&history_path).unwrap();
+        assert_eq!(
+            history
+                .iter()
+                .map(|e| (e.record.as_str(), e.old_value.as_deref(), e.result))
+                .collect::<Vec<_>>(),
+            vec![
+                ("home.example.com", None, ChangeResult::Created),
+                ("home.example.com", None, ChangeResult::Created),
+                (
+                    "home.example.com",
+                    Some("203.0.113.7"),
+                    ChangeResult::Updated
+                ),
+            ]
+        );
+        assert_eq!(history[0].sources, vec!["web services".to_string()]);
+    }
+
+    #[test]
+    fn test_publish_changes_reports_failed_duplicate_removal() {
+        let zone = InMemory::default();
+        let client_maker = || build_client(None);
+        let mut state = DynDnsState::default();
+        let args = SimplifiedDynDnsRunOptions {
+            account_domain_name: "example.com".to_string(),
+            subdomain: "home".to_string(),
+            family: FamilySelection::V4,
+            allow_bogon: false,
+            ip_sources: vec![IpSource::Web],
+            daemon: None,
+            history: None,
+        };
+
+        for value in ["203.0.113.7", "203.0.113.8"] {
+            zone.add_dns_record(&client_maker, "home", DnsRecordType::A, value)
+                .unwrap();
+        }
+        zone.fail_removals();
+
+        // The error is returned for the backoff, instead of bringing the daemon down
+        let address: IpAddr = "203.0.113.7".parse().unwrap();
+        let error = publish_changes(&client_maker, &zone, &args, &[address], &mut state, false)
+            .unwrap_err();
+        assert!(error.to_string().starts_with("A: Failed to remove"));
+        assert!(state.published.is_empty());
+    }
+
+    #[test]
+    fn test_state_file_round_trip() {
+        let path = std::env::temp_dir().join(format!(
+            "sam-dns-tools-dyndns-state-{}.json",
+            std::process::id()
+        ));
+
+        assert_eq!(DynDnsState::load(&path).unwrap(), DynDnsState::default());
+
+        let mut state = DynDnsState::default();
+        state.published.insert(
+            "example.com/home/AAAA".to_string(),
+            "2001:db8::1".parse().unwrap(),
+        );
+        state.last_reconcile = Some(1_700_000_000);
+        state.save(&path).unwrap();
+
+        assert_eq!(DynDnsState::load(&path).unwrap(), state);
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
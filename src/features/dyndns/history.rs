@@ -0,0 +1,529 @@
+//! The local history of the dyndns record changes, appended to a JSON Lines file (one change per line),
+//! and the summary of `dyndns history`, e.g., to see how often a flaky connection got a new address
+
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    io::Write,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    run_options::dyndns_run_options::SimplifiedDynDnsHistoryRunOptions,
+    traits::domain_control::DnsRecordType,
+    utils::calendar::{civil_from_days, days_from_civil},
+};
+
+use super::logic::RecordChange;
+
+/// What the provider did with the change
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeResult {
+    Created,
+    Updated,
+    Failed,
+}
+
+impl Display for ChangeResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChangeResult::Created => write!(f, "created"),
+            ChangeResult::Updated => write!(f, "updated"),
+            ChangeResult::Failed => write!(f, "failed"),
+        }
+    }
+}
+
+/// A line of the history file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// When the change was published (or failed), in seconds since the Unix epoch
+    pub timestamp: u64,
+    /// The fully qualified name of the record, e.g., `home.example.com`
+    pub record: String,
+    pub record_type: DnsRecordType,
+    /// The value at the provider before the change. None if the record was created, or if the update failed.
+    #[serde(default)]
+    pub old_value: Option<String>,
+    pub new_value: String,
+    /// Where the new value was detected, e.g., `web services`
+    #[serde(default)]
+    pub sources: Vec<String>,
+    pub result: ChangeResult,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The history file that changes are appended to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChangeHistory {
+    path: PathBuf,
+}
+
+impl ChangeHistory {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// Appends the outcome of setting a record, unless the record was already set.
+    /// A history that can't be written only prints a warning, as the record itself was handled.
+    pub fn record(
+        &self,
+        record: &str,
+        record_type: DnsRecordType,
+        new_value: &str,
+        sources: &[String],
+        outcome: &Result<RecordChange, Box<dyn std::error::Error>>,
+    ) {
+        let (result, old_value, error) = match outcome {
+            Ok(RecordChange::Unchanged) => return,
+            Ok(RecordChange::Created) => (ChangeResult::Created, None, None),
+            Ok(RecordChange::Updated { old_value }) => {
+                (ChangeResult::Updated, Some(old_value.clone()), None)
+            }
+            Err(e) => (ChangeResult::Failed, None, Some(e.to_string())),
+        };
+
+        let entry = HistoryEntry {
+            timestamp: now_unix_secs(),
+            record: record.to_string(),
+            record_type,
+            old_value,
+            new_value: new_value.to_string(),
+            sources: sources.to_vec(),
+            result,
+            error,
+        };
+
+        if let Err(e) = self.append(&entry) {
+            eprintln!(
+                "Warning: could not write the change to the history file {}: {}",
+                self.path.display(),
+                e
+            );
+        }
+    }
+
+    fn append(&self, entry: &HistoryEntry) -> Result<(), Box<dyn std::error::Error>> {
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+
+        // One write per line, so that concurrent runs don't interleave their lines
+        file.write_all(format!("{}\n", serde_json::to_string(entry)?).as_bytes())?;
+
+        Ok(())
+    }
+}
+
+/// Reads the history file. A file that doesn't exist yet is an empty history,
+/// and lines that can't be parsed (e.g., cut by a full disk) are skipped with a warning.
+pub fn load_history(path: &Path) -> Result<Vec<HistoryEntry>, Box<dyn std::error::Error>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let data = std::fs::read_to_string(path)?;
+
+    Ok(data
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .filter_map(|(index, line)| {
+            serde_json::from_str(line)
+                .inspect_err(|e| {
+                    eprintln!(
+                        "Skipping line {} of the history file {}: {}",
+                        index + 1,
+                        path.display(),
+                        e
+                    )
+                })
+                .ok()
+        })
+        .collect())
+}
+
+fn now_unix_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// E.g., `2026-10-19 08:30:00 UTC`
+pub fn format_timestamp(timestamp: u64) -> String {
+    let seconds = timestamp as i64;
+    let (year, month, day) = civil_from_days(seconds.div_euclid(86400));
+    let time = seconds.rem_euclid(86400);
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+/// E.g., `3d 4h`, `2h 5m` or `40s`, with the two largest units only
+pub fn format_duration(seconds: u64) -> String {
+    let parts = [
+        (seconds / 86400, "d"),
+        (seconds % 86400 / 3600, "h"),
+        (seconds % 3600 / 60, "m"),
+        (seconds % 60, "s"),
+    ]
+    .into_iter()
+    .skip_while(|(value, _)| *value == 0)
+    .take(2)
+    .filter(|(value, _)| *value > 0)
+    .map(|(value, unit)| format!("{}{}", value, unit))
+    .collect::<Vec<_>>();
+
+    match parts.is_empty() {
+        true => "0s".to_string(),
+        false => parts.join(" "),
+    }
+}
+
+/// Parses a point in time of `--since` and `--until`: a duration ago (`30m`, `12h`, `7d`),
+/// a date (`2026-10-01`) or a date and time in UTC (`2026-10-01T08:00:00Z`), or seconds since the Unix epoch
+pub fn parse_time(value: &str, now: u64) -> Result<u64, String> {
+    let error = || {
+        format!(
+            "Invalid time `{}`. Expected a duration ago (e.g., 12h or 7d), a date (2026-10-01), \
+             a date and time in UTC (2026-10-01T08:00:00Z) or seconds since the Unix epoch",
+            value
+        )
+    };
+
+    if let Ok(timestamp) = value.parse::<u64>() {
+        return Ok(timestamp);
+    }
+
+    if let Some(unit) = value.chars().last().filter(|c| "smhdw".contains(*c)) {
+        let amount = value[..value.len() - 1]
+            .parse::<u64>()
+            .map_err(|_| error())?;
+        let unit_seconds = match unit {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            'd' => 86400,
+            _ => 7 * 86400,
+        };
+
+        return Ok(now.saturating_sub(amount.saturating_mul(unit_seconds)));
+    }
+
+    let (date, time) = match value.trim_end_matches('Z').split_once(['T', ' ']) {
+        Some((date, time)) => (date, Some(time)),
+        None => (value, None),
+    };
+
+    let numbers = |text: &str, separator: char| {
+        text.split(separator)
+            .map(|v| v.parse::<i64>().ok())
+            .collect::<Option<Vec<_>>>()
+    };
+
+    let [year, month, day] = numbers(date, '-').ok_or_else(error)?[..] else {
+        return Err(error());
+    };
+    let (hour, minute, second) = match time.map(|time| numbers(time, ':')) {
+        None => (0, 0, 0),
+        Some(Some(time)) => match time[..] {
+            [hour, minute] => (hour, minute, 0),
+            [hour, minute, second] => (hour, minute, second),
+            _ => return Err(error()),
+        },
+        Some(None) => return Err(error()),
+    };
+
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 {
+        return Err(error());
+    }
+
+    let days = days_from_civil(year, month, day);
+    u64::try_from(days * 86400 + hour * 3600 + minute * 60 + second).map_err(|_| error())
+}
+
+/// The summary of the changes of one record in the time window
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordStats {
+    pub changes: usize,
+    pub failures: usize,
+    /// The value held the longest without interruption in the window, and for how many seconds
+    pub longest_held: Option<(String, u64)>,
+}
+
+/// Summarizes the changes of every record, `(record, type)`, between the start and the end of the window.
+/// A value is held from its change until the next change of the record, or until the end of the window.
+/// The value replaced by the first change is counted as held since the start of the window.
+pub fn summarize(
+    entries: &[&HistoryEntry],
+    window_start: u64,
+    window_end: u64,
+) -> BTreeMap<(String, String), RecordStats> {
+    let mut by_record: BTreeMap<(String, String), Vec<&HistoryEntry>> = BTreeMap::new();
+    for entry in entries {
+        by_record
+            .entry((entry.record.clone(), entry.record_type.to_string()))
+            .or_default()
+            .push(entry);
+    }
+
+    by_record
+        .into_iter()
+        .map(|(key, mut entries)| {
+            entries.sort_by_key(|e| e.timestamp);
+
+            let (succeeded, failed): (Vec<&HistoryEntry>, Vec<&HistoryEntry>) = entries
+                .into_iter()
+                .partition(|e| e.result != ChangeResult::Failed);
+
+            // The periods in which each value was held, merging consecutive changes to the same value
+            let mut periods: Vec<(String, u64, u64)> = Vec::new();
+            if let Some(old_value) = succeeded.first().and_then(|e| e.old_value.clone()) {
+                periods.push((old_value, window_start, window_start));
+            }
+            for entry in &succeeded {
+                match periods.last_mut() {
+                    Some((value, _, _)) if *value == entry.new_value => {}
+                    Some((_, _, end)) => {
+                        *end = entry.timestamp;
+                        periods.push((entry.new_value.clone(), entry.timestamp, entry.timestamp));
+                    }
+                    None => {
+                        periods.push((entry.new_value.clone(), entry.timestamp, entry.timestamp))
+                    }
+                }
+            }
+            if let Some((_, _, end)) = periods.last_mut() {
+                *end = window_end.max(*end);
+            }
+
+            let longest_held = periods
+                .into_iter()
+                .map(|(value, start, end)| (value, end.saturating_sub(start)))
+                .max_by_key(|(_, held)| *held);
+
+            let stats = RecordStats {
+                changes: succeeded.len(),
+                failures: failed.len(),
+                longest_held,
+            };
+
+            (key, stats)
+        })
+        .collect()
+}
+
+pub fn run_history(
+    args: SimplifiedDynDnsHistoryRunOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let history = load_history(&args.history_file_path)?;
+
+    let entries = history
+        .iter()
+        .filter(|e| args.since.is_none_or(|since| e.timestamp >= since))
+        .filter(|e| args.until.is_none_or(|until| e.timestamp <= until))
+        .filter(|e| args.record.as_ref().is_none_or(|r| e.record == *r))
+        .collect::<Vec<_>>();
+
+    let window_start = args
+        .since
+        .or_else(|| entries.iter().map(|e| e.timestamp).min())
+        .unwrap_or(args.now);
+    let window_end = args.until.unwrap_or(args.now);
+
+    println!(
+        "Dyndns history from {} to {} ({} entries in {})",
+        format_timestamp(window_start),
+        format_timestamp(window_end),
+        entries.len(),
+        args.history_file_path.display()
+    );
+
+    for entry in &entries {
+        let change = match &entry.old_value {
+            Some(old_value) => format!("{} -> {}", old_value, entry.new_value),
+            None => entry.new_value.clone(),
+        };
+        let result = match &entry.error {
+            Some(error) => format!("{}: {}", entry.result, error),
+            None => entry.result.to_string(),
+        };
+
+        println!(
+            "  {}  {} ({})  {}  {}  [{}]",
+            format_timestamp(entry.timestamp),
+            entry.record,
+            entry.record_type,
+            change,
+            result,
+            entry.sources.join(", ")
+        );
+    }
+
+    if entries.is_empty() {
+        return Ok(());
+    }
+
+    let window_days = window_end.saturating_sub(window_start).max(1) as f64 / 86400.0;
+
+    println!("Summary:");
+    for ((record, record_type), stats) in summarize(&entries, window_start, window_end) {
+        print!(
+            "  {} ({}): {} changes, {:.2} per day",
+            record,
+            record_type,
+            stats.changes,
+            stats.changes as f64 / window_days
+        );
+        if stats.failures > 0 {
+            print!(", {} failed updates", stats.failures);
+        }
+        match stats.longest_held {
+            Some((value, held)) => {
+                println!(", longest held: {} for {}", value, format_duration(held))
+            }
+            None => println!(),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(timestamp: u64, old_value: Option<&str>, new_value: &str) -> HistoryEntry {
+        HistoryEntry {
+            timestamp,
+            record: "home.example.com".to_string(),
+            record_type: DnsRecordType::A,
+            old_value: old_value.map(ToString::to_string),
+            new_value: new_value.to_string(),
+            sources: vec!["web services".to_string()],
+            result: match old_value {
+                Some(_) => ChangeResult::Updated,
+                None => ChangeResult::Created,
+            },
+            error: None,
+        }
+    }
+
+    #[test]
+    fn test_history_file() {
+        let path = std::env::temp_dir().join(format!(
+            "sam-dns-tools-history-{}.jsonl",
+            std::process::id()
+        ));
+        let history = ChangeHistory::new(path.clone());
+        let sources = vec!["web services".to_string()];
+
+        history.record(
+            "home.example.com",
+            DnsRecordType::A,
+            "203.0.113.1",
+            &sources,
+            &Ok(RecordChange::Created),
+        );
+        history.record(
+            "home.example.com",
+            DnsRecordType::A,
+            "203.0.113.1",
+            &sources,
+            &Ok(RecordChange::Unchanged),
+        );
+        history.record(
+            "home.example.com",
+            DnsRecordType::A,
+            "203.0.113.2",
+            &sources,
+            &Err("Provider unavailable".into()),
+        );
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"{\"cut\n")
+            .unwrap();
+
+        let entries = load_history(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].result, ChangeResult::Created);
+        assert_eq!(entries[0].sources, sources);
+        assert_eq!(entries[1].result, ChangeResult::Failed);
+        assert_eq!(entries[1].error.as_deref(), Some("Provider unavailable"));
+    }
+
+    #[test]
+    fn test_times() {
+        let now = 1_792_058_400; // 2026-10-15 10:00:00 UTC
+
+        assert_eq!(format_timestamp(now), "2026-10-15 10:00:00 UTC");
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00:00 UTC");
+        assert_eq!(format_timestamp(951_782_400), "2000-02-29 00:00:00 UTC");
+
+        assert_eq!(parse_time("2d", now), Ok(now - 2 * 86400));
+        assert_eq!(parse_time("30m", now), Ok(now - 1800));
+        assert_eq!(parse_time("1700000000", now), Ok(1_700_000_000));
+        assert_eq!(parse_time("2026-10-15", now), Ok(now - 10 * 3600));
+        assert_eq!(parse_time("2026-10-15T10:00:00Z", now), Ok(now));
+        assert_eq!(parse_time("2026-10-15 10:00", now), Ok(now));
+        assert!(parse_time("yesterday", now).is_err());
+        assert!(parse_time("2026-13-01", now).is_err());
+
+        assert_eq!(format_duration(0), "0s");
+        assert_eq!(format_duration(45), "45s");
+        assert_eq!(format_duration(3 * 86400 + 4 * 3600 + 5), "3d 4h");
+        assert_eq!(format_duration(2 * 3600 + 5 * 60 + 7), "2h 5m");
+        assert_eq!(format_duration(86400 + 7), "1d");
+    }
+
+    #[test]
+    fn test_summarize() {
+        let day = 86400;
+        let mut failed = entry(3 * day, None, "203.0.113.9");
+        failed.result = ChangeResult::Failed;
+
+        let entries = [
+            entry(day, Some("203.0.113.1"), "203.0.113.2"),
+            failed,
+            entry(2 * day, Some("203.0.113.2"), "203.0.113.3"),
+            entry(7 * day, Some("203.0.113.3"), "203.0.113.4"),
+        ];
+        let entries = entries.iter().collect::<Vec<_>>();
+
+        let summary = summarize(&entries, 0, 8 * day);
+        let stats = &summary[&("home.example.com".to_string(), "A".to_string())];
+
+        assert_eq!(stats.changes, 3);
+        assert_eq!(stats.failures, 1);
+        // Held from the 2nd to the 7th day, longer than the day before the first change or the last day
+        assert_eq!(
+            stats.longest_held,
+            Some(("203.0.113.3".to_string(), 5 * day))
+        );
+
+        // The last value is held until the end of the window
+        let summary = summarize(&entries[..1], day, 10 * day);
+        assert_eq!(
+            summary[&("home.example.com".to_string(), "A".to_string())].longest_held,
+            Some(("203.0.113.2".to_string(), 9 * day))
+        );
+    }
+}
@@ -0,0 +1,264 @@
+//! Actions run after a dyndns record was changed at the provider, e.g., to update firewall allowlists
+
+use std::{fmt::Display, process::Command, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{dns_providers::helpers::client_builder, traits::domain_control::DnsRecordType};
+
+/// An `on_change` action of a dyndns job, either `command: ...` or `webhook: ...`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum OnChangeAction {
+    /// A shell command, run with `sh -c`, with the `OLD_IP`, `NEW_IP`, `RECORD` and `RECORD_TYPE` env vars
+    Command { command: String },
+    /// A URL to POST the change to, as JSON. Given up on after `timeout_secs`, so that a hanging endpoint
+    /// doesn't block the run.
+    Webhook {
+        webhook: String,
+        #[serde(default = "default_webhook_timeout_secs")]
+        timeout_secs: u64,
+    },
+}
+
+fn default_webhook_timeout_secs() -> u64 {
+    10
+}
+
+impl Display for OnChangeAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OnChangeAction::Command { command } => write!(f, "command `{}`", command),
+            OnChangeAction::Webhook { webhook, .. } => write!(f, "webhook {}", webhook),
+        }
+    }
+}
+
+/// A record whose address (or, for jobs with `value_from`, value) changed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AddressChange {
+    /// The fully qualified name of the record, e.g., `home.example.com`
+    pub record: String,
+    pub record_type: DnsRecordType,
+    /// The previous value of the record. None if the record was created.
+    pub old_ip: Option<String>,
+    pub new_ip: String,
+}
+
+/// Runs all actions, even if some fail, and returns the failures
+pub fn run_on_change_actions(actions: &[OnChangeAction], change: &AddressChange) -> Vec<String> {
+    actions
+        .iter()
+        .filter_map(|action| {
+            let result = match action {
+                OnChangeAction::Command { command } => run_command(command, change),
+                OnChangeAction::Webhook {
+                    webhook,
+                    timeout_secs,
+                } => call_webhook(webhook, Duration::from_secs(*timeout_secs), change),
+            };
+
+            match result {
+                Ok(()) => {
+                    println!("Ran on_change {} for {}", action, change.record);
+                    None
+                }
+                Err(e) => Some(format!(
+                    "on_change {} for {} failed: {}",
+                    action, change.record, e
+                )),
+            }
+        })
+        .collect()
+}
+
+fn run_command(command: &str, change: &AddressChange) -> Result<(), Box<dyn std::error::Error>> {
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env("OLD_IP", change.old_ip.as_deref().unwrap_or_default())
+        .env("NEW_IP", &change.new_ip)
+        .env("RECORD", &change.record)
+        .env("RECORD_TYPE", change.record_type.to_string())
+        .status()?;
+
+    if !status.success() {
+        return Err(format!("exited with {}", status).into());
+    }
+
+    Ok(())
+}
+
+fn call_webhook(
+    url: &str,
+    timeout: Duration,
+    change: &AddressChange,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Webhooks usually go to chat or internal services, not to the DNS provider, so its proxy is not used
+    let client = client_builder(None).timeout(timeout).build()?;
+
+    client
+        .post(url)
+        .json(change)
+        .send()
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| -> Box<dyn std::error::Error> {
+            if e.is_timeout() {
+                format!("timed out after {} seconds", timeout.as_secs()).into()
+            } else {
+                Box::new(e)
+            }
+        })?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    fn change() -> AddressChange {
+        AddressChange {
+            record: "home.example.com".to_string(),
+            record_type: DnsRecordType::A,
+            old_ip: Some("203.0.113.1".to_string()),
+            new_ip: "203.0.113.2".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_on_change_actions_config_parsing() {
+        let actions: Vec<OnChangeAction> = serde_yml::from_str(
+            r#"
+- command: "systemctl restart wg-quick@wg0"
+- webhook: "https://chat.example/hooks/dyndns"
+- webhook: "https://slow.example/hooks/dyndns"
+  timeout_secs: 30
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            actions,
+            vec![
+                OnChangeAction::Command {
+                    command: "systemctl restart wg-quick@wg0".to_string()
+                },
+                OnChangeAction::Webhook {
+                    webhook: "https://chat.example/hooks/dyndns".to_string(),
+                    timeout_secs: 10
+                },
+                OnChangeAction::Webhook {
+                    webhook: "https://slow.example/hooks/dyndns".to_string(),
+                    timeout_secs: 30
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_command_action() {
+        let output_path = std::env::temp_dir().join(format!(
+            "sam-dns-tools-on-change-{}.txt",
+            std::process::id()
+        ));
+
+        let actions = vec![
+            OnChangeAction::Command {
+                command: format!(
+                    "echo \"$RECORD $RECORD_TYPE $OLD_IP $NEW_IP\" > {}",
+                    output_path.display()
+                ),
+            },
+            OnChangeAction::Command {
+                command: "exit 3".to_string(),
+            },
+        ];
+
+        let failures = run_on_change_actions(&actions, &change());
+
+        assert_eq!(
+            std::fs::read_to_string(&output_path).unwrap(),
+            "home.example.com A 203.0.113.1 203.0.113.2\n"
+        );
+        assert_eq!(failures.len(), 1);
+        assert!(failures[0].contains("exit 3"));
+
+        std::fs::remove_file(output_path).unwrap();
+    }
+
+    #[test]
+    fn test_webhook_action() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+            let mut content_length = 0;
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                if let Some((name, value)) = line.split_once(':')
+                    && name.eq_ignore_ascii_case("content-length")
+                {
+                    content_length = value.trim().parse().unwrap();
+                }
+                line.clear();
+            }
+            let mut body = vec![0u8; content_length];
+            reader.read_exact(&mut body).unwrap();
+
+            write!(
+                stream,
+                "HTTP/1.1 204 No Content\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+            )
+            .unwrap();
+
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+        });
+
+        let failures = run_on_change_actions(
+            &[OnChangeAction::Webhook {
+                webhook: url,
+                timeout_secs: default_webhook_timeout_secs(),
+            }],
+            &change(),
+        );
+
+        assert!(failures.is_empty());
+        assert_eq!(
+            server.join().unwrap(),
+            serde_json::json!({
+                "record": "home.example.com",
+                "record_type": "A",
+                "old_ip": "203.0.113.1",
+                "new_ip": "203.0.113.2",
+            })
+        );
+    }
+
+    #[test]
+    fn test_hanging_webhook_times_out() {
+        // Accepts the connection, but never answers
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+
+        let failures = run_on_change_actions(
+            &[OnChangeAction::Webhook {
+                webhook: url,
+                timeout_secs: 1,
+            }],
+            &change(),
+        );
+
+        assert_eq!(failures.len(), 1);
+        assert!(failures[0].ends_with("timed out after 1 seconds"));
+        drop(listener);
+    }
+}
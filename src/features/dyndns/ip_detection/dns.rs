@@ -0,0 +1,409 @@
+//! Detection of the public address by asking DNS servers that answer with the address of the client,
+//! using a minimal built-in DNS client over UDP
+
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::{IpDetectionConfig, IpFamily, Voter};
+
+const QUERY_TIMEOUT: Duration = Duration::from_secs(3);
+
+const OPENDNS_NAME: &str = "myip.opendns.com";
+const GOOGLE_NAME: &str = "o-o.myaddr.l.google.com";
+
+const TYPE_A: u16 = 1;
+const TYPE_TXT: u16 = 16;
+const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+
+/// resolver1.opendns.com and ns1.google.com
+const IPV4_DNS_SERVERS: [(DnsIpServiceKind, &str); 2] = [
+    (DnsIpServiceKind::OpenDns, "208.67.222.222:53"),
+    (DnsIpServiceKind::Google, "216.239.32.10:53"),
+];
+
+/// resolver1.opendns.com and ns1.google.com
+const IPV6_DNS_SERVERS: [(DnsIpServiceKind, &str); 2] = [
+    (DnsIpServiceKind::OpenDns, "[2620:119:35::35]:53"),
+    (DnsIpServiceKind::Google, "[2001:4860:4802:32::a]:53"),
+];
+
+/// How the server tells the address of the client
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DnsIpServiceKind {
+    /// The A/AAAA record of `myip.opendns.com`
+    OpenDns,
+    /// The TXT record of `o-o.myaddr.l.google.com`
+    Google,
+}
+
+/// A DNS server that answers with the address of the client
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DnsIpService {
+    pub kind: DnsIpServiceKind,
+    /// The address of the server, with the port, e.g., `208.67.222.222:53` or `resolver1.opendns.com:53`
+    pub server: String,
+}
+
+/// The configured servers of the family, or the built-in ones if none are configured
+fn servers(family: IpFamily, config: &IpDetectionConfig) -> Vec<DnsIpService> {
+    let (configured, defaults) = match family {
+        IpFamily::V4 => (&config.ipv4_dns_servers, IPV4_DNS_SERVERS.as_slice()),
+        IpFamily::V6 => (&config.ipv6_dns_servers, IPV6_DNS_SERVERS.as_slice()),
+    };
+
+    if configured.is_empty() {
+        defaults
+            .iter()
+            .map(|(kind, server)| DnsIpService {
+                kind: *kind,
+                server: server.to_string(),
+            })
+            .collect()
+    } else {
+        configured.clone()
+    }
+}
+
+pub fn voters(family: IpFamily, config: &IpDetectionConfig) -> Vec<Voter> {
+    servers(family, config)
+        .into_iter()
+        .map(|service| {
+            Voter::new(format!("dns://{}", service.server), move || {
+                get_my_ip_address_from_dns(&service, family)
+            })
+        })
+        .collect()
+}
+
+pub fn get_my_ip_address_from_dns(
+    service: &DnsIpService,
+    family: IpFamily,
+) -> Result<IpAddr, Box<dyn std::error::Error>> {
+    let server = service
+        .server
+        .to_socket_addrs()?
+        .find(|a| IpFamily::of(&a.ip()) == family)
+        .ok_or_else(|| format!("DNS server {} has no {} address", service.server, family))?;
+
+    let (name, query_type) = match (service.kind, family) {
+        (DnsIpServiceKind::OpenDns, IpFamily::V4) => (OPENDNS_NAME, TYPE_A),
+        (DnsIpServiceKind::OpenDns, IpFamily::V6) => (OPENDNS_NAME, TYPE_AAAA),
+        (DnsIpServiceKind::Google, _) => (GOOGLE_NAME, TYPE_TXT),
+    };
+
+    let answers = query(server, name, query_type)?;
+
+    answers
+        .iter()
+        .filter_map(|answer| match answer {
+            Answer::Address(ip_address) => Some(*ip_address),
+            Answer::Txt(text) => text.trim().parse().ok(),
+        })
+        .find(|a| IpFamily::of(a) == family)
+        .ok_or_else(|| {
+            format!(
+                "DNS server {} returned no {} address for {}",
+                service.server, family, name
+            )
+            .into()
+        })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Answer {
+    Address(IpAddr),
+    Txt(String),
+}
+
+fn query(
+    server: SocketAddr,
+    name: &str,
+    query_type: u16,
+) -> Result<Vec<Answer>, Box<dyn std::error::Error>> {
+    let socket = UdpSocket::bind(SocketAddr::new(IpFamily::of(&server.ip()).unspecified(), 0))?;
+    socket.set_read_timeout(Some(QUERY_TIMEOUT))?;
+    socket.connect(server)?;
+
+    let id = rand::random::<u16>();
+    socket.send(&build_query(id, name, query_type)?)?;
+
+    let mut buffer = [0u8; 4096];
+    loop {
+        let size = socket.recv(&mut buffer)?;
+
+        // Responses to other (e.g., timed out) queries are ignored
+        if size >= 2 && u16::from_be_bytes([buffer[0], buffer[1]]) == id {
+            return Ok(parse_response(&buffer[..size])?);
+        }
+    }
+}
+
+fn build_query(id: u16, name: &str, query_type: u16) -> Result<Vec<u8>, String> {
+    let mut message = Vec::with_capacity(512);
+
+    message.extend_from_slice(&id.to_be_bytes());
+    // Recursion desired, so that recursive resolvers answer too
+    message.extend_from_slice(&0x0100u16.to_be_bytes());
+    // One question, no answers, authority or additional records
+    message.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(format!("Invalid DNS name: {}", name));
+        }
+        message.push(label.len() as u8);
+        message.extend_from_slice(label.as_bytes());
+    }
+    message.push(0);
+
+    message.extend_from_slice(&query_type.to_be_bytes());
+    message.extend_from_slice(&CLASS_IN.to_be_bytes());
+
+    Ok(message)
+}
+
+/// Returns the A, AAAA and TXT records in the answer section of the response
+fn parse_response(message: &[u8]) -> Result<Vec<Answer>, String> {
+    let read_u16 = |offset: usize| -> Result<u16, String> {
+        message
+            .get(offset..offset + 2)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]))
+            .ok_or_else(|| "Truncated DNS response".to_string())
+    };
+
+    let flags = read_u16(2)?;
+    if flags & 0x8000 == 0 {
+        return Err("The DNS message is not a response".to_string());
+    }
+    let response_code = flags & 0x000f;
+    if response_code != 0 {
+        return Err(format!(
+            "DNS server responded with error code {}",
+            response_code
+        ));
+    }
+
+    let question_count = read_u16(4)?;
+    let answer_count = read_u16(6)?;
+
+    let mut offset = 12;
+    for _ in 0..question_count {
+        offset = skip_name(message, offset)? + 4;
+    }
+
+    let mut answers = Vec::new();
+    for _ in 0..answer_count {
+        offset = skip_name(message, offset)?;
+        let record_type = read_u16(offset)?;
+        let data_length = read_u16(offset + 8)? as usize;
+        let data_start = offset + 10;
+        let data = message
+            .get(data_start..data_start + data_length)
+            .ok_or_else(|| "Truncated DNS response".to_string())?;
+        offset = data_start + data_length;
+
+        match record_type {
+            TYPE_A if data.len() == 4 => answers.push(Answer::Address(IpAddr::V4(Ipv4Addr::new(
+                data[0], data[1], data[2], data[3],
+            )))),
+            TYPE_AAAA if data.len() == 16 => {
+                let octets: [u8; 16] = data.try_into().expect("Length checked above");
+                answers.push(Answer::Address(IpAddr::V6(Ipv6Addr::from(octets))))
+            }
+            TYPE_TXT => answers.push(Answer::Txt(parse_txt_data(data)?)),
+            _ => {}
+        }
+    }
+
+    Ok(answers)
+}
+
+/// Returns the offset right after the (possibly compressed) name that starts at the offset
+fn skip_name(message: &[u8], mut offset: usize) -> Result<usize, String> {
+    loop {
+        let length = *message
+            .get(offset)
+            .ok_or_else(|| "Truncated DNS response".to_string())?;
+
+        match length {
+            0 => return Ok(offset + 1),
+            // A pointer to a name elsewhere ends the name
+            l if l & 0xc0 == 0xc0 => return Ok(offset + 2),
+            l => offset += 1 + l as usize,
+        }
+    }
+}
+
+/// A TXT record is a sequence of length-prefixed strings, which are joined
+fn parse_txt_data(data: &[u8]) -> Result<String, String> {
+    let mut result = String::new();
+    let mut offset = 0;
+
+    while offset < data.len() {
+        let length = data[offset] as usize;
+        let string = data
+            .get(offset + 1..offset + 1 + length)
+            .ok_or_else(|| "Truncated TXT record".to_string())?;
+        result.push_str(&String::from_utf8_lossy(string));
+        offset += 1 + length;
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        super::{IpSource, Quorum, detect_ip_address},
+        *,
+    };
+
+    /// A stand-in DNS server that answers one query with the provided records
+    /// (type and data), pointing to the question's name with compression
+    fn spawn_stub_dns_server(
+        records: Vec<(u16, Vec<u8>)>,
+    ) -> (String, std::thread::JoinHandle<(String, u16)>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = socket.local_addr().unwrap().to_string();
+
+        let handle = std::thread::spawn(move || {
+            let mut buffer = [0u8; 512];
+            let (size, peer) = socket.recv_from(&mut buffer).unwrap();
+            let query = &buffer[..size];
+
+            let question_end = skip_name(query, 12).unwrap();
+            let mut labels = Vec::new();
+            let mut i = 12;
+            while query[i] != 0 {
+                let length = query[i] as usize;
+                labels.push(String::from_utf8_lossy(&query[i + 1..i + 1 + length]).to_string());
+                i += 1 + length;
+            }
+            let name = labels.join(".");
+            let query_type = u16::from_be_bytes([query[question_end], query[question_end + 1]]);
+
+            let mut response = query[..question_end + 4].to_vec();
+            response[2] |= 0x80;
+            response[6..8].copy_from_slice(&(records.len() as u16).to_be_bytes());
+            for (record_type, data) in &records {
+                response.extend_from_slice(&[0xc0, 12]);
+                response.extend_from_slice(&record_type.to_be_bytes());
+                response.extend_from_slice(&CLASS_IN.to_be_bytes());
+                response.extend_from_slice(&60u32.to_be_bytes());
+                response.extend_from_slice(&(data.len() as u16).to_be_bytes());
+                response.extend_from_slice(data);
+            }
+            socket.send_to(&response, peer).unwrap();
+
+            (name, query_type)
+        });
+
+        (address, handle)
+    }
+
+    fn txt(value: &str) -> Vec<u8> {
+        let mut data = vec![value.len() as u8];
+        data.extend_from_slice(value.as_bytes());
+        data
+    }
+
+    #[test]
+    fn test_opendns_detection() {
+        let (server, handle) = spawn_stub_dns_server(vec![(TYPE_A, vec![203, 0, 113, 9])]);
+
+        let service = DnsIpService {
+            kind: DnsIpServiceKind::OpenDns,
+            server,
+        };
+        let ip_address = get_my_ip_address_from_dns(&service, IpFamily::V4).unwrap();
+
+        assert_eq!(ip_address, "203.0.113.9".parse::<IpAddr>().unwrap());
+        assert_eq!(handle.join().unwrap(), (OPENDNS_NAME.to_string(), TYPE_A));
+    }
+
+    #[test]
+    fn test_google_detection() {
+        // Google also returns the client subnet in another TXT record, which is not an address
+        let (server, handle) = spawn_stub_dns_server(vec![
+            (TYPE_TXT, txt("edns0-client-subnet 198.51.100.0/24")),
+            (TYPE_TXT, txt("198.51.100.20")),
+        ]);
+
+        let service = DnsIpService {
+            kind: DnsIpServiceKind::Google,
+            server,
+        };
+        let ip_address = get_my_ip_address_from_dns(&service, IpFamily::V4).unwrap();
+
+        assert_eq!(ip_address, "198.51.100.20".parse::<IpAddr>().unwrap());
+        assert_eq!(handle.join().unwrap(), (GOOGLE_NAME.to_string(), TYPE_TXT));
+    }
+
+    #[test]
+    fn test_parse_response_errors() {
+        let mut response = build_query(1, OPENDNS_NAME, TYPE_A).unwrap();
+        assert!(parse_response(&response).is_err());
+
+        // NXDOMAIN
+        response[2] |= 0x80;
+        response[3] |= 0x03;
+        assert!(parse_response(&response).is_err());
+
+        response[3] &= 0xf0;
+        assert_eq!(parse_response(&response), Ok(Vec::new()));
+
+        assert!(parse_response(&response[..20]).is_err());
+    }
+
+    #[test]
+    fn test_parse_txt_data() {
+        let mut data = txt("2001:db8:");
+        data.extend(txt(":1"));
+        assert_eq!(parse_txt_data(&data), Ok("2001:db8::1".to_string()));
+        assert!(parse_txt_data(&[5, b'a']).is_err());
+    }
+
+    #[test]
+    fn test_dns_servers_vote_in_quorum() {
+        let (opendns_server, opendns_handle) =
+            spawn_stub_dns_server(vec![(TYPE_A, vec![203, 0, 113, 9])]);
+        let (google_server, google_handle) =
+            spawn_stub_dns_server(vec![(TYPE_TXT, txt("203.0.113.9"))]);
+
+        let config = IpDetectionConfig {
+            ipv4_dns_servers: vec![
+                DnsIpService {
+                    kind: DnsIpServiceKind::OpenDns,
+                    server: opendns_server,
+                },
+                DnsIpService {
+                    kind: DnsIpServiceKind::Google,
+                    server: google_server,
+                },
+            ],
+            quorum: Some(Quorum {
+                queries: 2,
+                agree: 2,
+            }),
+            ..Default::default()
+        };
+
+        let ip_address = detect_ip_address(
+            &|_| panic!("No HTTP client is needed for DNS detection"),
+            &[IpSource::Dns],
+            IpFamily::V4,
+            &config,
+        )
+        .unwrap();
+
+        assert_eq!(ip_address, "203.0.113.9".parse::<IpAddr>().unwrap());
+        opendns_handle.join().unwrap();
+        google_handle.join().unwrap();
+    }
+}
@@ -0,0 +1,784 @@
+//! Detection of the public address by asking the home router (the default gateway) for its WAN address,
+//! using PCP (RFC 6887), NAT-PMP (RFC 6886) or UPnP-IGD (SSDP discovery and the SOAP action `GetExternalIPAddress`)
+
+use std::{
+    ffi::CString,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6, ToSocketAddrs, UdpSocket},
+    time::Duration,
+};
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use super::{IpDetectionConfig, IpFamily, Voter, interface};
+
+/// The port of both PCP and NAT-PMP servers
+const NAT_PMP_PORT: u16 = 5351;
+/// NAT-PMP retransmits starting at 250ms, doubling every time (RFC 6886, section 3.1). The same is used for PCP.
+const NAT_PMP_INITIAL_TIMEOUT: Duration = Duration::from_millis(250);
+const NAT_PMP_ATTEMPTS: usize = 4;
+const NAT_PMP_EXTERNAL_ADDRESS_RESPONSE: u8 = 128;
+
+const PCP_VERSION: u8 = 2;
+const PCP_MAP_OPCODE: u8 = 1;
+const PCP_RESPONSE_BIT: u8 = 0x80;
+const PCP_MAP_MESSAGE_SIZE: usize = 60;
+const PCP_UDP_PROTOCOL: u8 = 17;
+/// The probe's mapping is deleted right after, this only bounds it if the deletion is lost
+const PCP_PROBE_LIFETIME: u32 = 60;
+
+const SSDP_MULTICAST_ADDRESS: &str = "239.255.255.250:1900";
+const SSDP_TIMEOUT: Duration = Duration::from_secs(2);
+const UPNP_HTTP_TIMEOUT: Duration = Duration::from_secs(5);
+const IGD_DEVICE_TYPE: &str = "urn:schemas-upnp-org:device:InternetGatewayDevice:1";
+/// The services that can tell the external address. Their versions are matched by prefix.
+const WAN_CONNECTION_SERVICES: [&str; 2] = [
+    "urn:schemas-upnp-org:service:WANIPConnection:",
+    "urn:schemas-upnp-org:service:WANPPPConnection:",
+];
+
+/// The `gateway` part of the `ip_detection` config section
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct GatewayConfig {
+    /// The PCP and NAT-PMP server for IPv4, e.g., `192.168.1.1:5351`. If not provided, the default gateway is used.
+    #[serde(default)]
+    pub nat_pmp_server: Option<String>,
+    /// The PCP server for IPv6, e.g., `[2001:db8::1]:5351`. If not provided, the default IPv6 router is used.
+    #[serde(default)]
+    pub ipv6_pcp_server: Option<String>,
+    /// Where SSDP discovery requests are sent. If not provided, the standard multicast address is used.
+    #[serde(default)]
+    pub ssdp_address: Option<String>,
+}
+
+/// No answer at all, as opposed to an error answer
+#[derive(thiserror::Error, Debug)]
+#[error("No response from {protocol} server {server} after {attempts} attempts")]
+struct NoResponse {
+    protocol: &'static str,
+    server: SocketAddr,
+    attempts: usize,
+}
+
+/// The protocols all ask the same router, so they're a single voter, trying them in turn
+pub fn voters(family: IpFamily, config: &IpDetectionConfig) -> Vec<Voter> {
+    let config = config.gateway.clone();
+
+    vec![Voter::new("gateway".to_string(), move || {
+        detect(&config, family)
+    })]
+}
+
+/// Asks the gateway over PCP, then NAT-PMP and UPnP-IGD, which only know the IPv4 WAN address
+fn detect(config: &GatewayConfig, family: IpFamily) -> Result<IpAddr, Box<dyn std::error::Error>> {
+    let mut errors = Vec::new();
+
+    match port_control_server(config, family) {
+        Ok((server, local_address)) => {
+            match get_external_address_from_pcp(server, local_address) {
+                Ok(ip_address) => return Ok(ip_address),
+                Err(e) => {
+                    // A NAT-PMP server answers PCP requests with an unsupported version error,
+                    // so without any answer, there's no NAT-PMP server either
+                    let answered = !e.is::<NoResponse>();
+                    errors.push(format!("PCP: {}", e));
+
+                    if family == IpFamily::V4 && answered {
+                        match get_external_address_from_nat_pmp(server) {
+                            Ok(ip_address) => return Ok(IpAddr::V4(ip_address)),
+                            Err(e) => errors.push(format!("NAT-PMP: {}", e)),
+                        }
+                    }
+                }
+            }
+        }
+        Err(e) => errors.push(format!("PCP: {}", e)),
+    }
+
+    if family == IpFamily::V4 {
+        let ssdp_address = config
+            .ssdp_address
+            .as_deref()
+            .unwrap_or(SSDP_MULTICAST_ADDRESS);
+
+        match get_external_address_from_upnp(ssdp_address) {
+            Ok(ip_address) => return Ok(IpAddr::V4(ip_address)),
+            Err(e) => errors.push(format!("UPnP-IGD: {}", e)),
+        }
+    }
+
+    Err(format!(
+        "The gateway could not tell its {} WAN address. {}",
+        family,
+        errors.join("; ")
+    )
+    .into())
+}
+
+/// The PCP server of the family, and the local address to send from
+fn port_control_server(
+    config: &GatewayConfig,
+    family: IpFamily,
+) -> Result<(SocketAddr, IpAddr), Box<dyn std::error::Error>> {
+    let configured = match family {
+        IpFamily::V4 => &config.nat_pmp_server,
+        IpFamily::V6 => &config.ipv6_pcp_server,
+    };
+
+    if let Some(server) = configured {
+        let server = server
+            .to_socket_addrs()?
+            .find(|a| IpFamily::of(&a.ip()) == family)
+            .ok_or_else(|| format!("PCP server {} has no {} address", server, family))?;
+
+        return Ok((server, family.unspecified()));
+    }
+
+    match family {
+        IpFamily::V4 => Ok((
+            SocketAddr::new(IpAddr::V4(default_gateway()?), NAT_PMP_PORT),
+            family.unspecified(),
+        )),
+        IpFamily::V6 => {
+            let route_table = std::fs::read_to_string("/proc/net/ipv6_route")?;
+            let (gateway, interface_name) =
+                parse_default_ipv6_gateway(&route_table).ok_or("No default IPv6 router found")?;
+
+            // The router is usually reached by its link-local address, which needs the interface
+            let name = CString::new(interface_name.clone())?;
+            // SAFETY: name is a valid null-terminated string, which outlives the call
+            let scope_id = unsafe { libc::if_nametoindex(name.as_ptr()) };
+            let server = SocketAddr::V6(SocketAddrV6::new(gateway, NAT_PMP_PORT, 0, scope_id));
+
+            // Sending from the global address, as the mapping is for the address it's sent from,
+            // and a link-local source would be chosen otherwise
+            let local_address =
+                interface::detect(Some(&interface_name), family).unwrap_or(family.unspecified());
+
+            Ok((server, local_address))
+        }
+    }
+}
+
+/// The default gateway from the kernel's routing table
+fn default_gateway() -> Result<Ipv4Addr, Box<dyn std::error::Error>> {
+    let route_table = std::fs::read_to_string("/proc/net/route")?;
+
+    parse_default_gateway(&route_table).ok_or_else(|| "No default IPv4 gateway found".into())
+}
+
+/// Parses /proc/net/route, whose addresses are hexadecimal in host (little endian) byte order, e.g.:
+///
+/// Iface Destination Gateway Flags RefCnt Use Metric Mask MTU Window IRTT
+/// eth0 00000000 0101A8C0 0003 0 0 100 00000000 0 0 0
+fn parse_default_gateway(route_table: &str) -> Option<Ipv4Addr> {
+    route_table.lines().skip(1).find_map(|line| {
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        let (destination, gateway, mask) = (fields.get(1)?, fields.get(2)?, fields.get(7)?);
+
+        if *destination != "00000000" || *mask != "00000000" {
+            return None;
+        }
+
+        let gateway = u32::from_str_radix(gateway, 16).ok()?;
+        (gateway != 0).then(|| Ipv4Addr::from(gateway.to_le_bytes()))
+    })
+}
+
+/// Parses /proc/net/ipv6_route, whose addresses are hexadecimal in network byte order, e.g.:
+///
+/// 00000000000000000000000000000000 00 00000000000000000000000000000000 00 fe800000000000000000000000000001 00000400 00000001 00000000 00000003 eth0
+fn parse_default_ipv6_gateway(route_table: &str) -> Option<(Ipv6Addr, String)> {
+    route_table.lines().find_map(|line| {
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        let (destination, prefix_len, next_hop, interface) = (
+            fields.first()?,
+            fields.get(1)?,
+            fields.get(4)?,
+            fields.get(9)?,
+        );
+
+        if u128::from_str_radix(destination, 16).ok()? != 0 || *prefix_len != "00" {
+            return None;
+        }
+
+        let next_hop = u128::from_str_radix(next_hop, 16).ok()?;
+        (next_hop != 0).then(|| (Ipv6Addr::from(next_hop), interface.to_string()))
+    })
+}
+
+/// The interface of the default IPv4 route in /proc/net/route, i.e., the one towards the internet
+pub(super) fn parse_default_route_interface(route_table: &str) -> Option<String> {
+    route_table.lines().skip(1).find_map(|line| {
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        let (interface, destination, mask) = (fields.first()?, fields.get(1)?, fields.get(7)?);
+
+        (*destination == "00000000" && *mask == "00000000").then(|| interface.to_string())
+    })
+}
+
+/// Sends the request until a response arrives, waiting twice as long every time. Returns the response's size.
+fn request_with_retries(
+    socket: &UdpSocket,
+    request: &[u8],
+    buffer: &mut [u8],
+    protocol: &'static str,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let mut timeout = NAT_PMP_INITIAL_TIMEOUT;
+    for _ in 0..NAT_PMP_ATTEMPTS {
+        socket.set_read_timeout(Some(timeout))?;
+        socket.send(request)?;
+
+        match socket.recv(buffer) {
+            Ok(size) => return Ok(size),
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                ) =>
+            {
+                timeout *= 2;
+            }
+            Err(e) => return Err(Box::new(e)),
+        }
+    }
+
+    Err(Box::new(NoResponse {
+        protocol,
+        server: socket.peer_addr()?,
+        attempts: NAT_PMP_ATTEMPTS,
+    }))
+}
+
+fn get_external_address_from_nat_pmp(
+    server: SocketAddr,
+) -> Result<Ipv4Addr, Box<dyn std::error::Error>> {
+    let socket = UdpSocket::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0))?;
+    socket.connect(server)?;
+
+    // Version 0, opcode 0: external address request
+    let request = [0u8, 0u8];
+
+    let mut buffer = [0u8; 16];
+    let size = request_with_retries(&socket, &request, &mut buffer, "NAT-PMP")?;
+
+    Ok(parse_nat_pmp_response(&buffer[..size])?)
+}
+
+/// Asks for a short mapping of the socket's own UDP port (PCP has no request for the external address alone),
+/// takes the external address from the response, and deletes the mapping
+fn get_external_address_from_pcp(
+    server: SocketAddr,
+    local_address: IpAddr,
+) -> Result<IpAddr, Box<dyn std::error::Error>> {
+    let socket = UdpSocket::bind(SocketAddr::new(local_address, 0))?;
+    socket.connect(server)?;
+
+    // The server checks that the client address in the request is the source address of the packet
+    let client_address = socket.local_addr()?;
+    let nonce: [u8; 12] = rand::rng().random();
+
+    let request = pcp_map_request(
+        client_address.ip(),
+        client_address.port(),
+        &nonce,
+        PCP_PROBE_LIFETIME,
+    );
+    let mut buffer = [0u8; 1100];
+    let size = request_with_retries(&socket, &request, &mut buffer, "PCP")?;
+    let external_address = parse_pcp_map_response(&buffer[..size], &nonce)?;
+
+    // A lifetime of 0 deletes the mapping. If this is lost, the mapping expires soon anyway.
+    let request = pcp_map_request(client_address.ip(), client_address.port(), &nonce, 0);
+    if let Err(e) = request_with_retries(&socket, &request, &mut buffer, "PCP") {
+        eprintln!(
+            "Could not delete the PCP mapping used to detect the address: {}",
+            e
+        );
+    }
+
+    Ok(external_address)
+}
+
+/// PCP carries all addresses as IPv6, with IPv4 addresses mapped (::ffff:a.b.c.d)
+fn pcp_address(ip_address: IpAddr) -> Ipv6Addr {
+    match ip_address {
+        IpAddr::V4(a) => a.to_ipv6_mapped(),
+        IpAddr::V6(a) => a,
+    }
+}
+
+/// A MAP request (RFC 6887, sections 7.1 and 11.1) for the internal UDP port, without a suggested external address
+fn pcp_map_request(
+    client_address: IpAddr,
+    internal_port: u16,
+    nonce: &[u8; 12],
+    lifetime: u32,
+) -> [u8; PCP_MAP_MESSAGE_SIZE] {
+    let suggested_external_address = match client_address {
+        IpAddr::V4(_) => Ipv4Addr::UNSPECIFIED.to_ipv6_mapped(),
+        IpAddr::V6(_) => Ipv6Addr::UNSPECIFIED,
+    };
+
+    let mut request = [0u8; PCP_MAP_MESSAGE_SIZE];
+    request[0] = PCP_VERSION;
+    request[1] = PCP_MAP_OPCODE;
+    request[4..8].copy_from_slice(&lifetime.to_be_bytes());
+    request[8..24].copy_from_slice(&pcp_address(client_address).octets());
+    request[24..36].copy_from_slice(nonce);
+    request[36] = PCP_UDP_PROTOCOL;
+    request[40..42].copy_from_slice(&internal_port.to_be_bytes());
+    request[44..60].copy_from_slice(&suggested_external_address.octets());
+
+    request
+}
+
+fn parse_pcp_map_response(message: &[u8], nonce: &[u8; 12]) -> Result<IpAddr, String> {
+    // A NAT-PMP server answers with its own version 0
+    if message.first() == Some(&0) {
+        return Err("The gateway only supports NAT-PMP".to_string());
+    }
+
+    if message.len() < PCP_MAP_MESSAGE_SIZE {
+        return Err("Truncated PCP response".to_string());
+    }
+
+    if message[0] != PCP_VERSION || message[1] != PCP_RESPONSE_BIT | PCP_MAP_OPCODE {
+        return Err(format!(
+            "Unexpected PCP response with version {} and opcode {}",
+            message[0], message[1]
+        ));
+    }
+
+    if message[3] != 0 {
+        return Err(format!(
+            "PCP server responded with result code {}",
+            message[3]
+        ));
+    }
+
+    if message[24..36] != nonce[..] {
+        return Err("The PCP response is for another request".to_string());
+    }
+
+    let external_address = Ipv6Addr::from(<[u8; 16]>::try_from(&message[44..60]).unwrap());
+
+    Ok(match external_address.to_ipv4_mapped() {
+        Some(a) => IpAddr::V4(a),
+        None => IpAddr::V6(external_address),
+    })
+}
+
+fn parse_nat_pmp_response(message: &[u8]) -> Result<Ipv4Addr, String> {
+    if message.len() < 12 {
+        return Err("Truncated NAT-PMP response".to_string());
+    }
+
+    if message[0] != 0 || message[1] != NAT_PMP_EXTERNAL_ADDRESS_RESPONSE {
+        return Err(format!(
+            "Unexpected NAT-PMP response with version {} and opcode {}",
+            message[0], message[1]
+        ));
+    }
+
+    let result_code = u16::from_be_bytes([message[2], message[3]]);
+    if result_code != 0 {
+        return Err(format!(
+            "NAT-PMP server responded with result code {}",
+            result_code
+        ));
+    }
+
+    Ok(Ipv4Addr::new(
+        message[8],
+        message[9],
+        message[10],
+        message[11],
+    ))
+}
+
+fn get_external_address_from_upnp(
+    ssdp_address: &str,
+) -> Result<Ipv4Addr, Box<dyn std::error::Error>> {
+    let location = discover_igd(ssdp_address)?;
+
+    // The gateway is on the local network, so no proxy is used
+    let client = reqwest::blocking::ClientBuilder::new()
+        .no_proxy()
+        .timeout(UPNP_HTTP_TIMEOUT)
+        .build()?;
+
+    let description = client.get(&location).send()?.error_for_status()?.text()?;
+
+    let (service_type, control_url) = find_wan_connection_service(&description)
+        .ok_or("The gateway has no WAN connection service")?;
+    let base_url = xml_element_text(&description, "URLBase").unwrap_or(&location);
+    let control_url = reqwest::Url::parse(base_url.trim())?.join(control_url.trim())?;
+
+    let body = format!(
+        concat!(
+            r#"<?xml version="1.0"?>"#,
+            r#"<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/">"#,
+            r#"<s:Body><u:GetExternalIPAddress xmlns:u="{}"></u:GetExternalIPAddress></s:Body>"#,
+            r#"</s:Envelope>"#
+        ),
+        service_type
+    );
+
+    let response = client
+        .post(control_url)
+        .header("Content-Type", r#"text/xml; charset="utf-8""#)
+        .header(
+            "SOAPAction",
+            format!("\"{}#GetExternalIPAddress\"", service_type),
+        )
+        .body(body)
+        .send()?
+        .error_for_status()?
+        .text()?;
+
+    let ip_address = xml_element_text(&response, "NewExternalIPAddress")
+        .ok_or("No external address in the response of the gateway")?;
+
+    Ok(ip_address.trim().parse()?)
+}
+
+/// Sends an SSDP search for gateways, and returns the location of the description of the first one that answers
+fn discover_igd(ssdp_address: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let socket = UdpSocket::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0))?;
+    socket.set_read_timeout(Some(SSDP_TIMEOUT))?;
+
+    let request = format!(
+        "M-SEARCH * HTTP/1.1\r\nHOST: {}\r\nMAN: \"ssdp:discover\"\r\nMX: 2\r\nST: {}\r\n\r\n",
+        SSDP_MULTICAST_ADDRESS, IGD_DEVICE_TYPE
+    );
+    socket.send_to(request.as_bytes(), ssdp_address)?;
+
+    let mut buffer = [0u8; 2048];
+    loop {
+        let size = match socket.recv(&mut buffer) {
+            Ok(size) => size,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                ) =>
+            {
+                return Err("No UPnP gateway answered the SSDP search".into());
+            }
+            Err(e) => return Err(Box::new(e)),
+        };
+
+        if let Some(location) = parse_ssdp_location(&String::from_utf8_lossy(&buffer[..size])) {
+            return Ok(location);
+        }
+    }
+}
+
+fn parse_ssdp_location(response: &str) -> Option<String> {
+    response.lines().find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        name.trim()
+            .eq_ignore_ascii_case("location")
+            .then(|| value.trim().to_string())
+    })
+}
+
+/// Returns the type and the control URL of the first WAN connection service in the device description
+fn find_wan_connection_service(description: &str) -> Option<(&str, &str)> {
+    description.split("<service>").skip(1).find_map(|service| {
+        let service_type = xml_element_text(service, "serviceType")?.trim();
+        let control_url = xml_element_text(service, "controlURL")?;
+
+        WAN_CONNECTION_SERVICES
+            .iter()
+            .any(|prefix| service_type.starts_with(prefix))
+            .then_some((service_type, control_url))
+    })
+}
+
+/// The text of the first element with the tag, ignoring namespace prefixes and attributes.
+/// Good enough for the simple documents of UPnP devices.
+fn xml_element_text<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+    let mut rest = xml;
+
+    loop {
+        let start = rest.find('<')?;
+        rest = &rest[start + 1..];
+        let end = rest.find('>')?;
+        let name = rest[..end].split_whitespace().next().unwrap_or_default();
+        let local_name = name.rsplit(':').next().unwrap_or(name);
+        rest = &rest[end + 1..];
+
+        if local_name == tag && !name.starts_with('/') && !rest.is_empty() {
+            let text_end = rest.find("</")?;
+            return Some(&rest[..text_end]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_parse_default_gateway() {
+        let route_table = "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT\n\
+            eth0\t0001A8C0\t00000000\t0001\t0\t0\t100\t00FFFFFF\t0\t0\t0\n\
+            eth0\t00000000\t0101A8C0\t0003\t0\t0\t100\t00000000\t0\t0\t0\n";
+
+        assert_eq!(
+            parse_default_gateway(route_table),
+            Some(Ipv4Addr::new(192, 168, 1, 1))
+        );
+        assert_eq!(parse_default_gateway("Iface\tDestination\n"), None);
+        assert_eq!(
+            parse_default_route_interface(route_table),
+            Some("eth0".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_default_ipv6_gateway() {
+        let route_table = "20010db8000000000000000000000000 40 00000000000000000000000000000000 00 00000000000000000000000000000000 00000100 00000001 00000000 00000001 eth0\n\
+            00000000000000000000000000000000 00 00000000000000000000000000000000 00 fe800000000000000000000000000001 00000400 00000001 00000000 00000003 eth0\n";
+
+        assert_eq!(
+            parse_default_ipv6_gateway(route_table),
+            Some(("fe80::1".parse().unwrap(), "eth0".to_string()))
+        );
+        assert_eq!(parse_default_ipv6_gateway(""), None);
+    }
+
+    #[test]
+    fn test_pcp_detection() {
+        // A stand-in PCP server, which maps the client to 198.51.100.9 and then deletes the mapping
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server = socket.local_addr().unwrap();
+
+        let handle = std::thread::spawn(move || {
+            let mut lifetimes = Vec::new();
+            for _ in 0..2 {
+                let mut buffer = [0u8; 1100];
+                let (size, peer) = socket.recv_from(&mut buffer).unwrap();
+                assert_eq!(size, PCP_MAP_MESSAGE_SIZE);
+                assert_eq!(&buffer[..2], &[PCP_VERSION, PCP_MAP_OPCODE]);
+                assert_eq!(
+                    &buffer[8..24],
+                    &Ipv4Addr::LOCALHOST.to_ipv6_mapped().octets()
+                );
+                assert_eq!(u16::from_be_bytes([buffer[40], buffer[41]]), peer.port());
+                lifetimes.push(u32::from_be_bytes(buffer[4..8].try_into().unwrap()));
+
+                let mut response = buffer[..PCP_MAP_MESSAGE_SIZE].to_vec();
+                response[1] = PCP_RESPONSE_BIT | PCP_MAP_OPCODE;
+                response[8..24].fill(0);
+                response[44..60]
+                    .copy_from_slice(&Ipv4Addr::new(198, 51, 100, 9).to_ipv6_mapped().octets());
+                socket.send_to(&response, peer).unwrap();
+            }
+            lifetimes
+        });
+
+        assert_eq!(
+            get_external_address_from_pcp(server, IpAddr::V4(Ipv4Addr::LOCALHOST)).unwrap(),
+            IpAddr::V4(Ipv4Addr::new(198, 51, 100, 9))
+        );
+        assert_eq!(handle.join().unwrap(), vec![PCP_PROBE_LIFETIME, 0]);
+
+        // Result code 2: NOT_AUTHORIZED
+        let nonce = [7u8; 12];
+        let mut response = pcp_map_request(IpAddr::V4(Ipv4Addr::LOCALHOST), 1234, &nonce, 0);
+        response[1] = PCP_RESPONSE_BIT | PCP_MAP_OPCODE;
+        response[3] = 2;
+        assert!(parse_pcp_map_response(&response, &nonce).is_err());
+        response[3] = 0;
+        assert!(parse_pcp_map_response(&response, &[8u8; 12]).is_err());
+    }
+
+    #[test]
+    fn test_gateway_falls_back_to_nat_pmp() {
+        // A stand-in NAT-PMP server, which doesn't know PCP
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server = socket.local_addr().unwrap();
+
+        let handle = std::thread::spawn(move || {
+            let mut buffer = [0u8; 1100];
+            let (_, peer) = socket.recv_from(&mut buffer).unwrap();
+            assert_eq!(buffer[0], PCP_VERSION);
+            // Result code 1: unsupported version
+            socket.send_to(&[0, 128, 0, 1], peer).unwrap();
+
+            let (size, peer) = socket.recv_from(&mut buffer).unwrap();
+            assert_eq!(&buffer[..size], &[0, 0]);
+            let mut response = vec![0, NAT_PMP_EXTERNAL_ADDRESS_RESPONSE, 0, 0];
+            response.extend_from_slice(&1234u32.to_be_bytes());
+            response.extend_from_slice(&[198, 51, 100, 45]);
+            socket.send_to(&response, peer).unwrap();
+        });
+
+        let config = IpDetectionConfig {
+            gateway: GatewayConfig {
+                nat_pmp_server: Some(server.to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let voters = voters(IpFamily::V4, &config);
+
+        assert_eq!(voters.len(), 1);
+        assert_eq!(
+            voters[0].query().unwrap(),
+            IpAddr::V4(Ipv4Addr::new(198, 51, 100, 45))
+        );
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_nat_pmp_detection() {
+        // A stand-in NAT-PMP server
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server = socket.local_addr().unwrap();
+
+        let handle = std::thread::spawn(move || {
+            let mut buffer = [0u8; 16];
+            let (size, peer) = socket.recv_from(&mut buffer).unwrap();
+            assert_eq!(&buffer[..size], &[0, 0]);
+
+            let mut response = vec![0, NAT_PMP_EXTERNAL_ADDRESS_RESPONSE, 0, 0];
+            response.extend_from_slice(&1234u32.to_be_bytes());
+            response.extend_from_slice(&[198, 51, 100, 44]);
+            socket.send_to(&response, peer).unwrap();
+        });
+
+        assert_eq!(
+            get_external_address_from_nat_pmp(server).unwrap(),
+            Ipv4Addr::new(198, 51, 100, 44)
+        );
+        handle.join().unwrap();
+
+        // Result code 3: network failure, e.g., the WAN link is down
+        assert!(parse_nat_pmp_response(&[0, 128, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0]).is_err());
+    }
+
+    /// Reads an HTTP request and returns its request line and body
+    fn read_http_request(stream: &std::net::TcpStream) -> (String, String) {
+        let mut reader = BufReader::new(stream);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line).unwrap();
+
+        let mut content_length = 0;
+        let mut header_line = String::new();
+        while reader.read_line(&mut header_line).unwrap() > 2 {
+            if let Some((name, value)) = header_line.split_once(':')
+                && name.eq_ignore_ascii_case("content-length")
+            {
+                content_length = value.trim().parse().unwrap();
+            }
+            header_line.clear();
+        }
+
+        let mut body = vec![0u8; content_length];
+        reader.read_exact(&mut body).unwrap();
+
+        (request_line, String::from_utf8(body).unwrap())
+    }
+
+    fn write_http_response(stream: &mut std::net::TcpStream, body: &str) {
+        write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Type: text/xml\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_upnp_detection() {
+        // A stand-in gateway: an SSDP responder, and an HTTP server for the description and the SOAP control URL
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let http_address = listener.local_addr().unwrap();
+
+        let ssdp_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let ssdp_address = ssdp_socket.local_addr().unwrap().to_string();
+
+        let ssdp_handle = std::thread::spawn(move || {
+            let mut buffer = [0u8; 1024];
+            let (size, peer) = ssdp_socket.recv_from(&mut buffer).unwrap();
+            let request = String::from_utf8_lossy(&buffer[..size]).to_string();
+            assert!(request.starts_with("M-SEARCH * HTTP/1.1\r\n"));
+            assert!(request.contains(IGD_DEVICE_TYPE));
+
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nCACHE-CONTROL: max-age=120\r\nST: {}\r\nLocation: http://{}/rootDesc.xml\r\n\r\n",
+                IGD_DEVICE_TYPE, http_address
+            );
+            ssdp_socket.send_to(response.as_bytes(), peer).unwrap();
+        });
+
+        let http_handle = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let (request_line, _) = read_http_request(&stream);
+            assert!(request_line.starts_with("GET /rootDesc.xml "));
+            write_http_response(
+                &mut stream,
+                r#"<?xml version="1.0"?>
+<root xmlns="urn:schemas-upnp-org:device-1-0">
+  <device>
+    <deviceType>urn:schemas-upnp-org:device:InternetGatewayDevice:1</deviceType>
+    <serviceList>
+      <service>
+        <serviceType>urn:schemas-upnp-org:service:Layer3Forwarding:1</serviceType>
+        <controlURL>/ctl/L3F</controlURL>
+      </service>
+      <service>
+        <serviceType>urn:schemas-upnp-org:service:WANIPConnection:2</serviceType>
+        <controlURL>/ctl/IPConn</controlURL>
+      </service>
+    </serviceList>
+  </device>
+</root>"#,
+            );
+
+            let (mut stream, _) = listener.accept().unwrap();
+            let (request_line, body) = read_http_request(&stream);
+            assert!(request_line.starts_with("POST /ctl/IPConn "));
+            assert!(body.contains(
+                r#"<u:GetExternalIPAddress xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:2">"#
+            ));
+            write_http_response(
+                &mut stream,
+                r#"<?xml version="1.0"?>
+<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/">
+  <s:Body>
+    <u:GetExternalIPAddressResponse xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:2">
+      <NewExternalIPAddress>203.0.113.77</NewExternalIPAddress>
+    </u:GetExternalIPAddressResponse>
+  </s:Body>
+</s:Envelope>"#,
+            );
+        });
+
+        assert_eq!(
+            get_external_address_from_upnp(&ssdp_address).unwrap(),
+            Ipv4Addr::new(203, 0, 113, 77)
+        );
+        ssdp_handle.join().unwrap();
+        http_handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_xml_element_text() {
+        let xml = r#"<a><b attr="1">text</b><ns:c>other</ns:c></a>"#;
+
+        assert_eq!(xml_element_text(xml, "b"), Some("text"));
+        assert_eq!(xml_element_text(xml, "c"), Some("other"));
+        assert_eq!(xml_element_text(xml, "d"), None);
+    }
+}
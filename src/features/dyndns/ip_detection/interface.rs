@@ -0,0 +1,353 @@
+//! Detection of the public address from the addresses of the local network interfaces
+
+use std::{
+    ffi::CStr,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+};
+
+use super::IpFamily;
+
+/// IPv6 address flags, as in linux/if_addr.h
+const IFA_F_TEMPORARY: u32 = 0x01;
+const IFA_F_DADFAILED: u32 = 0x08;
+const IFA_F_DEPRECATED: u32 = 0x20;
+const IFA_F_TENTATIVE: u32 = 0x40;
+
+const PROC_IF_INET6_PATH: &str = "/proc/net/if_inet6";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterfaceAddress {
+    pub interface: String,
+    pub address: IpAddr,
+    pub prefix_len: u8,
+    /// IPv6 flags (IFA_F_*), as reported by the kernel. Always 0 for IPv4, and where flags are not available.
+    pub flags: u32,
+}
+
+impl InterfaceAddress {
+    /// Temporary (privacy extension) IPv6 addresses change often, and are not meant to be reachable
+    pub fn is_temporary(&self) -> bool {
+        self.flags & IFA_F_TEMPORARY != 0
+    }
+
+    /// Whether the address is a globally routable address that can be published.
+    /// Private, link-local, unique local (ULA), loopback, temporary and not yet (or no longer) usable addresses are excluded.
+    pub fn is_publishable(&self) -> bool {
+        if self.is_temporary()
+            || self.flags & (IFA_F_DEPRECATED | IFA_F_TENTATIVE | IFA_F_DADFAILED) != 0
+        {
+            return false;
+        }
+
+        match self.address {
+            IpAddr::V4(a) => is_publishable_ipv4(&a),
+            IpAddr::V6(a) => is_publishable_ipv6(&a),
+        }
+    }
+}
+
+fn is_publishable_ipv4(address: &Ipv4Addr) -> bool {
+    !(address.is_private()
+        || address.is_loopback()
+        || address.is_link_local()
+        || address.is_unspecified()
+        || address.is_multicast()
+        || address.is_broadcast())
+}
+
+fn is_publishable_ipv6(address: &Ipv6Addr) -> bool {
+    let first_segment = address.segments()[0];
+    let is_link_local = first_segment & 0xffc0 == 0xfe80;
+    let is_unique_local = first_segment & 0xfe00 == 0xfc00;
+
+    !(is_link_local
+        || is_unique_local
+        || address.is_loopback()
+        || address.is_unspecified()
+        || address.is_multicast()
+        || address.to_ipv4_mapped().is_some())
+}
+
+/// Lists the addresses of all local network interfaces, using getifaddrs.
+/// On Linux, the IPv6 flags (e.g., temporary) are taken from /proc/net/if_inet6, which getifaddrs doesn't report.
+pub fn list_interface_addresses() -> std::io::Result<Vec<InterfaceAddress>> {
+    let mut result = getifaddrs()?;
+
+    if let Ok(proc_data) = std::fs::read_to_string(PROC_IF_INET6_PATH) {
+        let ipv6_entries = proc_data
+            .lines()
+            .filter_map(parse_proc_if_inet6_line)
+            .collect::<Vec<_>>();
+
+        for entry in result.iter_mut() {
+            if let Some(found) = ipv6_entries
+                .iter()
+                .find(|e| e.interface == entry.interface && e.address == entry.address)
+            {
+                entry.flags = found.flags;
+            }
+        }
+    }
+
+    Ok(result)
+}
+
+/// Parses a line of /proc/net/if_inet6, e.g.,
+/// `20010db8000000000000000000000001 02 40 00 80     eth0`
+/// (address, interface index, prefix length, scope, flags, interface name)
+fn parse_proc_if_inet6_line(line: &str) -> Option<InterfaceAddress> {
+    let mut fields = line.split_whitespace();
+
+    let address = u128::from_str_radix(fields.next()?, 16).ok()?;
+    let _index = fields.next()?;
+    let prefix_len = u8::from_str_radix(fields.next()?, 16).ok()?;
+    let _scope = fields.next()?;
+    let flags = u32::from_str_radix(fields.next()?, 16).ok()?;
+    let interface = fields.next()?.to_string();
+
+    Some(InterfaceAddress {
+        interface,
+        address: IpAddr::V6(Ipv6Addr::from(address)),
+        prefix_len,
+        flags,
+    })
+}
+
+fn getifaddrs() -> std::io::Result<Vec<InterfaceAddress>> {
+    let mut ifaddrs: *mut libc::ifaddrs = std::ptr::null_mut();
+
+    // SAFETY: getifaddrs allocates the list and stores its head in the provided pointer
+    if unsafe { libc::getifaddrs(&mut ifaddrs) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    let mut result = Vec::new();
+    let mut current = ifaddrs;
+
+    while !current.is_null() {
+        // SAFETY: current is a non-null element of the list returned by getifaddrs, which is freed only below
+        let entry = unsafe { &*current };
+        current = entry.ifa_next;
+
+        // SAFETY: the addresses are either null or valid sockaddrs of the family they declare
+        let (address, netmask) = unsafe {
+            (
+                sockaddr_to_ip(entry.ifa_addr),
+                sockaddr_to_ip(entry.ifa_netmask),
+            )
+        };
+
+        let Some(address) = address else {
+            continue;
+        };
+
+        // SAFETY: ifa_name is a valid null-terminated string for every element
+        let interface = unsafe { CStr::from_ptr(entry.ifa_name) }
+            .to_string_lossy()
+            .to_string();
+
+        let prefix_len = match netmask {
+            Some(IpAddr::V4(m)) => u32::from(m).count_ones() as u8,
+            Some(IpAddr::V6(m)) => u128::from(m).count_ones() as u8,
+            None => 0,
+        };
+
+        result.push(InterfaceAddress {
+            interface,
+            address,
+            prefix_len,
+            flags: 0,
+        });
+    }
+
+    // SAFETY: ifaddrs was returned by getifaddrs, and no references into it are kept
+    unsafe { libc::freeifaddrs(ifaddrs) };
+
+    Ok(result)
+}
+
+/// # Safety
+/// The pointer must be null, or point to a valid sockaddr whose size matches its family
+unsafe fn sockaddr_to_ip(sockaddr: *const libc::sockaddr) -> Option<IpAddr> {
+    if sockaddr.is_null() {
+        return None;
+    }
+
+    match unsafe { (*sockaddr).sa_family } as i32 {
+        libc::AF_INET => {
+            let sockaddr = unsafe { &*(sockaddr as *const libc::sockaddr_in) };
+            Some(IpAddr::V4(Ipv4Addr::from(u32::from_be(
+                sockaddr.sin_addr.s_addr,
+            ))))
+        }
+        libc::AF_INET6 => {
+            let sockaddr = unsafe { &*(sockaddr as *const libc::sockaddr_in6) };
+            Some(IpAddr::V6(Ipv6Addr::from(sockaddr.sin6_addr.s6_addr)))
+        }
+        _ => None,
+    }
+}
+
+/// Finds the publishable address of the family on the provided interface,
+/// or on any interface if no name is provided (in which case, the address must be on a single interface).
+pub fn detect(
+    interface_name: Option<&str>,
+    family: IpFamily,
+) -> Result<IpAddr, Box<dyn std::error::Error>> {
+    let addresses = list_interface_addresses()?;
+
+    select_address(&addresses, interface_name, family)
+}
+
+fn select_address(
+    addresses: &[InterfaceAddress],
+    interface_name: Option<&str>,
+    family: IpFamily,
+) -> Result<IpAddr, Box<dyn std::error::Error>> {
+    if let Some(name) = interface_name
+        && !addresses.iter().any(|a| a.interface == name)
+    {
+        return Err(Box::new(std::io::Error::other(format!(
+            "Interface `{}` not found or has no addresses",
+            name
+        ))));
+    }
+
+    let candidates = addresses
+        .iter()
+        .filter(|a| interface_name.is_none_or(|name| a.interface == name))
+        .filter(|a| IpFamily::of(&a.address) == family && a.is_publishable())
+        .collect::<Vec<_>>();
+
+    let first = candidates.first().ok_or_else(|| {
+        std::io::Error::other(format!(
+            "No publishable {} address found on {}",
+            family,
+            interface_name.unwrap_or("any interface")
+        ))
+    })?;
+
+    if candidates.iter().any(|c| c.interface != first.interface) {
+        let interfaces = candidates
+            .iter()
+            .map(|c| format!("{} ({})", c.interface, c.address))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        return Err(Box::new(std::io::Error::other(format!(
+            "Publishable {} addresses found on multiple interfaces: {}. Choose one with --interface",
+            family, interfaces
+        ))));
+    }
+
+    if candidates.len() > 1 {
+        println!(
+            "Interface {} has {} publishable {} addresses. Using the first one: {}",
+            first.interface,
+            candidates.len(),
+            family,
+            first.address
+        );
+    }
+
+    Ok(first.address)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(interface: &str, address: &str, flags: u32) -> InterfaceAddress {
+        InterfaceAddress {
+            interface: interface.to_string(),
+            address: address.parse().unwrap(),
+            prefix_len: 64,
+            flags,
+        }
+    }
+
+    #[test]
+    fn test_is_publishable() {
+        assert!(address("eth0", "203.0.113.5", 0).is_publishable());
+        assert!(address("eth0", "2001:db8::5", 0x80).is_publishable());
+
+        for non_public in [
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.1.1",
+            "127.0.0.1",
+            "fe80::1",
+            "fd00::1",
+            "fc00::1",
+            "::1",
+            "::ffff:203.0.113.5",
+        ] {
+            assert!(
+                !address("eth0", non_public, 0).is_publishable(),
+                "{}",
+                non_public
+            );
+        }
+
+        let temporary = address("eth0", "2001:db8::abcd", IFA_F_TEMPORARY);
+        assert!(temporary.is_temporary());
+        assert!(!temporary.is_publishable());
+        assert!(!address("eth0", "2001:db8::5", IFA_F_DEPRECATED).is_publishable());
+        assert!(!address("eth0", "2001:db8::5", IFA_F_TENTATIVE).is_publishable());
+    }
+
+    #[test]
+    fn test_parse_proc_if_inet6_line() {
+        let parsed =
+            parse_proc_if_inet6_line("20010db8000000000000000000000001 02 40 00 81     eth0")
+                .unwrap();
+        assert_eq!(parsed.interface, "eth0");
+        assert_eq!(parsed.address, "2001:db8::1".parse::<IpAddr>().unwrap());
+        assert_eq!(parsed.prefix_len, 64);
+        assert!(parsed.is_temporary());
+
+        assert!(parse_proc_if_inet6_line("garbage").is_none());
+    }
+
+    #[test]
+    fn test_select_address() {
+        let addresses = vec![
+            address("lo", "127.0.0.1", 0),
+            address("eth0", "192.168.1.10", 0),
+            address("eth0", "fe80::1", 0),
+            address("eth0", "2001:db8::abcd", IFA_F_TEMPORARY),
+            address("eth0", "2001:db8::1", 0),
+            address("ppp0", "203.0.113.5", 0),
+            address("wg0", "198.51.100.1", 0),
+        ];
+
+        assert_eq!(
+            select_address(&addresses, Some("eth0"), IpFamily::V6).unwrap(),
+            "2001:db8::1".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(
+            select_address(&addresses, None, IpFamily::V6).unwrap(),
+            "2001:db8::1".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(
+            select_address(&addresses, Some("ppp0"), IpFamily::V4).unwrap(),
+            "203.0.113.5".parse::<IpAddr>().unwrap()
+        );
+        // Ambiguous: ppp0 and wg0
+        assert!(select_address(&addresses, None, IpFamily::V4).is_err());
+        assert!(select_address(&addresses, Some("eth0"), IpFamily::V4).is_err());
+        assert!(select_address(&addresses, Some("eth9"), IpFamily::V4).is_err());
+    }
+
+    #[test]
+    fn test_list_interface_addresses() {
+        let addresses = list_interface_addresses().unwrap();
+
+        assert!(
+            addresses
+                .iter()
+                .any(|a| a.address == IpAddr::V4(Ipv4Addr::LOCALHOST) && a.prefix_len == 8)
+        );
+    }
+}
@@ -0,0 +1,516 @@
+//! The sources from which the public address to be published can be detected
+
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+};
+
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+
+use crate::traits::domain_control::DnsRecordType;
+
+use dns::DnsIpService;
+use gateway::GatewayConfig;
+use web::IpEchoService;
+
+pub mod dns;
+pub mod gateway;
+pub mod interface;
+pub mod special_use;
+pub mod stun;
+pub mod web;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum IpFamily {
+    V4,
+    V6,
+}
+
+impl IpFamily {
+    pub fn record_type(&self) -> DnsRecordType {
+        match self {
+            IpFamily::V4 => DnsRecordType::A,
+            IpFamily::V6 => DnsRecordType::AAAA,
+        }
+    }
+
+    pub fn of(ip_address: &IpAddr) -> Self {
+        match ip_address {
+            IpAddr::V4(_) => IpFamily::V4,
+            IpAddr::V6(_) => IpFamily::V6,
+        }
+    }
+
+    /// The unspecified address of the family. Binding to it forces connections to use that family.
+    pub fn unspecified(&self) -> IpAddr {
+        match self {
+            IpFamily::V4 => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            IpFamily::V6 => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        }
+    }
+}
+
+impl Display for IpFamily {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IpFamily::V4 => write!(f, "IPv4"),
+            IpFamily::V6 => write!(f, "IPv6"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IpSourceKind {
+    /// HTTP(S) services that echo back the address they see
+    #[default]
+    Web,
+    /// The addresses of the local network interfaces
+    Interface,
+    /// DNS servers that answer with the address of the client
+    Dns,
+    /// STUN servers, which return the address mapped by the NAT
+    Stun,
+    /// The WAN address of the home router, over PCP, NAT-PMP or UPnP-IGD
+    Gateway,
+}
+
+impl FromStr for IpSourceKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "web" => Ok(IpSourceKind::Web),
+            "interface" => Ok(IpSourceKind::Interface),
+            "dns" => Ok(IpSourceKind::Dns),
+            "stun" => Ok(IpSourceKind::Stun),
+            "gateway" => Ok(IpSourceKind::Gateway),
+            _ => Err(format!("Unknown IP source: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IpSource {
+    Web,
+    Interface { name: Option<String> },
+    Dns,
+    Stun,
+    Gateway,
+}
+
+impl IpSource {
+    /// The interface name is only used by the `interface` source
+    pub fn from_kind(kind: IpSourceKind, interface: Option<String>) -> Self {
+        match kind {
+            IpSourceKind::Web => IpSource::Web,
+            IpSourceKind::Interface => IpSource::Interface { name: interface },
+            IpSourceKind::Dns => IpSource::Dns,
+            IpSourceKind::Stun => IpSource::Stun,
+            IpSourceKind::Gateway => IpSource::Gateway,
+        }
+    }
+}
+
+impl Display for IpSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IpSource::Web => write!(f, "web services"),
+            IpSource::Interface { name: Some(name) } => write!(f, "interface {}", name),
+            IpSource::Interface { name: None } => write!(f, "local interfaces"),
+            IpSource::Dns => write!(f, "DNS servers"),
+            IpSource::Stun => write!(f, "STUN servers"),
+            IpSource::Gateway => write!(f, "the gateway"),
+        }
+    }
+}
+
+/// The `ip_detection` section of the config file
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct IpDetectionConfig {
+    /// The services used to detect the public IPv4 address. The built-in list is used if empty.
+    #[serde(default)]
+    pub ipv4_services: Vec<IpEchoService>,
+    /// The services used to detect the public IPv6 address. The built-in list is used if empty.
+    #[serde(default)]
+    pub ipv6_services: Vec<IpEchoService>,
+    /// The DNS servers used to detect the public IPv4 address. The built-in list is used if empty.
+    #[serde(default)]
+    pub ipv4_dns_servers: Vec<DnsIpService>,
+    /// The DNS servers used to detect the public IPv6 address. The built-in list is used if empty.
+    #[serde(default)]
+    pub ipv6_dns_servers: Vec<DnsIpService>,
+    /// The STUN servers (`host:port`) used to detect the public IPv4 address. The built-in list is used if empty.
+    #[serde(default)]
+    pub ipv4_stun_servers: Vec<String>,
+    /// The STUN servers (`host:port`) used to detect the public IPv6 address. The built-in list is used if empty.
+    #[serde(default)]
+    pub ipv6_stun_servers: Vec<String>,
+    /// How the gateway is reached for `gateway` detection
+    #[serde(default)]
+    pub gateway: GatewayConfig,
+    /// If set, multiple services (of all the selected sources) are queried and an address is only accepted
+    /// if enough of them agree on it. Otherwise, the first service that answers is trusted.
+    #[serde(default)]
+    pub quorum: Option<Quorum>,
+}
+
+impl IpDetectionConfig {
+    pub fn check(&self) -> Result<(), String> {
+        if let Some(quorum) = &self.quorum {
+            quorum.check()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Query `queries` services in parallel, and accept an address only if at least `agree` of them return it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Quorum {
+    pub queries: usize,
+    pub agree: usize,
+}
+
+impl Quorum {
+    pub fn check(&self) -> Result<(), String> {
+        if self.agree == 0 || self.agree > self.queries {
+            return Err(format!(
+                "The quorum's `agree` must be between 1 and `queries` ({}), but it is {}",
+                self.queries, self.agree
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Picks the address most sources agree on, if they're at least as many as the quorum requires.
+/// A tie between addresses is no quorum, as nothing tells which of them is right.
+/// The answers are pairs of the source name and what it returned.
+/// Disagreements and failures are reported, even when the quorum is reached.
+pub fn decide_by_quorum(
+    answers: &[(String, Result<IpAddr, String>)],
+    quorum: &Quorum,
+) -> Result<IpAddr, Box<dyn std::error::Error>> {
+    let mut votes: BTreeMap<IpAddr, Vec<&str>> = BTreeMap::new();
+
+    for (source, answer) in answers {
+        match answer {
+            Ok(ip_address) => votes.entry(*ip_address).or_default().push(source),
+            Err(e) => eprintln!("Source {} failed to return an address: {}", source, e),
+        }
+    }
+
+    if votes.len() > 1 {
+        eprintln!("Sources disagree on the public address:");
+        for (ip_address, sources) in &votes {
+            eprintln!("  {} from {}", ip_address, sources.join(", "));
+        }
+    }
+
+    let most_votes = votes.values().map(|sources| sources.len()).max();
+    let leaders = votes
+        .iter()
+        .filter(|(_, sources)| Some(sources.len()) == most_votes)
+        .collect::<Vec<_>>();
+
+    if leaders.len() > 1 {
+        return Err(Box::new(std::io::Error::other(format!(
+            "No quorum: {} out of {} sources agree on each of {}",
+            leaders[0].1.len(),
+            answers.len(),
+            leaders
+                .iter()
+                .map(|(ip_address, _)| ip_address.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))));
+    }
+
+    match leaders.first().copied() {
+        Some((ip_address, sources)) if sources.len() >= quorum.agree => {
+            println!(
+                "{} out of {} sources agree on the address {}",
+                sources.len(),
+                answers.len(),
+                ip_address
+            );
+            Ok(*ip_address)
+        }
+        Some((ip_address, sources)) => Err(Box::new(std::io::Error::other(format!(
+            "No quorum: at most {} out of {} sources agree (on {}), but {} are required",
+            sources.len(),
+            answers.len(),
+            ip_address,
+            quorum.agree
+        )))),
+        None => Err(Box::new(std::io::Error::other(format!(
+            "No quorum: none of the {} sources returned an address",
+            answers.len()
+        )))),
+    }
+}
+
+/// Something that can be asked for the public address, e.g., one web service or one DNS server.
+/// Each of them has one vote in quorum mode.
+pub struct Voter {
+    pub name: String,
+    query: Box<dyn Fn() -> Result<IpAddr, Box<dyn std::error::Error>> + Send + Sync>,
+}
+
+impl Voter {
+    pub fn new(
+        name: String,
+        query: impl Fn() -> Result<IpAddr, Box<dyn std::error::Error>> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            name,
+            query: Box::new(query),
+        }
+    }
+
+    pub fn query(&self) -> Result<IpAddr, Box<dyn std::error::Error>> {
+        (self.query)()
+    }
+}
+
+fn voters(
+    client_maker: &dyn Fn(IpFamily) -> reqwest::blocking::Client,
+    source: &IpSource,
+    family: IpFamily,
+    config: &IpDetectionConfig,
+) -> Vec<Voter> {
+    match source {
+        IpSource::Web => web::voters(client_maker(family), family, config),
+        IpSource::Interface { name } => {
+            let name = name.clone();
+            vec![Voter::new(source.to_string(), move || {
+                interface::detect(name.as_deref(), family)
+            })]
+        }
+        IpSource::Dns => dns::voters(family, config),
+        IpSource::Stun => stun::voters(family, config),
+        IpSource::Gateway => gateway::voters(family, config),
+    }
+}
+
+/// Asks every voter in its own thread, and returns the answers in the same order
+fn query_in_parallel(voters: &[Voter]) -> Vec<(String, Result<IpAddr, String>)> {
+    std::thread::scope(|scope| {
+        let handles = voters
+            .iter()
+            .map(|voter| scope.spawn(move || voter.query().map_err(|e| e.to_string())))
+            .collect::<Vec<_>>();
+
+        voters
+            .iter()
+            .zip(handles)
+            .map(|(voter, handle)| {
+                let answer = handle
+                    .join()
+                    .unwrap_or_else(|_| Err("The query panicked".to_string()));
+                (voter.name.clone(), answer)
+            })
+            .collect()
+    })
+}
+
+/// Takes up to `count` items, one from each group in turn, so that every group is represented
+/// (e.g., every selected source in a quorum) as far as the count allows
+fn take_round_robin<T>(groups: Vec<Vec<T>>, count: usize) -> Vec<T> {
+    let mut groups = groups
+        .into_iter()
+        .map(|group| group.into_iter())
+        .collect::<Vec<_>>();
+    let mut result = Vec::new();
+
+    while result.len() < count {
+        let taken_before = result.len();
+
+        for group in &mut groups {
+            if result.len() == count {
+                break;
+            }
+            result.extend(group.next());
+        }
+
+        if result.len() == taken_before {
+            break;
+        }
+    }
+
+    result
+}
+
+/// Detects the public address like detect_ip_address, and refuses special-use (bogon) addresses unless allowed
+pub fn detect_publishable_ip_address(
+    client_maker: &dyn Fn(IpFamily) -> reqwest::blocking::Client,
+    sources: &[IpSource],
+    family: IpFamily,
+    config: &IpDetectionConfig,
+    allow_bogon: bool,
+) -> Result<IpAddr, Box<dyn std::error::Error>> {
+    let ip_address = detect_ip_address(client_maker, sources, family, config)?;
+
+    special_use::check_publishable(&ip_address, allow_bogon)?;
+
+    Ok(ip_address)
+}
+
+/// Detects the public address of the family from the sources.
+/// Without a quorum, the sources are tried in order, and the first address returned is trusted.
+pub fn detect_ip_address(
+    client_maker: &dyn Fn(IpFamily) -> reqwest::blocking::Client,
+    sources: &[IpSource],
+    family: IpFamily,
+    config: &IpDetectionConfig,
+) -> Result<IpAddr, Box<dyn std::error::Error>> {
+    let voters_per_source = sources
+        .iter()
+        .map(|source| voters(client_maker, source, family, config))
+        .collect::<Vec<_>>();
+
+    if let Some(quorum) = &config.quorum {
+        let voters = {
+            let mut voters_per_source = voters_per_source;
+            for voters in &mut voters_per_source {
+                voters.shuffle(&mut rand::rng());
+            }
+            take_round_robin(voters_per_source, quorum.queries)
+        };
+
+        return decide_by_quorum(&query_in_parallel(&voters), quorum);
+    }
+
+    let voters = voters_per_source.into_iter().flatten().collect::<Vec<_>>();
+
+    let mut errors = Vec::new();
+    for voter in &voters {
+        match voter.query() {
+            Ok(ip_address) => return Ok(ip_address),
+            Err(e) => errors.push(format!("{}: {}", voter.name, e)),
+        }
+    }
+
+    Err(Box::new(std::io::Error::other(format!(
+        "Could not get the public {} address from any of the sources: {}",
+        family,
+        errors.join("; ")
+    ))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn answer(source: &str, ip_address: &str) -> (String, Result<IpAddr, String>) {
+        (source.to_string(), Ok(ip_address.parse().unwrap()))
+    }
+
+    #[test]
+    fn test_decide_by_quorum() {
+        let quorum = Quorum {
+            queries: 3,
+            agree: 2,
+        };
+
+        let answers = vec![
+            answer("a", "203.0.113.5"),
+            answer("b", "198.51.100.1"),
+            answer("c", "203.0.113.5"),
+        ];
+        assert_eq!(
+            decide_by_quorum(&answers, &quorum).unwrap(),
+            "203.0.113.5".parse::<IpAddr>().unwrap()
+        );
+
+        let answers = vec![
+            answer("a", "203.0.113.5"),
+            answer("b", "198.51.100.1"),
+            ("c".to_string(), Err("timed out".to_string())),
+        ];
+        assert!(decide_by_quorum(&answers, &quorum).is_err());
+
+        let answers = vec![("a".to_string(), Err("timed out".to_string()))];
+        assert!(decide_by_quorum(&answers, &quorum).is_err());
+
+        // Two sources returning the same wrong address can't win a tie
+        let quorum = Quorum {
+            queries: 4,
+            agree: 2,
+        };
+        let answers = vec![
+            answer("a", "203.0.113.5"),
+            answer("b", "198.51.100.1"),
+            answer("c", "198.51.100.1"),
+            answer("d", "203.0.113.5"),
+        ];
+        let error = decide_by_quorum(&answers, &quorum).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "No quorum: 2 out of 4 sources agree on each of 198.51.100.1, 203.0.113.5"
+        );
+    }
+
+    #[test]
+    fn test_quorum_check() {
+        for (queries, agree, valid) in [(3, 2, true), (3, 3, true), (3, 0, false), (3, 4, false)] {
+            assert_eq!(Quorum { queries, agree }.check().is_ok(), valid);
+        }
+
+        let config: IpDetectionConfig =
+            serde_yml::from_str("quorum:\n  queries: 2\n  agree: 3\n").unwrap();
+        assert!(config.check().is_err());
+        assert!(IpDetectionConfig::default().check().is_ok());
+    }
+
+    #[test]
+    fn test_take_round_robin() {
+        let groups = || {
+            vec![
+                vec!["web1", "web2", "web3", "web4"],
+                vec!["gateway"],
+                vec!["dns1", "dns2"],
+            ]
+        };
+
+        assert_eq!(take_round_robin(groups(), 2), vec!["web1", "gateway"]);
+        assert_eq!(
+            take_round_robin(groups(), 5),
+            vec!["web1", "gateway", "dns1", "web2", "dns2"]
+        );
+        assert_eq!(take_round_robin(groups(), 10).len(), 7);
+        assert!(take_round_robin(Vec::<Vec<&str>>::new(), 3).is_empty());
+    }
+
+    #[test]
+    fn test_ip_detection_config_parsing() {
+        let config: IpDetectionConfig = serde_yml::from_str(
+            r#"
+ipv4_services:
+  - url: https://ip.example/json
+    json_field: ip
+  - url: https://ip.example/text
+quorum:
+  queries: 3
+  agree: 2
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.ipv4_services.len(), 2);
+        assert_eq!(config.ipv4_services[0].json_field.as_deref(), Some("ip"));
+        assert!(config.ipv6_services.is_empty());
+        assert_eq!(
+            config.quorum,
+            Some(Quorum {
+                queries: 3,
+                agree: 2
+            })
+        );
+    }
+}
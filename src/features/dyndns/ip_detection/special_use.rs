@@ -0,0 +1,293 @@
+//! Validation of the detected address against the special-use (bogon) ranges of the IANA registries,
+//! so that a wrong answer (e.g., a private or CGNAT address from a misconfigured route) isn't published
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use super::{
+    IpFamily, gateway::parse_default_route_interface, interface::list_interface_addresses,
+};
+
+/// The IPv4 special-use ranges (IANA IPv4 Special-Purpose Address Registry, and multicast and reserved space)
+const IPV4_SPECIAL_USE: [(Ipv4Addr, u8, &str); 16] = [
+    (Ipv4Addr::new(0, 0, 0, 0), 8, "\"this network\" (RFC 791)"),
+    (Ipv4Addr::new(10, 0, 0, 0), 8, "private (RFC 1918)"),
+    (
+        Ipv4Addr::new(100, 64, 0, 0),
+        10,
+        "shared address space, i.e., CGNAT (RFC 6598)",
+    ),
+    (Ipv4Addr::new(127, 0, 0, 0), 8, "loopback (RFC 1122)"),
+    (Ipv4Addr::new(169, 254, 0, 0), 16, "link-local (RFC 3927)"),
+    (Ipv4Addr::new(172, 16, 0, 0), 12, "private (RFC 1918)"),
+    (
+        Ipv4Addr::new(192, 0, 0, 0),
+        24,
+        "IETF protocol assignments (RFC 6890)",
+    ),
+    (
+        Ipv4Addr::new(192, 0, 2, 0),
+        24,
+        "documentation, TEST-NET-1 (RFC 5737)",
+    ),
+    (
+        Ipv4Addr::new(192, 88, 99, 0),
+        24,
+        "6to4 relay anycast (RFC 7526)",
+    ),
+    (Ipv4Addr::new(192, 168, 0, 0), 16, "private (RFC 1918)"),
+    (Ipv4Addr::new(198, 18, 0, 0), 15, "benchmarking (RFC 2544)"),
+    (
+        Ipv4Addr::new(198, 51, 100, 0),
+        24,
+        "documentation, TEST-NET-2 (RFC 5737)",
+    ),
+    (
+        Ipv4Addr::new(203, 0, 113, 0),
+        24,
+        "documentation, TEST-NET-3 (RFC 5737)",
+    ),
+    (Ipv4Addr::new(224, 0, 0, 0), 4, "multicast (RFC 5771)"),
+    (Ipv4Addr::new(240, 0, 0, 0), 4, "reserved (RFC 1112)"),
+    (
+        Ipv4Addr::new(255, 255, 255, 255),
+        32,
+        "limited broadcast (RFC 919)",
+    ),
+];
+
+/// The IPv6 special-use ranges inside the global unicast space (2000::/3). Everything outside of it is not
+/// publishable either (e.g., loopback, link-local, unique local, multicast, IPv4-mapped).
+const IPV6_SPECIAL_USE: [(Ipv6Addr, u8, &str); 5] = [
+    (
+        Ipv6Addr::new(0x2001, 0, 0, 0, 0, 0, 0, 0),
+        23,
+        "IETF protocol assignments (RFC 2928)",
+    ),
+    (
+        Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0),
+        32,
+        "documentation (RFC 3849)",
+    ),
+    (
+        Ipv6Addr::new(0x2002, 0, 0, 0, 0, 0, 0, 0),
+        16,
+        "6to4 (RFC 3056)",
+    ),
+    (
+        Ipv6Addr::new(0x3fff, 0, 0, 0, 0, 0, 0, 0),
+        20,
+        "documentation (RFC 9637)",
+    ),
+    (
+        Ipv6Addr::new(0x64, 0xff9b, 0, 0, 0, 0, 0, 0),
+        96,
+        "IPv4/IPv6 translation, i.e., NAT64 (RFC 6052)",
+    ),
+];
+
+fn in_ipv4_range(address: Ipv4Addr, network: Ipv4Addr, prefix_len: u8) -> bool {
+    let mask = u32::MAX
+        .checked_shl(32 - u32::from(prefix_len))
+        .unwrap_or(0);
+
+    u32::from(address) & mask == u32::from(network) & mask
+}
+
+fn in_ipv6_range(address: Ipv6Addr, network: Ipv6Addr, prefix_len: u8) -> bool {
+    let mask = u128::MAX
+        .checked_shl(128 - u32::from(prefix_len))
+        .unwrap_or(0);
+
+    u128::from(address) & mask == u128::from(network) & mask
+}
+
+/// The special-use range of the address, if it's in one, i.e., if it can't be the public address of a host
+pub fn special_use_range(address: &IpAddr) -> Option<&'static str> {
+    match address {
+        IpAddr::V4(address) => IPV4_SPECIAL_USE
+            .iter()
+            .find(|(network, prefix_len, _)| in_ipv4_range(*address, *network, *prefix_len))
+            .map(|(_, _, name)| *name),
+        IpAddr::V6(address) => {
+            // 2001::/23 has globally reachable exceptions (e.g., TEREDO), but none that a dyndns host would have
+            let special = IPV6_SPECIAL_USE
+                .iter()
+                .find(|(network, prefix_len, _)| in_ipv6_range(*address, *network, *prefix_len))
+                .map(|(_, _, name)| *name);
+
+            if special.is_none() && address.segments()[0] & 0xe000 != 0x2000 {
+                return Some("outside of the global unicast space (2000::/3)");
+            }
+
+            special
+        }
+    }
+}
+
+/// Refuses special-use addresses, unless they are allowed, in which case only a warning is printed
+pub fn check_publishable(address: &IpAddr, allow_bogon: bool) -> Result<(), String> {
+    let Some(range) = special_use_range(address) else {
+        return Ok(());
+    };
+
+    if allow_bogon {
+        eprintln!(
+            "Warning: the detected address {} is {}, and is published anyway as requested",
+            address, range
+        );
+        return Ok(());
+    }
+
+    Err(format!(
+        "The detected address {} is {}, and can't be the public address. Refusing to publish it (see --allow-bogon)",
+        address, range
+    ))
+}
+
+fn is_cgnat(address: &IpAddr) -> bool {
+    match address {
+        IpAddr::V4(address) => in_ipv4_range(*address, Ipv4Addr::new(100, 64, 0, 0), 10),
+        IpAddr::V6(_) => false,
+    }
+}
+
+/// Compares the public IPv4 address with the addresses of the interface of the default route.
+/// If that interface has a public or CGNAT address (i.e., it's the WAN interface, not a LAN behind a router)
+/// that differs from the public address, there is another NAT upstream, usually the ISP's CGNAT.
+fn nat_warning(
+    public_address: &IpAddr,
+    interface: &str,
+    wan_addresses: &[IpAddr],
+) -> Option<String> {
+    if IpFamily::of(public_address) != IpFamily::V4 || wan_addresses.contains(public_address) {
+        return None;
+    }
+
+    let is_wan = |address: &IpAddr| {
+        IpFamily::of(address) == IpFamily::V4
+            && (is_cgnat(address) || special_use_range(address).is_none())
+    };
+
+    let wan_address = wan_addresses.iter().find(|a| is_wan(a))?;
+
+    let reason = if is_cgnat(wan_address) {
+        "which is in the CGNAT range"
+    } else {
+        "which indicates another NAT upstream, e.g., CGNAT"
+    };
+
+    Some(format!(
+        "Warning: the WAN interface {} has the address {}, but the public address is {}, {}. \
+         Incoming connections to the published address will likely not reach this network.",
+        interface, wan_address, public_address, reason
+    ))
+}
+
+/// Prints a warning if the WAN interface's address and the public address differ (Linux only, as it uses /proc/net/route)
+pub fn warn_if_behind_nat(public_address: &IpAddr) {
+    let Some(interface) = std::fs::read_to_string("/proc/net/route")
+        .ok()
+        .and_then(|route_table| parse_default_route_interface(&route_table))
+    else {
+        return;
+    };
+
+    let Ok(addresses) = list_interface_addresses() else {
+        return;
+    };
+
+    let wan_addresses = addresses
+        .into_iter()
+        .filter(|a| a.interface == interface)
+        .map(|a| a.address)
+        .collect::<Vec<_>>();
+
+    if let Some(warning) = nat_warning(public_address, &interface, &wan_addresses) {
+        eprintln!("{}", warning);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(address: &str) -> IpAddr {
+        address.parse().unwrap()
+    }
+
+    #[test]
+    fn test_special_use_range() {
+        for address in [
+            "10.1.2.3",
+            "172.31.255.255",
+            "192.168.1.1",
+            "100.64.0.1",
+            "100.127.255.254",
+            "127.0.0.1",
+            "169.254.1.1",
+            "192.0.2.7",
+            "198.51.100.7",
+            "203.0.113.7",
+            "198.19.0.1",
+            "0.1.2.3",
+            "224.0.0.1",
+            "255.255.255.255",
+            "::1",
+            "fe80::1",
+            "fd00::1",
+            "ff02::1",
+            "::ffff:8.8.8.8",
+            "2001:db8::1",
+            "3fff::1",
+            "64:ff9b::808:808",
+        ] {
+            assert!(special_use_range(&ip(address)).is_some(), "{}", address);
+        }
+
+        for address in [
+            "8.8.8.8",
+            "100.128.0.1",
+            "172.32.0.1",
+            "1.1.1.1",
+            "2a00:1450:4001:80e::200e",
+            "2606:4700::1111",
+        ] {
+            assert_eq!(special_use_range(&ip(address)), None, "{}", address);
+        }
+
+        assert_eq!(
+            special_use_range(&ip("100.64.0.1")),
+            Some("shared address space, i.e., CGNAT (RFC 6598)")
+        );
+    }
+
+    #[test]
+    fn test_check_publishable() {
+        assert!(check_publishable(&ip("8.8.8.8"), false).is_ok());
+        assert!(check_publishable(&ip("192.168.1.10"), false).is_err());
+        assert!(check_publishable(&ip("192.168.1.10"), true).is_ok());
+    }
+
+    #[test]
+    fn test_nat_warning() {
+        // A LAN host behind a home router: nothing to warn about
+        assert_eq!(
+            nat_warning(&ip("8.8.8.8"), "eth0", &[ip("192.168.1.10"), ip("fe80::1")]),
+            None
+        );
+        // The router itself, with the public address on its WAN interface
+        assert_eq!(nat_warning(&ip("8.8.8.8"), "ppp0", &[ip("8.8.8.8")]), None);
+
+        let warning = nat_warning(&ip("8.8.8.8"), "ppp0", &[ip("100.70.1.2")]).unwrap();
+        assert!(warning.contains("100.70.1.2"));
+        assert!(warning.contains("CGNAT range"));
+
+        assert!(nat_warning(&ip("8.8.8.8"), "eth0", &[ip("1.2.3.4")]).is_some());
+
+        // IPv6 usually has no NAT, and temporary addresses would differ anyway
+        assert_eq!(
+            nat_warning(&ip("2606:4700::1111"), "eth0", &[ip("2606:4700::2222")]),
+            None
+        );
+    }
+}
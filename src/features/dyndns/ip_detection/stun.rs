@@ -0,0 +1,338 @@
+//! Detection of the public (mapped) address with STUN Binding requests (RFC 5389) over UDP
+
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket},
+    time::Duration,
+};
+
+use super::{IpDetectionConfig, IpFamily, Voter};
+
+/// UDP is unreliable, so the request is retransmitted, waiting this long for each response
+const ATTEMPT_TIMEOUT: Duration = Duration::from_millis(1000);
+const ATTEMPTS: usize = 3;
+
+const MAGIC_COOKIE: u32 = 0x2112_a442;
+const BINDING_REQUEST: u16 = 0x0001;
+const BINDING_SUCCESS_RESPONSE: u16 = 0x0101;
+const BINDING_ERROR_RESPONSE: u16 = 0x0111;
+const ATTRIBUTE_MAPPED_ADDRESS: u16 = 0x0001;
+const ATTRIBUTE_XOR_MAPPED_ADDRESS: u16 = 0x0020;
+const ADDRESS_FAMILY_IPV4: u8 = 0x01;
+const ADDRESS_FAMILY_IPV6: u8 = 0x02;
+
+/// List of STUN servers to get the public IPv4 address from
+const STUN_SERVERS: [&str; 2] = ["stun.l.google.com:19302", "stun.cloudflare.com:3478"];
+
+/// List of STUN servers to get the public IPv6 address from
+const IPV6_STUN_SERVERS: [&str; 2] = ["stun.l.google.com:19302", "stun.cloudflare.com:3478"];
+
+/// The configured servers of the family, or the built-in ones if none are configured
+fn servers(family: IpFamily, config: &IpDetectionConfig) -> Vec<String> {
+    let (configured, defaults) = match family {
+        IpFamily::V4 => (&config.ipv4_stun_servers, STUN_SERVERS.as_slice()),
+        IpFamily::V6 => (&config.ipv6_stun_servers, IPV6_STUN_SERVERS.as_slice()),
+    };
+
+    if configured.is_empty() {
+        defaults.iter().map(|s| s.to_string()).collect()
+    } else {
+        configured.clone()
+    }
+}
+
+pub fn voters(family: IpFamily, config: &IpDetectionConfig) -> Vec<Voter> {
+    servers(family, config)
+        .into_iter()
+        .map(|server| {
+            Voter::new(format!("stun://{}", server), move || {
+                get_my_ip_address_from_stun(&server, family)
+            })
+        })
+        .collect()
+}
+
+pub fn get_my_ip_address_from_stun(
+    server: &str,
+    family: IpFamily,
+) -> Result<IpAddr, Box<dyn std::error::Error>> {
+    let server_address = server
+        .to_socket_addrs()?
+        .find(|a| IpFamily::of(&a.ip()) == family)
+        .ok_or_else(|| format!("STUN server {} has no {} address", server, family))?;
+
+    let mapped_address = binding_request(server_address)?;
+
+    if IpFamily::of(&mapped_address.ip()) != family {
+        return Err(format!(
+            "STUN server {} returned {}, which is not an {} address",
+            server, mapped_address, family
+        )
+        .into());
+    }
+
+    Ok(mapped_address.ip())
+}
+
+/// Sends a Binding request and returns the address the server saw the request coming from
+fn binding_request(server: SocketAddr) -> Result<SocketAddr, Box<dyn std::error::Error>> {
+    let socket = UdpSocket::bind(SocketAddr::new(IpFamily::of(&server.ip()).unspecified(), 0))?;
+    socket.set_read_timeout(Some(ATTEMPT_TIMEOUT))?;
+    socket.connect(server)?;
+
+    let transaction_id: [u8; 12] = rand::random();
+    let request = build_binding_request(&transaction_id);
+
+    let mut buffer = [0u8; 1024];
+    for _ in 0..ATTEMPTS {
+        socket.send(&request)?;
+
+        loop {
+            let size = match socket.recv(&mut buffer) {
+                Ok(size) => size,
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    break;
+                }
+                Err(e) => return Err(Box::new(e)),
+            };
+
+            // Responses to other transactions (e.g., earlier attempts) are ignored
+            if let Some(result) = parse_binding_response(&buffer[..size], &transaction_id) {
+                return Ok(result?);
+            }
+        }
+    }
+
+    Err(format!(
+        "No response from STUN server {} after {} attempts",
+        server, ATTEMPTS
+    )
+    .into())
+}
+
+fn build_binding_request(transaction_id: &[u8; 12]) -> Vec<u8> {
+    let mut message = Vec::with_capacity(20);
+
+    message.extend_from_slice(&BINDING_REQUEST.to_be_bytes());
+    // No attributes
+    message.extend_from_slice(&0u16.to_be_bytes());
+    message.extend_from_slice(&MAGIC_COOKIE.to_be_bytes());
+    message.extend_from_slice(transaction_id);
+
+    message
+}
+
+/// Returns None if the message is not a response to the transaction, so that it can be ignored
+fn parse_binding_response(
+    message: &[u8],
+    transaction_id: &[u8; 12],
+) -> Option<Result<SocketAddr, String>> {
+    if message.len() < 20
+        || message[4..8] != MAGIC_COOKIE.to_be_bytes()
+        || message[8..20] != transaction_id[..]
+    {
+        return None;
+    }
+
+    let message_type = u16::from_be_bytes([message[0], message[1]]);
+    let length = u16::from_be_bytes([message[2], message[3]]) as usize;
+
+    let result = match message_type {
+        BINDING_SUCCESS_RESPONSE => message
+            .get(20..20 + length)
+            .ok_or_else(|| "Truncated STUN response".to_string())
+            .and_then(|attributes| parse_mapped_address(attributes, transaction_id)),
+        BINDING_ERROR_RESPONSE => Err("STUN server responded with an error".to_string()),
+        other => Err(format!("Unexpected STUN message type {:#06x}", other)),
+    };
+
+    Some(result)
+}
+
+/// Finds the XOR-MAPPED-ADDRESS attribute, or MAPPED-ADDRESS for old (RFC 3489) servers
+fn parse_mapped_address(
+    attributes: &[u8],
+    transaction_id: &[u8; 12],
+) -> Result<SocketAddr, String> {
+    let mut mapped_address = None;
+
+    let mut offset = 0;
+    while offset + 4 <= attributes.len() {
+        let attribute_type = u16::from_be_bytes([attributes[offset], attributes[offset + 1]]);
+        let length = u16::from_be_bytes([attributes[offset + 2], attributes[offset + 3]]) as usize;
+        let value = attributes
+            .get(offset + 4..offset + 4 + length)
+            .ok_or_else(|| "Truncated STUN attribute".to_string())?;
+
+        match attribute_type {
+            ATTRIBUTE_XOR_MAPPED_ADDRESS => return parse_address(value, Some(transaction_id)),
+            ATTRIBUTE_MAPPED_ADDRESS => mapped_address = Some(parse_address(value, None)?),
+            _ => {}
+        }
+
+        // Attributes are padded to a multiple of 4 bytes
+        offset += 4 + length.div_ceil(4) * 4;
+    }
+
+    mapped_address.ok_or_else(|| "No mapped address in the STUN response".to_string())
+}
+
+/// Parses the value of a (XOR-)MAPPED-ADDRESS attribute. The XOR variant is unmasked with the transaction ID.
+fn parse_address(
+    value: &[u8],
+    xor_transaction_id: Option<&[u8; 12]>,
+) -> Result<SocketAddr, String> {
+    if value.len() < 4 {
+        return Err("Truncated STUN address".to_string());
+    }
+
+    // The mask is the magic cookie followed by the transaction ID, or zeros for the plain variant
+    let mut mask = [0u8; 16];
+    if let Some(transaction_id) = xor_transaction_id {
+        mask[..4].copy_from_slice(&MAGIC_COOKIE.to_be_bytes());
+        mask[4..].copy_from_slice(transaction_id);
+    }
+
+    let port = u16::from_be_bytes([value[2] ^ mask[0], value[3] ^ mask[1]]);
+
+    let ip_address = match (value[1], value.len()) {
+        (ADDRESS_FAMILY_IPV4, 8) => {
+            let mut octets = [0u8; 4];
+            for (i, octet) in octets.iter_mut().enumerate() {
+                *octet = value[4 + i] ^ mask[i];
+            }
+            IpAddr::V4(Ipv4Addr::from(octets))
+        }
+        (ADDRESS_FAMILY_IPV6, 20) => {
+            let mut octets = [0u8; 16];
+            for (i, octet) in octets.iter_mut().enumerate() {
+                *octet = value[4 + i] ^ mask[i];
+            }
+            IpAddr::V6(Ipv6Addr::from(octets))
+        }
+        (family, length) => {
+            return Err(format!(
+                "Invalid STUN address with family {} and length {}",
+                family, length
+            ));
+        }
+    };
+
+    Ok(SocketAddr::new(ip_address, port))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds the value of a XOR-MAPPED-ADDRESS attribute, like a server does
+    fn xor_mapped_address(address: SocketAddr, transaction_id: &[u8; 12]) -> Vec<u8> {
+        let mut mask = MAGIC_COOKIE.to_be_bytes().to_vec();
+        mask.extend_from_slice(transaction_id);
+
+        let (family, octets) = match address.ip() {
+            IpAddr::V4(ip) => (ADDRESS_FAMILY_IPV4, ip.octets().to_vec()),
+            IpAddr::V6(ip) => (ADDRESS_FAMILY_IPV6, ip.octets().to_vec()),
+        };
+
+        let mut value = vec![0, family];
+        value.extend_from_slice(&(address.port() ^ (MAGIC_COOKIE >> 16) as u16).to_be_bytes());
+        value.extend(octets.iter().zip(&mask).map(|(o, m)| o ^ m));
+        value
+    }
+
+    /// A stand-in STUN server that answers one Binding request with the provided mapped address,
+    /// preceded by an unknown attribute with padding
+    fn spawn_stub_stun_server(mapped_address: SocketAddr) -> (String, std::thread::JoinHandle<()>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = socket.local_addr().unwrap().to_string();
+
+        let handle = std::thread::spawn(move || {
+            let mut buffer = [0u8; 512];
+            let (size, peer) = socket.recv_from(&mut buffer).unwrap();
+            let request = &buffer[..size];
+
+            assert_eq!(request.len(), 20);
+            assert_eq!(
+                u16::from_be_bytes([request[0], request[1]]),
+                BINDING_REQUEST
+            );
+            let transaction_id: [u8; 12] = request[8..20].try_into().unwrap();
+
+            // SOFTWARE attribute of 5 bytes, padded to 8
+            let mut attributes = vec![0x80, 0x22, 0, 5, b's', b't', b'u', b'b', b'!', 0, 0, 0];
+            let value = xor_mapped_address(mapped_address, &transaction_id);
+            attributes.extend_from_slice(&ATTRIBUTE_XOR_MAPPED_ADDRESS.to_be_bytes());
+            attributes.extend_from_slice(&(value.len() as u16).to_be_bytes());
+            attributes.extend_from_slice(&value);
+
+            let mut response = BINDING_SUCCESS_RESPONSE.to_be_bytes().to_vec();
+            response.extend_from_slice(&(attributes.len() as u16).to_be_bytes());
+            response.extend_from_slice(&request[4..20]);
+            response.extend_from_slice(&attributes);
+
+            socket.send_to(&response, peer).unwrap();
+        });
+
+        (address, handle)
+    }
+
+    #[test]
+    fn test_stun_detection() {
+        let mapped_address: SocketAddr = "198.51.100.3:40000".parse().unwrap();
+        let (server, handle) = spawn_stub_stun_server(mapped_address);
+
+        let ip_address = get_my_ip_address_from_stun(&server, IpFamily::V4).unwrap();
+
+        assert_eq!(ip_address, mapped_address.ip());
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_parse_binding_response() {
+        let transaction_id = [7u8; 12];
+
+        for mapped_address in ["198.51.100.3:40000", "[2001:db8::3]:3478"] {
+            let mapped_address: SocketAddr = mapped_address.parse().unwrap();
+            let value = xor_mapped_address(mapped_address, &transaction_id);
+
+            let mut response = BINDING_SUCCESS_RESPONSE.to_be_bytes().to_vec();
+            response.extend_from_slice(&(value.len() as u16 + 4).to_be_bytes());
+            response.extend_from_slice(&MAGIC_COOKIE.to_be_bytes());
+            response.extend_from_slice(&transaction_id);
+            response.extend_from_slice(&ATTRIBUTE_XOR_MAPPED_ADDRESS.to_be_bytes());
+            response.extend_from_slice(&(value.len() as u16).to_be_bytes());
+            response.extend_from_slice(&value);
+
+            assert_eq!(
+                parse_binding_response(&response, &transaction_id),
+                Some(Ok(mapped_address))
+            );
+
+            // Responses to other transactions are ignored
+            assert_eq!(parse_binding_response(&response, &[8u8; 12]), None);
+        }
+
+        // A plain MAPPED-ADDRESS, as sent by old servers
+        let mut response = BINDING_SUCCESS_RESPONSE.to_be_bytes().to_vec();
+        response.extend_from_slice(&12u16.to_be_bytes());
+        response.extend_from_slice(&MAGIC_COOKIE.to_be_bytes());
+        response.extend_from_slice(&transaction_id);
+        response.extend_from_slice(&[0, 1, 0, 8, 0, 1, 0x0d, 0x96, 203, 0, 113, 4]);
+        assert_eq!(
+            parse_binding_response(&response, &transaction_id),
+            Some(Ok("203.0.113.4:3478".parse().unwrap()))
+        );
+
+        let mut response = build_binding_request(&transaction_id);
+        response[..2].copy_from_slice(&BINDING_ERROR_RESPONSE.to_be_bytes());
+        assert!(matches!(
+            parse_binding_response(&response, &transaction_id),
+            Some(Err(_))
+        ));
+    }
+}
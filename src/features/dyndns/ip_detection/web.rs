@@ -0,0 +1,424 @@
+//! Detection of the public address using HTTP(S) echo services
+
+use std::net::IpAddr;
+
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+
+use crate::dns_providers::helpers::client_builder;
+
+use super::{IpDetectionConfig, IpFamily, Voter};
+
+/// List of services/URLs to get the public IP address from
+const IP_ADDRESSES_SERVICES: [&str; 9] = [
+    "https://api.ipify.org",
+    "https://checkip.amazonaws.com",
+    "https://ipinfo.io/ip",
+    "https://ifconfig.me/ip",
+    "https://icanhazip.com",
+    "https://ipecho.net/plain",
+    "https://myexternalip.com/raw",
+    "https://ident.me/",
+    "https://ip.tyk.nu/",
+];
+
+/// List of services/URLs to get the public IPv6 address from
+const IPV6_ADDRESSES_SERVICES: [&str; 4] = [
+    "https://api6.ipify.org",
+    "https://ipv6.icanhazip.com",
+    "https://v6.ident.me/",
+    "https://ipv6.wtfismyip.com/text",
+];
+
+/// A web service that returns the public address of the caller
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IpEchoService {
+    pub url: String,
+    /// For services that return JSON, the field that has the address. Nested fields are separated with dots, e.g., `data.ip`.
+    /// If not provided, the first address found in the response body is used, so bare addresses,
+    /// and addresses in text (e.g., `Current IP Address: 203.0.113.5`) work as well.
+    #[serde(default)]
+    pub json_field: Option<String>,
+}
+
+impl IpEchoService {
+    fn plain(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            json_field: None,
+        }
+    }
+}
+
+/// The configured services of the family, or the built-in ones if none are configured
+fn services(family: IpFamily, config: &IpDetectionConfig) -> Vec<IpEchoService> {
+    let (configured, defaults) = match family {
+        IpFamily::V4 => (&config.ipv4_services, IP_ADDRESSES_SERVICES.as_slice()),
+        IpFamily::V6 => (&config.ipv6_services, IPV6_ADDRESSES_SERVICES.as_slice()),
+    };
+
+    if configured.is_empty() {
+        defaults
+            .iter()
+            .map(|url| IpEchoService::plain(url))
+            .collect()
+    } else {
+        configured.clone()
+    }
+}
+
+/// Where the connections of the web detection leave from, e.g., to detect the address of one uplink of many
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LocalBinding {
+    /// The local (source) address of the connections
+    pub address: Option<IpAddr>,
+    /// The network interface the connections are bound to (SO_BINDTODEVICE, Linux only)
+    pub interface: Option<String>,
+}
+
+impl LocalBinding {
+    pub fn is_bound(&self) -> bool {
+        self.address.is_some() || self.interface.is_some()
+    }
+}
+
+/// Builds the client used to query the services, going through the proxy if provided.
+/// Without a proxy, the client is bound to the unspecified address of the family, which makes sure that
+/// the service sees an address of that family. With a proxy, the proxy's egress address is what the service sees.
+pub fn build_ip_detection_client(
+    proxy_address: Option<String>,
+    family: IpFamily,
+) -> reqwest::blocking::Client {
+    build_bound_ip_detection_client(proxy_address, family, &LocalBinding::default())
+}
+
+/// Like build_ip_detection_client, with the connections bound to a local address or interface.
+/// With a proxy, the binding applies to the connections to the proxy.
+pub fn build_bound_ip_detection_client(
+    proxy_address: Option<String>,
+    family: IpFamily,
+    binding: &LocalBinding,
+) -> reqwest::blocking::Client {
+    let builder = match (proxy_address, binding.address) {
+        (Some(proxy), address) => client_builder(Some(proxy)).local_address(address),
+        (None, address) => {
+            client_builder(None).local_address(address.unwrap_or(family.unspecified()))
+        }
+    };
+
+    #[cfg(target_os = "linux")]
+    let builder = match &binding.interface {
+        Some(interface) => builder.interface(interface),
+        None => builder,
+    };
+
+    builder.build().expect("IP detection client builder failed")
+}
+
+/// One voter per service, in a random order so that the load is spread among the services
+pub fn voters(
+    client: reqwest::blocking::Client,
+    family: IpFamily,
+    config: &IpDetectionConfig,
+) -> Vec<Voter> {
+    let mut services = services(family, config);
+
+    services.shuffle(&mut rand::rng());
+
+    services
+        .into_iter()
+        .map(|service| {
+            let client = client.clone();
+            Voter::new(service.url.clone(), move || {
+                get_my_ip_address_from_service(&client, &service, family)
+            })
+        })
+        .collect()
+}
+
+fn get_my_ip_address_from_service(
+    client: &reqwest::blocking::Client,
+    service: &IpEchoService,
+    family: IpFamily,
+) -> Result<IpAddr, Box<dyn std::error::Error>> {
+    let response = client.get(&service.url).send()?;
+
+    if response.status().is_success() {
+        let body = response.text()?;
+
+        let ip_address = parse_ip_address_from_body(&body, service.json_field.as_deref(), family)
+            .map_err(|e| {
+            eprintln!(
+                "Could not parse IP address from service {} with value {}. Error: {}",
+                service.url, body, e
+            );
+            std::io::Error::other(e)
+        })?;
+
+        return Ok(ip_address);
+    }
+
+    Err(Box::new(std::io::Error::other(format!(
+        "Service {} responded with status {}",
+        service.url,
+        response.status()
+    ))))
+}
+
+/// Finds the address of the family in a response body, either from a JSON field, or the first one found in the text
+fn parse_ip_address_from_body(
+    body: &str,
+    json_field: Option<&str>,
+    family: IpFamily,
+) -> Result<IpAddr, String> {
+    let ip_address = match json_field {
+        Some(field) => {
+            let json = serde_json::from_str::<serde_json::Value>(body)
+                .map_err(|e| format!("Invalid JSON: {}", e))?;
+
+            let value = field
+                .split('.')
+                .try_fold(&json, |v, key| v.get(key))
+                .and_then(|v| v.as_str())
+                .ok_or_else(|| format!("No string field `{}` in the JSON", field))?;
+
+            value
+                .trim()
+                .parse::<IpAddr>()
+                .map_err(|e| format!("Invalid address `{}` in `{}`: {}", value, field, e))?
+        }
+        None => body
+            .split(|c: char| !(c.is_ascii_hexdigit() || c == '.' || c == ':'))
+            // Only sentence punctuation is trimmed, as colons belong to IPv6 addresses like `2001:db8::`
+            .filter_map(|token| {
+                token
+                    .parse()
+                    .or_else(|_| token.trim_matches(['.', ',', ';']).parse())
+                    .ok()
+            })
+            .find(|a: &IpAddr| IpFamily::of(a) == family)
+            .ok_or_else(|| format!("No {} address found in the response", family))?,
+    };
+
+    if IpFamily::of(&ip_address) != family {
+        return Err(format!(
+            "The address {} is not an {} address",
+            ip_address, family
+        ));
+    }
+
+    Ok(ip_address)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+    };
+
+    use super::{
+        super::{IpSource, detect_ip_address},
+        *,
+    };
+
+    fn get_my_ip_address_from_url(
+        url: &str,
+        family: IpFamily,
+    ) -> Result<IpAddr, Box<dyn std::error::Error>> {
+        get_my_ip_address_from_service(
+            &build_ip_detection_client(None, family),
+            &IpEchoService::plain(url),
+            family,
+        )
+    }
+
+    #[test]
+    fn test_get_my_routable_ip_address() {
+        let ip_address = detect_ip_address(
+            &|family| build_ip_detection_client(None, family),
+            &[IpSource::Web],
+            IpFamily::V4,
+            &IpDetectionConfig::default(),
+        )
+        .unwrap();
+
+        let ip_addresses = IP_ADDRESSES_SERVICES
+            .iter()
+            .cloned()
+            .map(|url| get_my_ip_address_from_url(url, IpFamily::V4))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert!(
+            ip_addresses.into_iter().all(|v| v == ip_address),
+            "One of the IP addresses returned by the services is different from the others"
+        );
+
+        println!("My IP address is: {}", ip_address);
+    }
+
+    #[test]
+    fn test_parse_ip_address_from_body() {
+        let v4: IpAddr = "203.0.113.5".parse().unwrap();
+        let v6: IpAddr = "2001:db8::5".parse().unwrap();
+
+        assert_eq!(
+            parse_ip_address_from_body("203.0.113.5\n", None, IpFamily::V4),
+            Ok(v4)
+        );
+        assert_eq!(
+            parse_ip_address_from_body(
+                "<html><body>Current IP Address: 203.0.113.5</body></html>",
+                None,
+                IpFamily::V4
+            ),
+            Ok(v4)
+        );
+        assert_eq!(
+            parse_ip_address_from_body("Your IP is 2001:db8::5.", None, IpFamily::V6),
+            Ok(v6)
+        );
+        assert_eq!(
+            parse_ip_address_from_body(
+                r#"{"ip": "203.0.113.5", "country": "XX"}"#,
+                Some("ip"),
+                IpFamily::V4
+            ),
+            Ok(v4)
+        );
+        assert_eq!(
+            parse_ip_address_from_body(
+                r#"{"data": {"address": "2001:db8::5"}}"#,
+                Some("data.address"),
+                IpFamily::V6
+            ),
+            Ok(v6)
+        );
+        // Even without a field, the address is found in the JSON text
+        assert_eq!(
+            parse_ip_address_from_body(r#"{"ip":"203.0.113.5"}"#, None, IpFamily::V4),
+            Ok(v4)
+        );
+
+        let v6_network: IpAddr = "2001:db8::".parse().unwrap();
+        assert_eq!(
+            parse_ip_address_from_body("Your IP is 2001:db8::", None, IpFamily::V6),
+            Ok(v6_network)
+        );
+        assert_eq!(
+            parse_ip_address_from_body("Your IP is 2001:db8::.", None, IpFamily::V6),
+            Ok(v6_network)
+        );
+
+        assert!(parse_ip_address_from_body("203.0.113.5", None, IpFamily::V6).is_err());
+        assert!(parse_ip_address_from_body(r#"{"ip": 5}"#, Some("ip"), IpFamily::V4).is_err());
+        assert!(parse_ip_address_from_body("no address", None, IpFamily::V4).is_err());
+    }
+
+    #[test]
+    fn test_services() {
+        let config = IpDetectionConfig::default();
+        assert_eq!(
+            services(IpFamily::V4, &config).len(),
+            IP_ADDRESSES_SERVICES.len()
+        );
+
+        let config = IpDetectionConfig {
+            ipv4_services: vec![IpEchoService::plain("https://ip.example")],
+            ..Default::default()
+        };
+        assert_eq!(
+            services(IpFamily::V4, &config),
+            vec![IpEchoService::plain("https://ip.example")]
+        );
+        assert_eq!(
+            services(IpFamily::V6, &config).len(),
+            IPV6_ADDRESSES_SERVICES.len()
+        );
+    }
+
+    #[test]
+    fn test_detection_goes_through_proxy() {
+        // A stand-in HTTP proxy that answers every request with its own "egress" address
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy_url = format!("http://{}", listener.local_addr().unwrap());
+
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            let mut header_line = String::new();
+            while reader.read_line(&mut header_line).unwrap() > 2 {
+                header_line.clear();
+            }
+            let body = "198.51.100.77\n";
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .unwrap();
+            request_line
+        });
+
+        let config = IpDetectionConfig {
+            ipv4_services: vec![IpEchoService::plain("http://ip.example/")],
+            ..Default::default()
+        };
+        let ip_address = detect_ip_address(
+            &|family| build_ip_detection_client(Some(proxy_url.clone()), family),
+            &[IpSource::Web],
+            IpFamily::V4,
+            &config,
+        )
+        .unwrap();
+
+        // Proxied requests carry the absolute URL of the service
+        let request_line = server.join().unwrap();
+        assert!(request_line.starts_with("GET http://ip.example/ "));
+        assert_eq!(ip_address, "198.51.100.77".parse::<IpAddr>().unwrap());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_detection_is_bound_to_local_address() {
+        // A stand-in echo service that answers with the address the connection came from.
+        // On Linux, all of 127.0.0.0/8 is local, so 127.0.0.2 stands in for the address of a second uplink.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+
+        let server = std::thread::spawn(move || {
+            let (mut stream, peer) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+            let body = peer.ip().to_string();
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .unwrap();
+        });
+
+        let binding = LocalBinding {
+            address: Some("127.0.0.2".parse().unwrap()),
+            interface: None,
+        };
+        let ip_address = get_my_ip_address_from_service(
+            &build_bound_ip_detection_client(None, IpFamily::V4, &binding),
+            &IpEchoService::plain(&url),
+            IpFamily::V4,
+        )
+        .unwrap();
+
+        server.join().unwrap();
+        assert_eq!(ip_address, "127.0.0.2".parse::<IpAddr>().unwrap());
+    }
+}
@@ -0,0 +1,972 @@
+//! Dyndns jobs from the `dyndns` section of the config file, which update many names in many zones in one run
+
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    net::{IpAddr, Ipv6Addr},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    run_options::dyndns_run_options::FamilySelection,
+    traits::domain_control::{DnsRecord, DnsRecordType, DomainController},
+};
+
+use super::{
+    history::ChangeHistory,
+    hooks::{AddressChange, OnChangeAction, run_on_change_actions},
+    ip_detection::{
+        IpDetectionConfig, IpFamily, IpSource, IpSourceKind, detect_publishable_ip_address,
+        special_use::warn_if_behind_nat, web::LocalBinding,
+    },
+    logic::{RecordChange, fully_qualified_name, sync_record},
+    prefix::{PrefixHost, combine, detect_prefix},
+    value_source::ValueSource,
+};
+
+/// The detected address, or why it couldn't be detected
+type Detection = Result<IpAddr, String>;
+
+/// The value of a job with `value_from`, or why it couldn't be read
+type ValueDetection = Option<Result<String, String>>;
+
+/// What a detection depends on, to share it between the jobs that have the same
+type DetectionKey = (Vec<IpSource>, IpFamily, LocalBinding);
+
+/// The detected IPv6 prefix of a job with prefix hosts, or why it couldn't be detected
+type PrefixDetection = Option<Result<Ipv6Addr, String>>;
+
+/// The `dyndns` section of the config file
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct DynDnsConfig {
+    #[serde(default)]
+    pub jobs: Vec<DynDnsJob>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DynDnsJob {
+    /// A label for the summary. If not provided, the zone and the names are used.
+    #[serde(default)]
+    pub label: Option<String>,
+    /// The domain name (as in account) of the config file whose records are updated
+    pub zone: String,
+    /// The names (subdomains) in the zone to point to the public address
+    #[serde(default)]
+    pub names: Vec<String>,
+    #[serde(default)]
+    pub family: FamilySelection,
+    /// The TTL of the records that are created or updated. If not provided, the provider's default is used.
+    #[serde(default)]
+    pub ttl: Option<u32>,
+    /// Where the public address is detected from, tried in order
+    #[serde(default = "default_ip_sources")]
+    pub ip_source: Vec<IpSourceKind>,
+    /// The network interface for the `interface` source
+    #[serde(default)]
+    pub interface: Option<String>,
+    /// The local address the `web` detection connects from, to detect the public address of one uplink of many
+    #[serde(default)]
+    pub local_address: Option<IpAddr>,
+    /// The network interface the `web` detection connects through (Linux only), to detect the public
+    /// address of one uplink of many
+    #[serde(default)]
+    pub bind_interface: Option<String>,
+    /// Actions run after a record of the job was created or changed at the provider
+    #[serde(default)]
+    pub on_change: Vec<OnChangeAction>,
+    /// Hosts whose AAAA records are the current IPv6 prefix of `prefix_interface`, combined with their
+    /// interface identifiers. All of them are updated together when the ISP rotates the delegated prefix.
+    #[serde(default)]
+    pub prefix_hosts: Vec<PrefixHost>,
+    /// The network interface (usually the LAN side of the router) whose address has the current prefix
+    #[serde(default)]
+    pub prefix_interface: Option<String>,
+    /// The length of the prefix taken from `prefix_interface`, in bits
+    #[serde(default = "default_prefix_length")]
+    pub prefix_length: u8,
+    /// Where the value of the records of `names` comes from, instead of the public address,
+    /// e.g., `command: "wg show wg0 public-key"` or `file: "/etc/version"`
+    #[serde(default)]
+    pub value_from: Option<ValueSource>,
+    /// The type of the records with `value_from`. TXT if not provided.
+    #[serde(default)]
+    pub record_type: Option<DnsRecordType>,
+}
+
+fn default_ip_sources() -> Vec<IpSourceKind> {
+    vec![IpSourceKind::Web]
+}
+
+fn default_prefix_length() -> u8 {
+    64
+}
+
+impl DynDnsJob {
+    fn ip_sources(&self) -> Vec<IpSource> {
+        self.ip_source
+            .iter()
+            .map(|kind| IpSource::from_kind(*kind, self.interface.clone()))
+            .collect()
+    }
+
+    fn local_binding(&self) -> LocalBinding {
+        LocalBinding {
+            address: self.local_address,
+            interface: self.bind_interface.clone(),
+        }
+    }
+
+    /// The binding only applies to the web detection, so any other source would detect another uplink's address
+    fn check_local_binding(&self) -> Result<(), String> {
+        if !self.local_binding().is_bound() {
+            return Ok(());
+        }
+
+        if self.ip_source.iter().any(|kind| *kind != IpSourceKind::Web) {
+            return Err(
+                "local_address and bind_interface can only be used with the web ip_source"
+                    .to_string(),
+            );
+        }
+
+        if self.bind_interface.is_some() && !cfg!(target_os = "linux") {
+            return Err("bind_interface is only supported on Linux".to_string());
+        }
+
+        if let Some(address) = self.local_address
+            && self
+                .family
+                .families()
+                .iter()
+                .any(|family| *family != IpFamily::of(&address))
+        {
+            return Err(format!(
+                "local_address {} can't be used to detect the addresses of family {:?}",
+                address, self.family
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl Display for DynDnsJob {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.label {
+            Some(label) => write!(f, "{}", label),
+            None => {
+                let names = self
+                    .names
+                    .iter()
+                    .chain(self.prefix_hosts.iter().map(|h| &h.name))
+                    .map(String::as_str)
+                    .collect::<Vec<_>>();
+
+                write!(f, "{} ({})", self.zone, names.join(", "))
+            }
+        }
+    }
+}
+
+/// The result of a job, for the summary
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct JobReport {
+    pub updated: usize,
+    pub unchanged: usize,
+    pub failures: Vec<String>,
+}
+
+impl JobReport {
+    pub fn succeeded(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+impl Display for JobReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} updated, {} unchanged", self.updated, self.unchanged)?;
+
+        if !self.failures.is_empty() {
+            write!(f, ", failures: {}", self.failures.join("; "))?;
+        }
+
+        Ok(())
+    }
+}
+
+pub fn run_jobs(
+    client_maker: &dyn Fn() -> reqwest::blocking::Client,
+    ip_detection_client_maker: &dyn Fn(IpFamily, &LocalBinding) -> reqwest::blocking::Client,
+    jobs: &[DynDnsJob],
+    ip_detection_config: &IpDetectionConfig,
+    allow_bogon: bool,
+    history: Option<&ChangeHistory>,
+    domain_controllers: BTreeMap<String, Box<dyn DomainController>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if jobs.is_empty() {
+        return Err(Box::new(std::io::Error::other(
+            "No --account-domain-name and --subdomain provided, and no jobs in the dyndns section of the config file",
+        )));
+    }
+
+    let addresses = detect_job_addresses(jobs, &|sources, family, binding| {
+        let result = detect_publishable_ip_address(
+            &|family| ip_detection_client_maker(family, binding),
+            sources,
+            family,
+            ip_detection_config,
+            allow_bogon,
+        )
+        .map_err(|e| e.to_string());
+
+        // The default route says nothing about the uplinks of bound jobs
+        if let Ok(address) = &result
+            && !binding.is_bound()
+        {
+            warn_if_behind_nat(address);
+        }
+
+        result
+    });
+
+    let prefixes = detect_job_prefixes(jobs, &detect_prefix);
+    let values = read_job_values(jobs);
+
+    let reports = process_jobs(
+        client_maker,
+        jobs,
+        &addresses,
+        &prefixes,
+        &values,
+        &domain_controllers,
+        history,
+    );
+
+    println!("Dyndns summary:");
+    for (job, report) in jobs.iter().zip(&reports) {
+        let status = if report.succeeded() { " OK " } else { "FAIL" };
+        println!("  [{}] {}: {}", status, job, report);
+    }
+
+    let failed_count = reports.iter().filter(|r| !r.succeeded()).count();
+    if failed_count > 0 {
+        return Err(Box::new(std::io::Error::other(format!(
+            "{} out of {} dyndns jobs failed",
+            failed_count,
+            jobs.len()
+        ))));
+    }
+
+    Ok(())
+}
+
+/// Detects the addresses every job needs, once per source, family and binding combination.
+/// The result has, for every job, the detected address (or the error) of each of its families.
+fn detect_job_addresses(
+    jobs: &[DynDnsJob],
+    detect: &dyn Fn(&[IpSource], IpFamily, &LocalBinding) -> Detection,
+) -> Vec<Vec<(IpFamily, Detection)>> {
+    let mut detected: Vec<(DetectionKey, Detection)> = Vec::new();
+
+    jobs.iter()
+        .map(|job| {
+            // A job with only prefix hosts, or with values from elsewhere, doesn't need the public address.
+            // A record_type without value_from is reported when reading the values.
+            if job.names.is_empty() || job.value_from.is_some() || job.record_type.is_some() {
+                return Vec::new();
+            }
+
+            let sources = job.ip_sources();
+            let binding = job.local_binding();
+            let binding_check = job.check_local_binding();
+
+            job.family
+                .families()
+                .into_iter()
+                .map(|family| {
+                    if let Err(e) = &binding_check {
+                        return (family, Err(e.clone()));
+                    }
+
+                    let key = (sources.clone(), family, binding.clone());
+                    let result = match detected.iter().find(|(k, _)| *k == key) {
+                        Some((_, result)) => result.clone(),
+                        None => {
+                            let result = detect(&sources, family, &binding);
+                            detected.push((key, result.clone()));
+                            result
+                        }
+                    };
+
+                    (family, result)
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Detects the prefix of every job with prefix hosts, once per interface
+fn detect_job_prefixes(
+    jobs: &[DynDnsJob],
+    detect: &dyn Fn(&str) -> Result<Ipv6Addr, String>,
+) -> Vec<PrefixDetection> {
+    let mut detected: BTreeMap<String, Result<Ipv6Addr, String>> = BTreeMap::new();
+
+    jobs.iter()
+        .map(|job| {
+            if job.prefix_hosts.is_empty() {
+                return None;
+            }
+
+            let Some(interface) = &job.prefix_interface else {
+                return Some(Err("prefix_hosts require a prefix_interface".to_string()));
+            };
+            if job.prefix_length > 128 {
+                return Some(Err(format!(
+                    "prefix_length {} is longer than an IPv6 address",
+                    job.prefix_length
+                )));
+            }
+
+            let result = detected
+                .entry(interface.clone())
+                .or_insert_with(|| detect(interface));
+
+            Some(result.clone())
+        })
+        .collect()
+}
+
+/// Reads the value of every job with `value_from`
+fn read_job_values(jobs: &[DynDnsJob]) -> Vec<ValueDetection> {
+    jobs.iter()
+        .map(|job| match (&job.value_from, job.record_type) {
+            (Some(value_from), _) => Some(value_from.read()),
+            (None, Some(_)) => Some(Err("record_type requires value_from".to_string())),
+            (None, None) => None,
+        })
+        .collect()
+}
+
+/// Makes the listing of the zone reflect a successful sync_record, which leaves exactly one record of the
+/// name and type with the value, so that later jobs setting the same record see it instead of a stale listing
+fn update_listing(
+    records: &mut Vec<DnsRecord>,
+    name: &str,
+    record_type: DnsRecordType,
+    value: &str,
+    ttl: Option<u32>,
+) {
+    records.retain(|r| !(r.name == name && r.record_type == record_type));
+    records.push(DnsRecord {
+        name: name.to_string(),
+        record_type,
+        value: value.to_string(),
+        ttl,
+        priority: None,
+    });
+}
+
+/// Sets the records of all jobs, listing the records of every zone only once
+fn process_jobs(
+    client_maker: &dyn Fn() -> reqwest::blocking::Client,
+    jobs: &[DynDnsJob],
+    addresses: &[Vec<(IpFamily, Detection)>],
+    prefixes: &[PrefixDetection],
+    values: &[ValueDetection],
+    domain_controllers: &BTreeMap<String, Box<dyn DomainController>>,
+    history: Option<&ChangeHistory>,
+) -> Vec<JobReport> {
+    let mut reports = vec![JobReport::default(); jobs.len()];
+    let mut zone_records: BTreeMap<&str, Result<Vec<DnsRecord>, String>> = BTreeMap::new();
+
+    for ((((job, job_addresses), prefix), value), report) in jobs
+        .iter()
+        .zip(addresses)
+        .zip(prefixes)
+        .zip(values)
+        .zip(reports.iter_mut())
+    {
+        let Some(domain_controller) = domain_controllers.get(&job.zone) else {
+            report
+                .failures
+                .push(format!("Zone {} not found in config", job.zone));
+            continue;
+        };
+
+        let detected = job_addresses
+            .iter()
+            .filter_map(|(family, result)| match result {
+                Ok(ip_address) => Some(*ip_address),
+                Err(e) => {
+                    report.failures.push(format!(
+                        "Could not detect the public {} address: {}",
+                        family, e
+                    ));
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+
+        // The records to set: the names at the public addresses (or at the value from elsewhere),
+        // and the prefix hosts at their addresses, with where their values come from for the history
+        let ip_sources = job
+            .ip_sources()
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        let ip_sources = &ip_sources;
+        let mut targets = job
+            .names
+            .iter()
+            .flat_map(|name| {
+                detected.iter().map(move |ip_address| {
+                    (
+                        name,
+                        IpFamily::of(ip_address).record_type(),
+                        ip_address.to_string(),
+                        ip_sources.clone(),
+                    )
+                })
+            })
+            .collect::<Vec<_>>();
+
+        match (value, &job.value_from) {
+            (Some(Ok(value)), Some(value_from)) => {
+                let record_type = job.record_type.unwrap_or(DnsRecordType::TXT);
+                targets.extend(job.names.iter().map(|name| {
+                    (
+                        name,
+                        record_type,
+                        value.clone(),
+                        vec![value_from.to_string()],
+                    )
+                }));
+            }
+            (Some(Err(e)), _) => report
+                .failures
+                .push(format!("Could not read the value: {}", e)),
+            _ => {}
+        }
+
+        match prefix {
+            Some(Ok(prefix)) => targets.extend(job.prefix_hosts.iter().map(|host| {
+                let address = combine(*prefix, job.prefix_length, host.interface_id);
+                let source = format!(
+                    "prefix of {}",
+                    job.prefix_interface.as_deref().unwrap_or_default()
+                );
+                (
+                    &host.name,
+                    DnsRecordType::AAAA,
+                    address.to_string(),
+                    vec![source],
+                )
+            })),
+            Some(Err(e)) => report
+                .failures
+                .push(format!("Could not detect the IPv6 prefix: {}", e)),
+            None => {}
+        }
+
+        if targets.is_empty() {
+            continue;
+        }
+
+        let records = zone_records.entry(&job.zone).or_insert_with(|| {
+            domain_controller
+                .list_dns_records(client_maker)
+                .map_err(|e| e.to_string())
+        });
+
+        let records = match records {
+            Ok(records) => records,
+            Err(e) => {
+                report.failures.push(format!(
+                    "Could not list the records of zone {}: {}",
+                    job.zone, e
+                ));
+                continue;
+            }
+        };
+
+        for (name, record_type, value, sources) in &targets {
+            let outcome = sync_record(
+                client_maker,
+                domain_controller.as_ref(),
+                records,
+                name,
+                *record_type,
+                value,
+                job.ttl,
+            );
+
+            if outcome.is_ok() {
+                update_listing(records, name, *record_type, value, job.ttl);
+            }
+
+            if let Some(history) = history {
+                history.record(
+                    &fully_qualified_name(name, &job.zone),
+                    *record_type,
+                    value,
+                    sources,
+                    &outcome,
+                );
+            }
+
+            match outcome {
+                Ok(RecordChange::Unchanged) => report.unchanged += 1,
+                Ok(change) => {
+                    report.updated += 1;
+
+                    let change = AddressChange {
+                        record: fully_qualified_name(name, &job.zone),
+                        record_type: *record_type,
+                        old_ip: match change {
+                            RecordChange::Updated { old_value } => Some(old_value),
+                            _ => None,
+                        },
+                        new_ip: value.clone(),
+                    };
+                    report
+                        .failures
+                        .extend(run_on_change_actions(&job.on_change, &change));
+                }
+                Err(e) => report
+                    .failures
+                    .push(format!("{} ({}): {}", name, record_type, e)),
+            }
+        }
+    }
+
+    reports
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use crate::{
+        dns_providers::{helpers::build_client, in_memory::InMemory},
+        traits::domain_control::DnsRecordType,
+    };
+
+    use super::*;
+
+    fn job(zone: &str, names: &[&str], family: FamilySelection) -> DynDnsJob {
+        DynDnsJob {
+            label: None,
+            zone: zone.to_string(),
+            names: names.iter().map(|n| n.to_string()).collect(),
+            family,
+            ttl: Some(120),
+            ip_source: default_ip_sources(),
+            interface: None,
+            local_address: None,
+            bind_interface: None,
+            on_change: Vec::new(),
+            prefix_hosts: Vec::new(),
+            prefix_interface: None,
+            prefix_length: default_prefix_length(),
+            value_from: None,
+            record_type: None,
+        }
+    }
+
+    #[test]
+    fn test_jobs_config_parsing() {
+        let config: DynDnsConfig = serde_yml::from_str(
+            r#"
+jobs:
+  - label: "home"
+    zone: "example.com"
+    names: ["home", "nas"]
+    family: "both"
+    ttl: 300
+    ip_source: ["interface", "web"]
+    interface: "ppp0"
+  - zone: "example.net"
+    names: ["vpn"]
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.jobs.len(), 2);
+        assert_eq!(config.jobs[0].family, FamilySelection::Both);
+        assert_eq!(
+            config.jobs[0].ip_sources(),
+            vec![
+                IpSource::Interface {
+                    name: Some("ppp0".to_string())
+                },
+                IpSource::Web
+            ]
+        );
+        assert_eq!(config.jobs[1].family, FamilySelection::V4);
+        assert_eq!(config.jobs[1].ttl, None);
+        assert_eq!(config.jobs[1].ip_sources(), vec![IpSource::Web]);
+        assert_eq!(config.jobs[1].to_string(), "example.net (vpn)");
+    }
+
+    #[test]
+    fn test_detection_is_shared_between_jobs() {
+        let jobs = vec![
+            job("example.com", &["a"], FamilySelection::Both),
+            job("example.net", &["b"], FamilySelection::V4),
+        ];
+
+        let calls = RefCell::new(Vec::new());
+        let addresses = detect_job_addresses(&jobs, &|_, family, _| {
+            calls.borrow_mut().push(family);
+            match family {
+                IpFamily::V4 => Ok("203.0.113.1".parse().unwrap()),
+                IpFamily::V6 => Err("No IPv6".to_string()),
+            }
+        });
+
+        assert_eq!(*calls.borrow(), vec![IpFamily::V4, IpFamily::V6]);
+        assert_eq!(addresses[0].len(), 2);
+        assert_eq!(addresses[1].len(), 1);
+        assert!(addresses[1][0].1.is_ok());
+    }
+
+    #[test]
+    fn test_process_jobs() {
+        let example_com = InMemory::default();
+        let example_net = InMemory::default();
+        let mut domain_controllers: BTreeMap<String, Box<dyn DomainController>> = BTreeMap::new();
+        domain_controllers.insert("example.com".to_string(), Box::new(example_com.clone()));
+        domain_controllers.insert("example.net".to_string(), Box::new(example_net.clone()));
+
+        let client_maker = || build_client(None);
+        example_com
+            .add_dns_record(&client_maker, "a", DnsRecordType::A, "203.0.113.1")
+            .unwrap();
+
+        let jobs = vec![
+            job("example.com", &["a", "b"], FamilySelection::Both),
+            job("example.com", &["c"], FamilySelection::V4),
+            job("example.net", &["d"], FamilySelection::V4),
+            job("example.org", &["e"], FamilySelection::V4),
+        ];
+        let v4: Detection = Ok("203.0.113.1".parse().unwrap());
+        let addresses = vec![
+            vec![
+                (IpFamily::V4, v4.clone()),
+                (IpFamily::V6, Err("No IPv6".to_string())),
+            ],
+            vec![(IpFamily::V4, v4.clone())],
+            vec![(IpFamily::V4, v4.clone())],
+            vec![(IpFamily::V4, v4)],
+        ];
+
+        let reports = process_jobs(
+            &client_maker,
+            &jobs,
+            &addresses,
+            &vec![None; jobs.len()],
+            &vec![None; jobs.len()],
+            &domain_controllers,
+            None,
+        );
+
+        // One listing per zone
+        assert_eq!(example_com.list_calls(), 1);
+        assert_eq!(example_net.list_calls(), 1);
+
+        assert_eq!(reports[0].updated, 1);
+        assert_eq!(reports[0].unchanged, 1);
+        assert_eq!(reports[0].failures.len(), 1);
+        assert!(reports[1].succeeded());
+        assert_eq!(reports[1].updated, 1);
+        assert!(reports[2].succeeded());
+        assert!(!reports[3].succeeded());
+
+        let names = example_com
+            .records()
+            .into_iter()
+            .map(|r| r.name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["a", "b", "c"]);
+        assert_eq!(example_net.records()[0].name, "d");
+    }
+
+    #[test]
+    fn test_jobs_setting_the_same_record() {
+        let zone = InMemory::default();
+        let mut domain_controllers: BTreeMap<String, Box<dyn DomainController>> = BTreeMap::new();
+        domain_controllers.insert("example.com".to_string(), Box::new(zone.clone()));
+        let client_maker = || build_client(None);
+
+        let jobs = vec![
+            job("example.com", &["home"], FamilySelection::V4),
+            job("example.com", &["home"], FamilySelection::V4),
+            job("example.com", &["home"], FamilySelection::V4),
+        ];
+        let addresses = ["203.0.113.1", "203.0.113.1", "203.0.113.2"]
+            .iter()
+            .map(|a| vec![(IpFamily::V4, Ok(a.parse().unwrap()))])
+            .collect::<Vec<_>>();
+
+        let reports = process_jobs(
+            &client_maker,
+            &jobs,
+            &addresses,
+            &vec![None; jobs.len()],
+            &vec![None; jobs.len()],
+            &domain_controllers,
+            None,
+        );
+
+        // The later jobs see what the earlier ones set, instead of the listing from before the run
+        assert_eq!(zone.list_calls(), 1);
+        assert_eq!(reports[0].updated, 1);
+        assert_eq!(reports[1].unchanged, 1);
+        assert_eq!(reports[2].updated, 1);
+        assert!(reports.iter().all(JobReport::succeeded));
+        assert_eq!(
+            zone.records()
+                .into_iter()
+                .map(|r| r.value)
+                .collect::<Vec<_>>(),
+            vec!["203.0.113.2"]
+        );
+    }
+
+    #[test]
+    fn test_on_change_runs_only_on_change() {
+        let zone = InMemory::default();
+        let mut domain_controllers: BTreeMap<String, Box<dyn DomainController>> = BTreeMap::new();
+        domain_controllers.insert("example.com".to_string(), Box::new(zone.clone()));
+
+        let output_path = std::env::temp_dir().join(format!(
+            "sam-dns-tools-jobs-on-change-{}.txt",
+            std::process::id()
+        ));
+
+        let mut jobs = vec![job("example.com", &["home", "@"], FamilySelection::V4)];
+        jobs[0].on_change = vec![OnChangeAction::Command {
+            command: format!(
+                "echo \"$RECORD $OLD_IP>$NEW_IP\" >> {}",
+                output_path.display()
+            ),
+        }];
+
+        let client_maker = || build_client(None);
+        let run = |address: &str| {
+            let addresses = vec![vec![(IpFamily::V4, Ok(address.parse().unwrap()))]];
+            process_jobs(
+                &client_maker,
+                &jobs,
+                &addresses,
+                &vec![None; jobs.len()],
+                &vec![None; jobs.len()],
+                &domain_controllers,
+                None,
+            )
+        };
+
+        assert!(run("203.0.113.1")[0].succeeded());
+        assert!(run("203.0.113.1")[0].succeeded());
+        assert!(run("203.0.113.2")[0].succeeded());
+
+        assert_eq!(
+            std::fs::read_to_string(&output_path).unwrap(),
+            "home.example.com >203.0.113.1\n\
+             example.com >203.0.113.1\n\
+             home.example.com 203.0.113.1>203.0.113.2\n\
+             example.com 203.0.113.1>203.0.113.2\n"
+        );
+
+        std::fs::remove_file(output_path).unwrap();
+    }
+
+    #[test]
+    fn test_prefix_hosts() {
+        let mut jobs: Vec<DynDnsJob> = serde_yml::from_str(
+            r#"
+- zone: "example.com"
+  prefix_interface: "br-lan"
+  prefix_hosts:
+    - name: "nas"
+      interface_id: "::1234:5678"
+    - name: "printer"
+      interface_id: "::a"
+- zone: "example.com"
+  prefix_interface: "br-lan"
+  prefix_length: 56
+  prefix_hosts:
+    - name: "camera"
+      interface_id: "::ff:0:0:0:1"
+"#,
+        )
+        .unwrap();
+        jobs.push(job("example.com", &["home"], FamilySelection::V4));
+
+        assert_eq!(jobs[0].prefix_length, 64);
+        assert_eq!(jobs[0].to_string(), "example.com (nas, printer)");
+
+        // Jobs with only prefix hosts don't detect the public address, and the prefix is detected once
+        let addresses = detect_job_addresses(&jobs, &|_, _, _| Ok("203.0.113.1".parse().unwrap()));
+        assert!(addresses[0].is_empty());
+        assert_eq!(addresses[2].len(), 1);
+
+        let calls = RefCell::new(0);
+        let prefixes = detect_job_prefixes(&jobs, &|interface| {
+            assert_eq!(interface, "br-lan");
+            *calls.borrow_mut() += 1;
+            Ok("2001:db8:1234:5600::1".parse().unwrap())
+        });
+        assert_eq!(*calls.borrow(), 1);
+        assert_eq!(prefixes[2], None);
+
+        let zone = InMemory::default();
+        let mut domain_controllers: BTreeMap<String, Box<dyn DomainController>> = BTreeMap::new();
+        domain_controllers.insert("example.com".to_string(), Box::new(zone.clone()));
+        let client_maker = || build_client(None);
+
+        let reports = process_jobs(
+            &client_maker,
+            &jobs,
+            &addresses,
+            &prefixes,
+            &vec![None; jobs.len()],
+            &domain_controllers,
+            None,
+        );
+        assert!(reports.iter().all(|r| r.succeeded()));
+        assert_eq!(zone.list_calls(), 1);
+
+        let records = zone
+            .records()
+            .into_iter()
+            .map(|r| (r.name, r.value))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            records,
+            vec![
+                (
+                    "nas".to_string(),
+                    "2001:db8:1234:5600::1234:5678".to_string()
+                ),
+                ("printer".to_string(), "2001:db8:1234:5600::a".to_string()),
+                ("camera".to_string(), "2001:db8:1234:56ff::1".to_string()),
+                ("home".to_string(), "203.0.113.1".to_string()),
+            ]
+        );
+
+        // Without an interface, the job fails
+        jobs[0].prefix_interface = None;
+        let prefixes = detect_job_prefixes(&jobs, &|_| Err("No IPv6".to_string()));
+        assert!(matches!(prefixes[0], Some(Err(_))));
+    }
+
+    #[test]
+    fn test_jobs_bound_to_uplinks() {
+        let mut wan1 = job("example.com", &["wan1"], FamilySelection::V4);
+        wan1.local_address = Some("192.0.2.10".parse().unwrap());
+        let mut wan2 = job("example.com", &["wan2"], FamilySelection::V4);
+        wan2.bind_interface = Some("eth2".to_string());
+        let unbound = job("example.com", &["office"], FamilySelection::V4);
+        let mut wrong_family = job("example.com", &["wan1-v6"], FamilySelection::V6);
+        wrong_family.local_address = Some("192.0.2.10".parse().unwrap());
+        let mut wrong_source = wan1.clone();
+        wrong_source.ip_source = vec![IpSourceKind::Stun];
+
+        let jobs = vec![wan1, wan2, unbound, wrong_family, wrong_source];
+
+        let calls = RefCell::new(0);
+        let addresses = detect_job_addresses(&jobs, &|_, _, binding| {
+            *calls.borrow_mut() += 1;
+            match (binding.address, binding.interface.as_deref()) {
+                (Some(_), _) => Ok("203.0.113.1".parse().unwrap()),
+                (None, Some("eth2")) => Ok("198.51.100.2".parse().unwrap()),
+                _ => Ok("203.0.113.9".parse().unwrap()),
+            }
+        });
+
+        // Every uplink is detected on its own
+        assert_eq!(*calls.borrow(), 3);
+        assert_eq!(addresses[0][0].1, Ok("203.0.113.1".parse().unwrap()));
+        assert_eq!(addresses[1][0].1, Ok("198.51.100.2".parse().unwrap()));
+        assert_eq!(addresses[2][0].1, Ok("203.0.113.9".parse().unwrap()));
+        assert!(addresses[3][0].1.is_err());
+        assert!(addresses[4][0].1.is_err());
+    }
+
+    #[test]
+    fn test_value_jobs() {
+        let mut jobs: Vec<DynDnsJob> = serde_yml::from_str(
+            r#"
+- zone: "example.com"
+  names: ["_wg"]
+  value_from:
+    command: "echo ' key1 '"
+- zone: "example.com"
+  names: ["mail"]
+  record_type: "CNAME"
+  value_from:
+    command: "echo mx.example.net."
+- zone: "example.com"
+  names: ["broken"]
+  value_from:
+    command: "exit 1"
+- zone: "example.com"
+  names: ["typed"]
+  record_type: "TXT"
+"#,
+        )
+        .unwrap();
+
+        // Jobs with values from elsewhere don't detect the public address
+        let addresses = detect_job_addresses(&jobs, &|_, _, _| unreachable!());
+        assert!(addresses.iter().all(|a| a.is_empty()));
+
+        let zone = InMemory::default();
+        let mut domain_controllers: BTreeMap<String, Box<dyn DomainController>> = BTreeMap::new();
+        domain_controllers.insert("example.com".to_string(), Box::new(zone.clone()));
+        let client_maker = || build_client(None);
+        let run = |jobs: &[DynDnsJob]| {
+            process_jobs(
+                &client_maker,
+                jobs,
+                &addresses,
+                &vec![None; jobs.len()],
+                &read_job_values(jobs),
+                &domain_controllers,
+                None,
+            )
+        };
+
+        let reports = run(&jobs);
+        assert!(reports[0].succeeded() && reports[1].succeeded());
+        assert!(!reports[2].succeeded());
+        assert!(reports[3].failures[0].contains("requires value_from"));
+
+        // An unchanged value isn't set again, a changed one replaces the record
+        run(&jobs);
+        jobs[0].value_from = Some(ValueSource::Command {
+            command: "echo key2".to_string(),
+        });
+        run(&jobs);
+
+        let records = zone
+            .records()
+            .into_iter()
+            .map(|r| (r.name, r.record_type, r.value))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            records,
+            vec![
+                (
+                    "mail".to_string(),
+                    DnsRecordType::CNAME,
+                    "mx.example.net.".to_string()
+                ),
+                ("_wg".to_string(), DnsRecordType::TXT, "key2".to_string()),
+            ]
+        );
+    }
+}
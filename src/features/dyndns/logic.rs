@@ -1,28 +1,30 @@
-use std::{collections::BTreeMap, net::Ipv4Addr};
-
-use rand::seq::SliceRandom;
+use std::{collections::BTreeMap, net::IpAddr};
 
 use crate::{
-    run_options::dyndns_run_options::SimplifiedDynDnsRunOptions,
+    dns_providers::helpers::record_value_matches,
+    run_options::dyndns_run_options::{FamilySelection, SimplifiedDynDnsRunOptions},
     traits::domain_control::{DnsRecord, DnsRecordType, DomainController},
 };
 
-/// List of services/URLs to get the public IP address from
-const IP_ADDRESSES_SERVICES: [&str; 9] = [
-    "https://api.ipify.org",
-    "https://checkip.amazonaws.com",
-    "https://ipinfo.io/ip",
-    "https://ifconfig.me/ip",
-    "https://icanhazip.com",
-    "https://ipecho.net/plain",
-    "https://myexternalip.com/raw",
-    "https://ident.me/",
-    "https://ip.tyk.nu/",
-];
+use super::ip_detection::{
+    IpDetectionConfig, IpFamily, detect_publishable_ip_address, special_use::warn_if_behind_nat,
+};
+
+impl FamilySelection {
+    pub fn families(&self) -> Vec<IpFamily> {
+        match self {
+            FamilySelection::V4 => vec![IpFamily::V4],
+            FamilySelection::V6 => vec![IpFamily::V6],
+            FamilySelection::Both => vec![IpFamily::V4, IpFamily::V6],
+        }
+    }
+}
 
 pub fn run_regular(
     client_maker: &dyn Fn() -> reqwest::blocking::Client,
+    ip_detection_client_maker: &dyn Fn(IpFamily) -> reqwest::blocking::Client,
     args: SimplifiedDynDnsRunOptions,
+    ip_detection_config: &IpDetectionConfig,
     domain_controllers: BTreeMap<String, Box<dyn DomainController>>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let domain_controller = domain_controllers
@@ -34,176 +36,326 @@ pub fn run_regular(
             )
         });
 
-    set_ipv4_record(client_maker, domain_controller.as_ref(), &args.subdomain)?;
+    let mut detected_count = 0;
+    let mut failures = Vec::new();
+
+    let sources = args
+        .ip_sources
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>();
+    let ip_sources = sources.join(", ");
+
+    // Every family is handled on its own, so that a failure in one doesn't block the others
+    for family in args.family.families() {
+        let my_ip_address = match detect_publishable_ip_address(
+            ip_detection_client_maker,
+            &args.ip_sources,
+            family,
+            ip_detection_config,
+            args.allow_bogon,
+        ) {
+            Ok(ip_address) => ip_address,
+            Err(e) => {
+                eprintln!(
+                    "Could not detect the public {} address from {}, skipping its record: {}",
+                    family, ip_sources, e
+                );
+                continue;
+            }
+        };
+
+        detected_count += 1;
+
+        warn_if_behind_nat(&my_ip_address);
+
+        let outcome = set_ip_record(
+            client_maker,
+            domain_controller.as_ref(),
+            &args.subdomain,
+            my_ip_address,
+        );
+
+        if let Some(history) = &args.history {
+            history.record(
+                &fully_qualified_name(&args.subdomain, &args.account_domain_name),
+                family.record_type(),
+                &my_ip_address.to_string(),
+                &sources,
+                &outcome,
+            );
+        }
+
+        if let Err(e) = outcome {
+            eprintln!(
+                "Failed to update the {} record of `{}`: {}",
+                family.record_type(),
+                args.subdomain,
+                e
+            );
+            failures.push(format!("{}: {}", family.record_type(), e));
+        }
+    }
+
+    if detected_count == 0 {
+        return Err(Box::new(std::io::Error::other(
+            "Could not detect the public IP address of any of the requested families",
+        )));
+    }
+
+    if !failures.is_empty() {
+        return Err(Box::new(std::io::Error::other(format!(
+            "Failed to update records: {}",
+            failures.join("; ")
+        ))));
+    }
 
     println!("DynDns end reached. If nothing was printed, the record was already set correctly.\n");
 
     Ok(())
 }
 
-fn set_ipv4_record(
+/// Sets the A or AAAA record (depending on the address family) of the subdomain to the provided address
+pub fn set_ip_record(
     client_maker: &dyn Fn() -> reqwest::blocking::Client,
     domain_controller: &dyn DomainController,
     subdomain: &str,
-) -> Result<(), Box<dyn std::error::Error>> {
-    const DNS_RECORD_TYPE: DnsRecordType = DnsRecordType::A;
+    my_ip_address: IpAddr,
+) -> Result<RecordChange, Box<dyn std::error::Error>> {
+    println!("Found routable ip address: {}", my_ip_address);
 
-    let my_ip_address = get_my_routable_ip_address()?;
+    let records = domain_controller.list_dns_records(client_maker)?;
 
-    println!("Found local routable ip address: {}", my_ip_address);
+    sync_ip_record(
+        client_maker,
+        domain_controller,
+        &records,
+        subdomain,
+        my_ip_address,
+        None,
+    )
+}
 
-    let records = domain_controller.list_dns_records(client_maker)?;
+/// The name of the record in the zone, e.g., `home.example.com`, or the zone itself for `@`
+pub fn fully_qualified_name(name: &str, zone: &str) -> String {
+    match name {
+        "" | "@" => zone.to_string(),
+        name => format!("{}.{}", name, zone),
+    }
+}
 
-    let current_ipv4_records = records
-        .iter()
-        .filter(|r| r.name == subdomain && r.record_type == DNS_RECORD_TYPE)
-        .collect::<Vec<&DnsRecord>>();
+/// What sync_record did to the record
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordChange {
+    /// The record already had the value (duplicates may have been removed)
+    Unchanged,
+    Created,
+    Updated {
+        old_value: String,
+    },
+}
 
-    remove_reduntant_records(
+/// Like set_ip_record, but with the records of the zone already listed, so that many records can be
+/// set with one listing. The TTL, if provided, is used for the records that are created.
+pub fn sync_ip_record(
+    client_maker: &dyn Fn() -> reqwest::blocking::Client,
+    domain_controller: &dyn DomainController,
+    records: &[DnsRecord],
+    subdomain: &str,
+    my_ip_address: IpAddr,
+    ttl: Option<u32>,
+) -> Result<RecordChange, Box<dyn std::error::Error>> {
+    sync_record(
+        client_maker,
         domain_controller,
+        records,
         subdomain,
-        &current_ipv4_records,
-        client_maker,
-    )?;
+        IpFamily::of(&my_ip_address).record_type(),
+        &my_ip_address.to_string(),
+        ttl,
+    )
+}
 
-    // Check if the first record is the same as the current IP address
-    if let Some(record) = current_ipv4_records.first() {
-        if record.value != my_ip_address.to_string() {
-            println!(
-                "Record `{}` found but its value is {}. Setting it to the current IP address {}",
-                subdomain, record.value, my_ip_address
-            );
+/// Sets the record of the subdomain and type to the value, if it doesn't have it already,
+/// with the records of the zone already listed. Works for any record type, not only addresses.
+pub fn sync_record(
+    client_maker: &dyn Fn() -> reqwest::blocking::Client,
+    domain_controller: &dyn DomainController,
+    records: &[DnsRecord],
+    subdomain: &str,
+    dns_record_type: DnsRecordType,
+    value: &str,
+    ttl: Option<u32>,
+) -> Result<RecordChange, Box<dyn std::error::Error>> {
+    let add_record = || match ttl {
+        Some(ttl) => domain_controller.add_dns_record_with_ttl(
+            client_maker,
+            subdomain,
+            dns_record_type,
+            value,
+            ttl,
+        ),
+        None => domain_controller.add_dns_record(client_maker, subdomain, dns_record_type, value),
+    };
 
-            // Remove the current incorrect record
-            domain_controller.remove_dns_record(
-                client_maker,
-                subdomain,
-                DNS_RECORD_TYPE,
-                Some(&record.value),
-            )?;
-
-            // Record found, but it's value is different. Update it.
-            domain_controller.add_dns_record(
-                client_maker,
-                subdomain,
-                DNS_RECORD_TYPE,
-                &my_ip_address.to_string(),
-            )?;
+    let current_records = records
+        .iter()
+        .filter(|r| r.name == subdomain && r.record_type == dns_record_type)
+        .collect::<Vec<&DnsRecord>>();
+
+    remove_reduntant_records(domain_controller, subdomain, &current_records, client_maker)?;
+
+    // Check if the first record already has the value
+    if let Some(record) = current_records.first() {
+        if record_value_matches(dns_record_type, &record.value, value) {
+            return Ok(RecordChange::Unchanged);
         }
-    } else {
+
         println!(
-            "Record `{}` not found. Setting it to the current IP address {}",
-            subdomain, my_ip_address
+            "Record `{}` ({}) found but its value is {}. Setting it to {}",
+            subdomain, dns_record_type, record.value, value
         );
 
-        // No record found, create one
-        domain_controller.add_dns_record(
+        // Remove the current incorrect record
+        domain_controller.remove_dns_record(
             client_maker,
             subdomain,
-            DNS_RECORD_TYPE,
-            &my_ip_address.to_string(),
+            dns_record_type,
+            Some(&record.value),
         )?;
-    }
 
-    Ok(())
+        // Record found, but it's value is different. Update it.
+        add_record()?;
+
+        Ok(RecordChange::Updated {
+            old_value: record.value.clone(),
+        })
+    } else {
+        println!(
+            "Record `{}` ({}) not found. Setting it to {}",
+            subdomain, dns_record_type, value
+        );
+
+        // No record found, create one
+        add_record()?;
+
+        Ok(RecordChange::Created)
+    }
 }
 
 fn remove_reduntant_records(
     domain_controller: &dyn DomainController,
     subdomain: &str,
-    current_ipv4_records: &Vec<&DnsRecord>,
+    current_ip_records: &Vec<&DnsRecord>,
     client_maker: &dyn Fn() -> reqwest::blocking::Client,
 ) -> Result<(), Box<dyn std::error::Error>> {
     // Remove all records except the first one. Duplicates are bad.
-    if current_ipv4_records.len() > 1 {
+    if current_ip_records.len() > 1 {
         println!(
             "Found {} records for `{}`. Removing all except the first one",
-            current_ipv4_records.len(),
+            current_ip_records.len(),
             subdomain
         );
     }
-    for record in current_ipv4_records.iter().skip(1) {
+    let Some(first) = current_ip_records.first() else {
+        return Ok(());
+    };
+    for record in current_ip_records.iter().skip(1) {
+        // Providers remove by value, so removing a duplicate of the first record would remove the first one too
+        if record_value_matches(record.record_type, &record.value, &first.value) {
+            println!(
+                "Keeping redundant record `{}` with value {}, as it has the value of the first one",
+                record.name, record.value
+            );
+            continue;
+        }
+
         println!(
             "Removing redundant record `{}` with value {}",
             record.name, record.value
         );
 
-        domain_controller
-            .remove_dns_record(
-                client_maker,
-                subdomain,
-                record.record_type,
-                Some(&record.value),
-            )
-            .unwrap_or_else(|e| panic!("Failed to remove redundant record {}: {}", record.name, e));
+        domain_controller.remove_dns_record(
+            client_maker,
+            subdomain,
+            record.record_type,
+            Some(&record.value),
+        )?;
     }
 
     Ok(())
 }
 
-fn get_my_routable_ip_address() -> Result<Ipv4Addr, Box<dyn std::error::Error>> {
-    let services = {
-        let mut services = IP_ADDRESSES_SERVICES.to_vec();
-
-        services.shuffle(&mut rand::rng());
-
-        services
+#[cfg(test)]
+mod tests {
+    use crate::{
+        dns_providers::{helpers::build_client, in_memory::InMemory},
+        traits::domain_control::DnsRecordType,
     };
 
-    // Get the first IP address that is returned
-    let result = services
-        .into_iter()
-        .map(get_my_ip_address_from_url)
-        .find(|r| r.is_ok())
-        .ok_or(Box::new(std::io::Error::other(
-            "Could not get public IP address from any of the services",
-        )))??;
+    use super::*;
 
-    Ok(result)
-}
+    #[test]
+    fn test_set_ip_record_per_family() {
+        let zone = InMemory::default();
+        let client_maker = || build_client(None);
 
-fn get_my_ip_address_from_url(url: &str) -> Result<Ipv4Addr, Box<dyn std::error::Error>> {
-    let response = reqwest::blocking::get(url)?;
+        let ipv4: IpAddr = "203.0.113.7".parse().unwrap();
+        let ipv6: IpAddr = "2001:db8::7".parse().unwrap();
 
-    if response.status().is_success() {
-        let body = response.text()?;
+        set_ip_record(&client_maker, &zone, "home", ipv4).unwrap();
+        set_ip_record(&client_maker, &zone, "home", ipv6).unwrap();
 
-        let ip_address = body.trim().parse::<Ipv4Addr>().map_err(|e| {
-            eprintln!(
-                "Could not parse IP address from service {} with value {}. Error: {}",
-                url, body, e
-            );
-            e
-        })?;
-
-        return Ok(ip_address);
-    }
+        let records = zone.records();
+        assert_eq!(records.len(), 2);
+        assert!(
+            records
+                .iter()
+                .any(|r| r.record_type == DnsRecordType::A && r.value == "203.0.113.7")
+        );
+        assert!(
+            records
+                .iter()
+                .any(|r| r.record_type == DnsRecordType::AAAA && r.value == "2001:db8::7")
+        );
 
-    Err(Box::new(std::io::Error::other(
-        "Could not get public IP address from any of the services",
-    )))
-}
+        // Updating the AAAA record leaves the A record alone
+        let new_ipv6: IpAddr = "2001:db8::8".parse().unwrap();
+        set_ip_record(&client_maker, &zone, "home", new_ipv6).unwrap();
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        let records = zone.records();
+        assert_eq!(records.len(), 2);
+        assert!(
+            records
+                .iter()
+                .any(|r| r.record_type == DnsRecordType::A && r.value == "203.0.113.7")
+        );
+        assert!(
+            records
+                .iter()
+                .any(|r| r.record_type == DnsRecordType::AAAA && r.value == "2001:db8::8")
+        );
+    }
 
     #[test]
-    fn test_get_my_routable_ip_address() {
-        let ip_address = get_my_routable_ip_address().unwrap();
+    fn test_identical_duplicates_keep_the_record() {
+        let zone = InMemory::default();
+        let client_maker = || build_client(None);
 
-        let ip_addresses = IP_ADDRESSES_SERVICES
-            .iter()
-            .cloned()
-            .map(get_my_ip_address_from_url)
-            .collect::<Result<Vec<_>, _>>()
-            .unwrap();
+        for _ in 0..2 {
+            zone.add_dns_record(&client_maker, "home", DnsRecordType::A, "203.0.113.7")
+                .unwrap();
+        }
 
-        assert!(
-            ip_addresses.into_iter().all(|v| v == ip_address),
-            "One of the IP addresses returned by the services is different from the others"
+        let address: IpAddr = "203.0.113.7".parse().unwrap();
+        assert_eq!(
+            set_ip_record(&client_maker, &zone, "home", address).unwrap(),
+            RecordChange::Unchanged
         );
 
-        println!("My IP address is: {}", ip_address);
+        // Removing the duplicate by value would have removed the first record as well
+        let records = zone.records();
+        assert!(!records.is_empty());
+        assert!(records.iter().all(|r| r.value == "203.0.113.7"));
     }
 }
@@ -1,11 +1,42 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
 use crate::{
-    config::Config, dns_providers::helpers::build_client, dyndns::logic::run_regular,
-    run_options::dyndns_run_options::DynDnsRunOptions,
+    config::Config,
+    dns_providers::helpers::build_client,
+    dyndns::{
+        daemon::run_daemon,
+        history::run_history,
+        ip_detection::web::{build_bound_ip_detection_client, build_ip_detection_client},
+        jobs::run_jobs,
+        logic::run_regular,
+    },
+    run_options::dyndns_run_options::{DynDnsCommand, DynDnsRunOptions},
 };
 
-mod logic;
+pub mod daemon;
+pub mod history;
+pub mod hooks;
+pub mod ip_detection;
+pub mod jobs;
+pub mod logic;
+pub mod netlink;
+pub mod prefix;
+pub mod value_source;
 
 pub fn run(options: DynDnsRunOptions) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(DynDnsCommand::History(history_options)) = options.command {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+
+        history_options
+            .check(now)
+            .unwrap_or_else(|e| panic!("Arguments provided are not correct: {}", e));
+
+        return run_history(history_options.into_simplified(now));
+    }
+
     options
         .check()
         .unwrap_or_else(|e| panic!("Arguments provided are not correct: {}", e));
@@ -16,16 +47,55 @@ pub fn run(options: DynDnsRunOptions) -> Result<(), Box<dyn std::error::Error>>
 
     println!("Starting with config: {:?}", config);
 
+    let ip_detection_config = config.ip_detection.clone();
+    let jobs = config.dyndns.jobs.clone();
+
     let domain_controllers = config.into_domain_controllers();
 
     let proxy = options.proxy.clone();
     let client_maker = Box::new(|| build_client(proxy.clone()));
 
-    run_regular(
-        client_maker.as_ref(),
-        options.into_simplified(),
-        domain_controllers,
-    )?;
+    // The public address is detected directly, unless a separate proxy is provided for it
+    let ip_detection_proxy = options.ip_detection_proxy.clone();
+    let ip_detection_client_maker =
+        Box::new(|family| build_ip_detection_client(ip_detection_proxy.clone(), family));
+
+    if options.jobs_mode() {
+        // Every job may bind its detection to its own uplink
+        let bound_ip_detection_client_maker = Box::new(|family, binding: &_| {
+            build_bound_ip_detection_client(ip_detection_proxy.clone(), family, binding)
+        });
+
+        return run_jobs(
+            client_maker.as_ref(),
+            bound_ip_detection_client_maker.as_ref(),
+            &jobs,
+            &ip_detection_config,
+            options.allow_bogon,
+            options.history().as_ref(),
+            domain_controllers,
+        );
+    }
+
+    let mut args = options.into_simplified();
+
+    match args.daemon.take() {
+        Some(daemon_options) => run_daemon(
+            client_maker.as_ref(),
+            ip_detection_client_maker.as_ref(),
+            args,
+            daemon_options,
+            &ip_detection_config,
+            domain_controllers,
+        )?,
+        None => run_regular(
+            client_maker.as_ref(),
+            ip_detection_client_maker.as_ref(),
+            args,
+            &ip_detection_config,
+            domain_controllers,
+        )?,
+    }
 
     Ok(())
 }
@@ -0,0 +1,303 @@
+//! Watching the addresses of a network interface with rtnetlink (Linux only), so that the daemon can check
+//! the public address right after, e.g., a PPPoE reconnect, instead of at the next interval.
+
+use std::{
+    cell::Cell,
+    time::{Duration, Instant},
+};
+
+use super::daemon::termination_requested;
+
+/// How long to wait for events at once, so that termination signals are handled quickly
+const POLL_STEP: Duration = Duration::from_millis(200);
+
+/// The size of the netlink message header (`struct nlmsghdr`)
+const NLMSG_HEADER_LEN: usize = 16;
+/// The size of the address message (`struct ifaddrmsg`) after the header
+const IFADDRMSG_LEN: usize = 8;
+
+const RTM_NEWLINK: u16 = 16;
+const RTM_DELLINK: u16 = 17;
+const RTM_NEWADDR: u16 = 20;
+const RTM_DELADDR: u16 = 21;
+
+/// A subscription to the address add/remove events of one network interface
+pub struct AddressWatcher {
+    interface: String,
+    /// Resolved again whenever a link is added or removed, since interfaces like ppp0 get a new index
+    /// every time they are recreated. None while the interface doesn't exist.
+    interface_index: Cell<Option<u32>>,
+    #[cfg(target_os = "linux")]
+    socket: std::os::fd::OwnedFd,
+}
+
+impl AddressWatcher {
+    #[cfg(target_os = "linux")]
+    pub fn new(interface: &str) -> std::io::Result<Self> {
+        use std::os::fd::{FromRawFd, OwnedFd};
+
+        let interface_index = interface_index(interface)?;
+        if interface_index.is_none() {
+            eprintln!(
+                "Network interface `{}` not found, watching for it to appear",
+                interface
+            );
+        }
+
+        // SAFETY: socket takes no pointers, and the returned descriptor is checked below
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                libc::NETLINK_ROUTE,
+            )
+        };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        // SAFETY: fd is a valid, open descriptor that nothing else owns, so OwnedFd closes it exactly once
+        let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        // SAFETY: sockaddr_nl is a plain C struct, for which all zeroes is a valid value
+        let mut address: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+        address.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        address.nl_groups =
+            (libc::RTMGRP_LINK | libc::RTMGRP_IPV4_IFADDR | libc::RTMGRP_IPV6_IFADDR) as u32;
+
+        // SAFETY: address is a valid sockaddr_nl that outlives the call, and its size is the length passed
+        let result = unsafe {
+            libc::bind(
+                fd,
+                &address as *const libc::sockaddr_nl as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if result != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        Ok(Self {
+            interface: interface.to_string(),
+            interface_index: Cell::new(interface_index),
+            socket,
+        })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn new(_interface: &str) -> std::io::Result<Self> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "Watching interface addresses is only supported on Linux",
+        ))
+    }
+
+    pub fn interface(&self) -> &str {
+        &self.interface
+    }
+
+    /// Waits until an address of the interface is added or removed, the timeout passes, or termination is requested.
+    /// Returns whether an address changed.
+    #[cfg(target_os = "linux")]
+    pub fn wait_for_change(&self, timeout: Duration) -> std::io::Result<bool> {
+        use std::os::fd::AsRawFd;
+
+        let start = Instant::now();
+        let mut buffer = vec![0u8; 16 * 1024];
+
+        while !termination_requested() {
+            let elapsed = start.elapsed();
+            if elapsed >= timeout {
+                break;
+            }
+
+            let mut poll_fd = libc::pollfd {
+                fd: self.socket.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let step = POLL_STEP.min(timeout - elapsed);
+            // SAFETY: poll_fd is a single valid pollfd, as the count of 1 says, with the socket still owned by self
+            let ready = unsafe { libc::poll(&mut poll_fd, 1, step.as_millis() as libc::c_int) };
+
+            if ready < 0 {
+                let error = std::io::Error::last_os_error();
+                if error.kind() == std::io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(error);
+            }
+            if ready == 0 {
+                continue;
+            }
+
+            // SAFETY: the buffer is valid for writes of buffer.len() bytes, the length passed, and the socket is
+            // still owned by self
+            let received = unsafe {
+                libc::recv(
+                    self.socket.as_raw_fd(),
+                    buffer.as_mut_ptr() as *mut libc::c_void,
+                    buffer.len(),
+                    libc::MSG_DONTWAIT,
+                )
+            };
+
+            if received < 0 {
+                let error = std::io::Error::last_os_error();
+                match error.raw_os_error() {
+                    Some(libc::EINTR) | Some(libc::EAGAIN) => continue,
+                    // The kernel dropped events because they came faster than they were read, so some may be ours
+                    Some(libc::ENOBUFS) => return Ok(true),
+                    _ => return Err(error),
+                }
+            }
+
+            let messages = &buffer[..received as usize];
+            let previous_index = self.interface_index.get();
+            if has_link_change(messages) {
+                self.interface_index.set(interface_index(&self.interface)?);
+            }
+
+            // The old index too, for the addresses removed along with the interface it belonged to
+            if [previous_index, self.interface_index.get()]
+                .into_iter()
+                .flatten()
+                .any(|index| has_address_change(messages, index))
+            {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn wait_for_change(&self, _timeout: Duration) -> std::io::Result<bool> {
+        unreachable!("AddressWatcher can't be created on this platform")
+    }
+
+    /// Waits until the interface stayed quiet for the debounce duration, since a reconnect
+    /// usually removes and adds several addresses in a row
+    pub fn wait_until_quiet(&self, debounce: Duration) -> std::io::Result<()> {
+        while self.wait_for_change(debounce)? {}
+
+        Ok(())
+    }
+}
+
+/// The index of the interface, or None if it doesn't exist (yet)
+#[cfg(target_os = "linux")]
+fn interface_index(interface: &str) -> std::io::Result<Option<u32>> {
+    let name = std::ffi::CString::new(interface)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+
+    // SAFETY: name is a valid null-terminated string, which outlives the call
+    match unsafe { libc::if_nametoindex(name.as_ptr()) } {
+        0 => Ok(None),
+        index => Ok(Some(index)),
+    }
+}
+
+/// The type and the payload (after the header) of each complete netlink message in the buffer
+fn messages(buffer: &[u8]) -> impl Iterator<Item = (u16, &[u8])> {
+    let mut offset = 0;
+
+    std::iter::from_fn(move || {
+        if offset + NLMSG_HEADER_LEN > buffer.len() {
+            return None;
+        }
+
+        let header = &buffer[offset..];
+        let length = u32::from_ne_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let message_type = u16::from_ne_bytes([header[4], header[5]]);
+
+        if length < NLMSG_HEADER_LEN || offset + length > buffer.len() {
+            return None;
+        }
+
+        // Messages are aligned to 4 bytes
+        offset += (length + 3) & !3;
+
+        Some((message_type, &header[NLMSG_HEADER_LEN..length]))
+    })
+}
+
+/// Whether the netlink messages have an address being added to or removed from the interface
+fn has_address_change(buffer: &[u8], interface_index: u32) -> bool {
+    messages(buffer).any(|(message_type, ifaddrmsg)| {
+        (message_type == RTM_NEWADDR || message_type == RTM_DELADDR)
+            && ifaddrmsg.len() >= IFADDRMSG_LEN
+            && u32::from_ne_bytes([ifaddrmsg[4], ifaddrmsg[5], ifaddrmsg[6], ifaddrmsg[7]])
+                == interface_index
+    })
+}
+
+/// Whether the netlink messages have a link being added or removed (or changed), after which the
+/// interface may have another index
+fn has_link_change(buffer: &[u8]) -> bool {
+    messages(buffer)
+        .any(|(message_type, _)| message_type == RTM_NEWLINK || message_type == RTM_DELLINK)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(message_type: u16, interface_index: u32, payload_len: usize) -> Vec<u8> {
+        let length = NLMSG_HEADER_LEN + IFADDRMSG_LEN + payload_len;
+
+        let mut message = Vec::new();
+        message.extend_from_slice(&(length as u32).to_ne_bytes());
+        message.extend_from_slice(&message_type.to_ne_bytes());
+        message.extend_from_slice(&[0; 10]);
+        // ifaddrmsg: family, prefix length, flags, scope, index
+        message.extend_from_slice(&[libc::AF_INET as u8, 32, 0, 0]);
+        message.extend_from_slice(&interface_index.to_ne_bytes());
+        message.extend(std::iter::repeat_n(0xab, payload_len));
+        // Padding to the 4-byte alignment
+        message.resize(length.div_ceil(4) * 4, 0);
+
+        message
+    }
+
+    #[test]
+    fn test_has_address_change() {
+        let new_address_on_3 = message(RTM_NEWADDR, 3, 6);
+        let removed_address_on_3 = message(RTM_DELADDR, 3, 0);
+        let new_address_on_7 = message(RTM_NEWADDR, 7, 0);
+        let new_link_on_3 = message(16, 3, 0);
+
+        assert!(has_address_change(&new_address_on_3, 3));
+        assert!(has_address_change(&removed_address_on_3, 3));
+        assert!(!has_address_change(&new_address_on_7, 3));
+        assert!(!has_address_change(&new_link_on_3, 3));
+
+        // The relevant message comes after others in the same read, after the padding of the first one
+        let batch = [new_address_on_7.clone(), new_address_on_3.clone()].concat();
+        assert!(has_address_change(&batch, 3));
+
+        // Truncated messages are ignored
+        assert!(!has_address_change(&new_address_on_3[..20], 3));
+        assert!(!has_address_change(&[], 3));
+    }
+
+    #[test]
+    fn test_has_link_change() {
+        let new_link_on_3 = message(RTM_NEWLINK, 3, 0);
+        let removed_link_on_3 = message(RTM_DELLINK, 3, 0);
+        let new_address_on_3 = message(RTM_NEWADDR, 3, 0);
+
+        assert!(has_link_change(&new_link_on_3));
+        assert!(has_link_change(&removed_link_on_3));
+        assert!(!has_link_change(&new_address_on_3));
+        assert!(has_link_change(
+            &[new_address_on_3, removed_link_on_3].concat()
+        ));
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_interface_index() {
+        assert_eq!(interface_index("no-such-if0").unwrap(), None);
+        assert_eq!(interface_index("lo").unwrap(), Some(1));
+    }
+}
@@ -0,0 +1,65 @@
+//! Addresses of LAN hosts under a delegated IPv6 prefix, which changes whenever the ISP rotates it.
+//! Every host is defined by its (static) interface identifier, and combined with the current prefix.
+
+use std::net::{IpAddr, Ipv6Addr};
+
+use serde::{Deserialize, Serialize};
+
+use super::ip_detection::{IpFamily, interface};
+
+/// A host of a dyndns job whose AAAA record is the current prefix combined with its interface identifier
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PrefixHost {
+    /// The name (subdomain) in the zone of the job
+    pub name: String,
+    /// The host part of the address, e.g., `::1234:5678`, or `::1` for the router itself
+    pub interface_id: Ipv6Addr,
+}
+
+/// The address made of the first `prefix_length` bits of the prefix, and the remaining bits of the interface identifier
+pub fn combine(prefix: Ipv6Addr, prefix_length: u8, interface_id: Ipv6Addr) -> Ipv6Addr {
+    let prefix_mask = u128::MAX
+        .checked_shl(128 - u32::from(prefix_length.min(128)))
+        .unwrap_or(0);
+
+    Ipv6Addr::from((u128::from(prefix) & prefix_mask) | (u128::from(interface_id) & !prefix_mask))
+}
+
+/// Finds the current prefix from the publishable IPv6 address of the interface (usually the LAN interface
+/// of the router, which gets an address of the delegated prefix). Only the prefix bits are meaningful.
+pub fn detect_prefix(interface_name: &str) -> Result<Ipv6Addr, String> {
+    match interface::detect(Some(interface_name), IpFamily::V6) {
+        Ok(IpAddr::V6(address)) => Ok(address),
+        Ok(IpAddr::V4(_)) => unreachable!("An IPv6 address was requested"),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_combine() {
+        let prefix: Ipv6Addr = "2001:db8:1234:5600:aaaa:bbbb:cccc:dddd".parse().unwrap();
+
+        assert_eq!(
+            combine(prefix, 64, "::1234:5678".parse().unwrap()),
+            "2001:db8:1234:5600::1234:5678".parse::<Ipv6Addr>().unwrap()
+        );
+        assert_eq!(
+            combine(prefix, 56, "::ff:0:0:0:1".parse().unwrap()),
+            "2001:db8:1234:56ff::1".parse::<Ipv6Addr>().unwrap()
+        );
+        // The prefix bits of the interface identifier are ignored
+        assert_eq!(
+            combine(prefix, 64, "fe80::1".parse().unwrap()),
+            "2001:db8:1234:5600::1".parse::<Ipv6Addr>().unwrap()
+        );
+        assert_eq!(combine(prefix, 128, "::1".parse().unwrap()), prefix);
+        assert_eq!(
+            combine(prefix, 0, "::1".parse().unwrap()),
+            "::1".parse::<Ipv6Addr>().unwrap()
+        );
+    }
+}
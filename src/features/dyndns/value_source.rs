@@ -0,0 +1,96 @@
+//! Record values that come from a command or a file instead of the public address, e.g., a TXT record with
+//! the current WireGuard public key, reconciled the same way as the address records
+
+use std::{fmt::Display, process::Command};
+
+use serde::{Deserialize, Serialize};
+
+/// The `value_from` of a dyndns job, either `command: ...` or `file: ...`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ValueSource {
+    /// A shell command, run with `sh -c`, whose output is the value
+    Command { command: String },
+    /// A file whose contents are the value
+    File { file: String },
+}
+
+impl Display for ValueSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValueSource::Command { command } => write!(f, "command `{}`", command),
+            ValueSource::File { file } => write!(f, "file {}", file),
+        }
+    }
+}
+
+impl ValueSource {
+    /// Reads the value, without the surrounding whitespace (e.g., the trailing newline).
+    /// An empty value is an error, so that a failing source never empties a record.
+    pub fn read(&self) -> Result<String, String> {
+        let value = match self {
+            ValueSource::Command { command } => {
+                let output = Command::new("sh")
+                    .arg("-c")
+                    .arg(command)
+                    .output()
+                    .map_err(|e| format!("Could not run {}: {}", self, e))?;
+
+                if !output.status.success() {
+                    return Err(format!(
+                        "{} exited with {}: {}",
+                        self,
+                        output.status,
+                        String::from_utf8_lossy(&output.stderr).trim()
+                    ));
+                }
+
+                String::from_utf8(output.stdout)
+                    .map_err(|_| format!("The output of {} is not valid UTF-8", self))?
+            }
+            ValueSource::File { file } => std::fs::read_to_string(file)
+                .map_err(|e| format!("Could not read {}: {}", self, e))?,
+        };
+
+        let value = value.trim();
+        if value.is_empty() {
+            return Err(format!("The value from {} is empty", self));
+        }
+
+        Ok(value.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_value_sources() {
+        let sources: Vec<ValueSource> = serde_yml::from_str(
+            r#"
+- command: "printf ' abc \n'"
+- file: "/nonexistent/sam-dns-tools-value"
+- command: "exit 2"
+- command: "true"
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(sources[0].read(), Ok("abc".to_string()));
+        assert!(sources[1].read().is_err());
+        assert!(sources[2].read().unwrap_err().contains("exited with"));
+        assert!(sources[3].read().unwrap_err().contains("empty"));
+
+        let path = std::env::temp_dir().join(format!(
+            "sam-dns-tools-value-source-{}.txt",
+            std::process::id()
+        ));
+        std::fs::write(&path, "v1.2.3\n").unwrap();
+        let file = ValueSource::File {
+            file: path.display().to_string(),
+        };
+        assert_eq!(file.read(), Ok("v1.2.3".to_string()));
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,353 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    features::certbot::logic::{
+        ACME_CHALLENGE_SUBDOMAIN, ResolvedRecord, cleanup, dns01_txt_value, resolve_zone,
+        set_record,
+    },
+    run_options::issue_run_options::SimplifiedIssueRunOptions,
+    traits::domain_control::DomainController,
+};
+
+use super::acme::{self, AcmeClient, Status};
+
+const DNS01_CHALLENGE_TYPE: &str = "dns-01";
+
+/// A DNS-01 challenge that has to be solved before the order can be finalized
+struct PendingChallenge {
+    authorization_url: String,
+    challenge_url: String,
+    fqdn: String,
+    value: String,
+}
+
+/// Keeps track of the TXT records set for challenges, and removes them when dropped,
+/// so that they're cleaned up whether the issuance succeeds, fails or panics
+struct ChallengeRecordsGuard<'a> {
+    client_maker: &'a dyn Fn() -> reqwest::blocking::Client,
+    records: Vec<(ResolvedRecord<'a>, String)>,
+}
+
+impl<'a> ChallengeRecordsGuard<'a> {
+    fn new(client_maker: &'a dyn Fn() -> reqwest::blocking::Client) -> Self {
+        Self {
+            client_maker,
+            records: Vec::new(),
+        }
+    }
+
+    fn set(
+        &mut self,
+        resolved: ResolvedRecord<'a>,
+        value: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let change = set_record(self.client_maker, &resolved, value)?;
+
+        println!("{}", change);
+
+        self.records.push((resolved, value.to_string()));
+
+        Ok(())
+    }
+}
+
+impl Drop for ChallengeRecordsGuard<'_> {
+    fn drop(&mut self) {
+        for (resolved, value) in &self.records {
+            match cleanup(self.client_maker, resolved, value) {
+                Ok(change) => println!("{}", change),
+                Err(e) => eprintln!(
+                    "Failed to clean up the challenge record {} in zone {}: {}",
+                    resolved.record_name, resolved.zone, e
+                ),
+            }
+        }
+    }
+}
+
+pub fn run_regular(
+    client_maker: &dyn Fn() -> reqwest::blocking::Client,
+    acme_client: &mut AcmeClient,
+    args: SimplifiedIssueRunOptions,
+    domain_controllers: BTreeMap<String, Box<dyn DomainController>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let account_url = acme_client.register_account(args.contact_email.as_deref())?;
+
+    println!("Using ACME account: {}", account_url);
+
+    let (order_url, order) = acme_client.new_order(&args.domain_names)?;
+
+    println!("Created order: {}", order_url);
+
+    let pending_challenges = collect_pending_challenges(acme_client, &order.authorizations)?;
+
+    solve_challenges(
+        client_maker,
+        acme_client,
+        &args,
+        &domain_controllers,
+        &pending_challenges,
+    )?;
+
+    let (key_pem, csr_der) = acme::generate_key_and_csr(&args.domain_names)?;
+
+    acme_client.finalize(&order, &csr_der)?;
+
+    let order = acme_client.poll_order(&order_url, args.poll_timeout)?;
+
+    let certificate_url = order
+        .certificate
+        .ok_or("The order is valid, but has no certificate URL")?;
+
+    let certificate_chain = acme_client.download_certificate(&certificate_url)?;
+
+    acme::write_private_file(&args.key_path, &key_pem)?;
+    std::fs::write(&args.cert_path, certificate_chain)?;
+
+    println!(
+        "Certificate issued for {}. Certificate chain written to {} and private key written to {}",
+        args.domain_names.join(", "),
+        args.cert_path.display(),
+        args.key_path.display()
+    );
+
+    Ok(())
+}
+
+fn collect_pending_challenges(
+    acme_client: &mut AcmeClient,
+    authorization_urls: &[String],
+) -> Result<Vec<PendingChallenge>, Box<dyn std::error::Error>> {
+    let mut result = Vec::new();
+
+    for authorization_url in authorization_urls {
+        let authorization = acme_client.authorization(authorization_url)?;
+
+        if authorization.status == Status::Valid {
+            println!(
+                "Authorization for {} is already valid",
+                authorization.identifier.value
+            );
+            continue;
+        }
+
+        let challenge = authorization
+            .challenges
+            .iter()
+            .find(|c| c.challenge_type == DNS01_CHALLENGE_TYPE)
+            .ok_or_else(|| {
+                format!(
+                    "No {} challenge offered for {}",
+                    DNS01_CHALLENGE_TYPE, authorization.identifier.value
+                )
+            })?;
+
+        // For wildcards, the identifier is the name without `*.`, which is where the challenge record goes
+        let fqdn = format!(
+            "{}.{}",
+            ACME_CHALLENGE_SUBDOMAIN, authorization.identifier.value
+        );
+
+        result.push(PendingChallenge {
+            authorization_url: authorization_url.clone(),
+            challenge_url: challenge.url.clone(),
+            fqdn,
+            value: dns01_txt_value(&acme_client.key_authorization(&challenge.token)),
+        });
+    }
+
+    Ok(result)
+}
+
+/// Sets the records of all challenges, asks the server to validate them and waits for the result.
+/// The records are removed at the end, regardless of the result.
+fn solve_challenges(
+    client_maker: &dyn Fn() -> reqwest::blocking::Client,
+    acme_client: &mut AcmeClient,
+    args: &SimplifiedIssueRunOptions,
+    domain_controllers: &BTreeMap<String, Box<dyn DomainController>>,
+    pending_challenges: &[PendingChallenge],
+) -> Result<(), Box<dyn std::error::Error>> {
+    if pending_challenges.is_empty() {
+        return Ok(());
+    }
+
+    let mut guard = ChallengeRecordsGuard::new(client_maker);
+
+    for challenge in pending_challenges {
+        let resolved = resolve_zone(&challenge.fqdn, domain_controllers)?;
+        guard.set(resolved, &challenge.value)?;
+    }
+
+    println!(
+        "Waiting {} seconds for the DNS records to propagate",
+        args.propagation_delay.as_secs()
+    );
+    std::thread::sleep(args.propagation_delay);
+
+    for challenge in pending_challenges {
+        acme_client.respond_to_challenge(&challenge.challenge_url)?;
+    }
+
+    for challenge in pending_challenges {
+        let authorization =
+            acme_client.poll_authorization(&challenge.authorization_url, args.poll_timeout)?;
+
+        println!(
+            "Authorization for {} is valid",
+            authorization.identifier.value
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::{
+        dns_providers::{helpers::build_client, in_memory::InMemory},
+        traits::domain_control::{DnsRecord, DnsRecordType},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_challenge_records_are_cleaned_up() {
+        let zone = InMemory::default();
+        let mut domain_controllers: BTreeMap<String, Box<dyn DomainController>> = BTreeMap::new();
+        domain_controllers.insert("example.com".to_string(), Box::new(zone.clone()));
+
+        let client_maker = || build_client(None);
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let mut guard = ChallengeRecordsGuard::new(&client_maker);
+            for value in ["value-1", "value-2"] {
+                let resolved =
+                    resolve_zone("_acme-challenge.example.com", &domain_controllers).unwrap();
+                guard.set(resolved, value).unwrap();
+            }
+
+            assert_eq!(zone.records().len(), 2);
+
+            panic!("Validation failed");
+        }));
+
+        assert!(result.is_err());
+        assert!(zone.records().is_empty());
+    }
+
+    /// A domain controller that sets TXT records in Pebble's challenge test server (pebble-challtestsrv),
+    /// which Pebble uses as its DNS server
+    struct ChallTestSrv {
+        management_url: String,
+        zone: String,
+    }
+
+    impl ChallTestSrv {
+        fn host(&self, name: &str) -> String {
+            format!("{}.{}.", name, self.zone)
+        }
+    }
+
+    impl DomainController for ChallTestSrv {
+        fn provider_name(&self) -> &'static str {
+            "pebble-challtestsrv"
+        }
+
+        fn add_dns_record(
+            &self,
+            client_maker: &dyn Fn() -> reqwest::blocking::Client,
+            name: &str,
+            _record_type: DnsRecordType,
+            value: &str,
+        ) -> Result<(), Box<dyn std::error::Error>> {
+            client_maker()
+                .post(format!("{}/set-txt", self.management_url))
+                .json(&serde_json::json!({ "host": self.host(name), "value": value }))
+                .send()?
+                .error_for_status()?;
+
+            Ok(())
+        }
+
+        fn remove_dns_record(
+            &self,
+            client_maker: &dyn Fn() -> reqwest::blocking::Client,
+            name: &str,
+            _record_type: DnsRecordType,
+            _value: Option<&str>,
+        ) -> Result<usize, Box<dyn std::error::Error>> {
+            client_maker()
+                .post(format!("{}/clear-txt", self.management_url))
+                .json(&serde_json::json!({ "host": self.host(name) }))
+                .send()?
+                .error_for_status()?;
+
+            Ok(1)
+        }
+
+        fn list_dns_records(
+            &self,
+            _client_maker: &dyn Fn() -> reqwest::blocking::Client,
+        ) -> Result<Vec<DnsRecord>, Box<dyn std::error::Error>> {
+            Ok(Vec::new())
+        }
+    }
+
+    /// Issues a certificate from a local Pebble server. Start Pebble and its challenge test server first, e.g.:
+    ///
+    /// pebble-challtestsrv -defaultIPv6 "" -defaultIPv4 127.0.0.1
+    /// PEBBLE_VA_NOSLEEP=1 pebble -config test/config/pebble-config.json -dnsserver 127.0.0.1:8053
+    ///
+    /// then run: PEBBLE_CA_CERT_PATH=/path/to/pebble/test/certs/pebble.minica.pem cargo test -- --ignored test_issue_with_pebble
+    #[test]
+    #[ignore = "requires a running Pebble ACME test server"]
+    fn test_issue_with_pebble() {
+        let directory_url = std::env::var("PEBBLE_DIRECTORY_URL")
+            .unwrap_or("https://localhost:14000/dir".to_string());
+        let ca_cert_path =
+            std::env::var("PEBBLE_CA_CERT_PATH").expect("PEBBLE_CA_CERT_PATH must be set");
+        let management_url =
+            std::env::var("PEBBLE_CHALLTESTSRV_URL").unwrap_or("http://localhost:8055".to_string());
+
+        let output_dir =
+            std::env::temp_dir().join(format!("sam-dns-tools-pebble-test-{}", std::process::id()));
+        std::fs::create_dir_all(&output_dir).unwrap();
+
+        let mut domain_controllers: BTreeMap<String, Box<dyn DomainController>> = BTreeMap::new();
+        domain_controllers.insert(
+            "example.com".to_string(),
+            Box::new(ChallTestSrv {
+                management_url,
+                zone: "example.com".to_string(),
+            }),
+        );
+
+        let acme_http_client =
+            super::super::build_acme_client(Some(&PathBuf::from(ca_cert_path))).unwrap();
+        let account_key =
+            acme::load_or_create_account_key(&output_dir.join("account_key.pem")).unwrap();
+        let mut acme_client =
+            AcmeClient::new(acme_http_client, &directory_url, account_key).unwrap();
+
+        let args = SimplifiedIssueRunOptions {
+            domain_names: vec!["example.com".to_string(), "*.example.com".to_string()],
+            contact_email: None,
+            cert_path: output_dir.join("fullchain.pem"),
+            key_path: output_dir.join("privkey.pem"),
+            propagation_delay: std::time::Duration::ZERO,
+            poll_timeout: std::time::Duration::from_secs(60),
+        };
+        let cert_path = args.cert_path.clone();
+
+        let client_maker = || build_client(None);
+        run_regular(&client_maker, &mut acme_client, args, domain_controllers).unwrap();
+
+        let certificate_chain = std::fs::read_to_string(cert_path).unwrap();
+        assert!(certificate_chain.starts_with("-----BEGIN CERTIFICATE-----"));
+
+        std::fs::remove_dir_all(output_dir).unwrap();
+    }
+}
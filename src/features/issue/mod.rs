@@ -0,0 +1,56 @@
+mod acme;
+mod logic;
+
+use std::path::PathBuf;
+
+use crate::{
+    config::Config, dns_providers::helpers::build_client, issue::logic::run_regular,
+    run_options::issue_run_options::IssueRunOptions,
+};
+
+/// Builds the HTTP client for the ACME server, optionally trusting an extra root certificate (e.g., Pebble's)
+fn build_acme_client(
+    ca_cert_path: Option<&PathBuf>,
+) -> Result<reqwest::blocking::Client, Box<dyn std::error::Error>> {
+    let mut builder = reqwest::blocking::ClientBuilder::new();
+
+    if let Some(path) = ca_cert_path {
+        let pem = std::fs::read(path)?;
+        builder = builder.add_root_certificate(reqwest::Certificate::from_pem(&pem)?);
+    }
+
+    Ok(builder.build()?)
+}
+
+pub fn run(options: IssueRunOptions) -> Result<(), Box<dyn std::error::Error>> {
+    options
+        .check()
+        .unwrap_or_else(|e| panic!("Arguments provided are not correct: {}", e));
+
+    println!("Starting in issue mode with args: {:?}", &options);
+
+    let config = Config::from_file_or_default(&options.config_file_path)?;
+
+    println!("Starting with config: {:?}", config);
+
+    let domain_controllers = config.into_domain_controllers();
+
+    let proxy = options.proxy.clone();
+    let client_maker = Box::new(|| build_client(proxy.clone()));
+
+    let account_key = acme::load_or_create_account_key(&options.account_key_path)?;
+    let mut acme_client = acme::AcmeClient::new(
+        build_acme_client(options.acme_ca_cert_path.as_ref())?,
+        &options.acme_directory_url,
+        account_key,
+    )?;
+
+    run_regular(
+        client_maker.as_ref(),
+        &mut acme_client,
+        options.into_simplified(),
+        domain_controllers,
+    )?;
+
+    Ok(())
+}
@@ -0,0 +1,80 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    features::certbot::logic::{
+        ACME_CHALLENGE_SUBDOMAIN, cleanup, dns01_txt_value, resolve_zone, set_record,
+    },
+    run_options::lego_exec_run_options::{
+        LegoOperation, LegoRequest, SimplifiedLegoExecRunOptions,
+    },
+    traits::domain_control::DomainController,
+};
+
+pub fn run_regular(
+    client_maker: &dyn Fn() -> reqwest::blocking::Client,
+    args: SimplifiedLegoExecRunOptions,
+    domain_controllers: BTreeMap<String, Box<dyn DomainController>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (fqdn, value) = challenge_record(args.request);
+
+    let resolved = resolve_zone(&fqdn, &domain_controllers)?;
+
+    let change = match args.operation {
+        LegoOperation::Present => set_record(client_maker, &resolved, &value)?,
+        LegoOperation::Cleanup => cleanup(client_maker, &resolved, &value)?,
+    };
+
+    println!("{}", change);
+
+    Ok(())
+}
+
+/// Returns the FQDN and the value of the TXT record that lego is asking for
+fn challenge_record(request: LegoRequest) -> (String, String) {
+    match request {
+        LegoRequest::Default { fqdn, value } => (fqdn, value),
+        LegoRequest::Raw {
+            domain,
+            token,
+            key_authorization,
+        } => {
+            println!(
+                "Raw lego request for domain {} with token {}",
+                domain, token
+            );
+
+            let fqdn = format!(
+                "{}.{}.",
+                ACME_CHALLENGE_SUBDOMAIN,
+                domain.trim_end_matches('.')
+            );
+
+            (fqdn, dns01_txt_value(&key_authorization))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_challenge_record() {
+        let (fqdn, value) = challenge_record(LegoRequest::Default {
+            fqdn: "_acme-challenge.my.example.org.".to_string(),
+            value: "-MsijOYZxqyjGnFGwhjrhfg-Xgbl5r68WPda0J9EgqqI".to_string(),
+        });
+        assert_eq!(fqdn, "_acme-challenge.my.example.org.");
+        assert_eq!(value, "-MsijOYZxqyjGnFGwhjrhfg-Xgbl5r68WPda0J9EgqqI");
+
+        let (fqdn, value) = challenge_record(LegoRequest::Raw {
+            domain: "my.example.org".to_string(),
+            token: "some-token".to_string(),
+            key_authorization:
+                "KxAy-J3NwUmg9ZQuM-gP_Mq1nStaYSaP9tYQs5_-YsE.Gg1AU0d0Y4mJ9MrRkf9ljvgQ-X10ZfWIOp1yR0n-Mc"
+                    .to_string(),
+        });
+        assert_eq!(fqdn, "_acme-challenge.my.example.org.");
+        assert_eq!(value, "b-Xvbt2zlewaLqyN8_sJZj49aWWezgKgPUdG2aEU_kc");
+    }
+}
@@ -1,3 +1,9 @@
+pub mod acme_sh;
 pub mod certbot;
+pub mod dhcp_leases;
+pub mod dns_persist;
 pub mod dyndns;
+pub mod issue;
+pub mod lego_exec;
+pub mod serve_dyndns2;
 pub mod tester;
@@ -0,0 +1,568 @@
+use std::{collections::BTreeMap, net::IpAddr};
+
+use base64::Engine;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    features::{
+        certbot::logic::{ResolvedRecord, ZoneResolutionError, resolve_zone},
+        dyndns::{
+            ip_detection::IpFamily,
+            logic::{RecordChange, sync_ip_record},
+        },
+    },
+    run_options::serve_dyndns2_run_options::SimplifiedServeDynDns2RunOptions,
+    traits::domain_control::DomainController,
+};
+
+use super::DynDns2User;
+
+/// The path routers call, as in the dyndns2 protocol
+const UPDATE_PATH: &str = "/nic/update";
+
+/// More hostnames in one request are rejected with `numhost`, as in the dyndns2 protocol
+const MAX_HOSTNAMES_PER_REQUEST: usize = 20;
+
+const AUTHENTICATION_REALM: &str = "sam-dns-tools";
+
+/// The record name of the apex of a zone, as in the `names` of the dyndns jobs
+const APEX_RECORD_NAME: &str = "@";
+
+/// The HTTP status and the dyndns2 return codes (one line per hostname) of a request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpdateResponse {
+    pub status: u16,
+    pub body: String,
+}
+
+impl UpdateResponse {
+    fn ok(body: impl Into<String>) -> Self {
+        Self {
+            status: 200,
+            body: body.into(),
+        }
+    }
+
+    fn error(status: u16, body: impl Into<String>) -> Self {
+        Self {
+            status,
+            body: body.into(),
+        }
+    }
+}
+
+/// Serves update requests one at a time until the process is stopped
+pub fn serve(
+    client_maker: &dyn Fn() -> reqwest::blocking::Client,
+    args: SimplifiedServeDynDns2RunOptions,
+    users: &[DynDns2User],
+    domain_controllers: BTreeMap<String, Box<dyn DomainController>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let server = tiny_http::Server::http(args.listen)
+        .map_err(|e| format!("Could not listen on {}: {}", args.listen, e))?;
+
+    println!(
+        "dyndns2 server listening on http://{}{}",
+        args.listen, UPDATE_PATH
+    );
+
+    for request in server.incoming_requests() {
+        let authorization = request
+            .headers()
+            .iter()
+            .find(|h| h.field.equiv("Authorization"))
+            .map(|h| h.value.to_string());
+
+        let response = if *request.method() == tiny_http::Method::Get {
+            handle_request(
+                client_maker,
+                users,
+                &domain_controllers,
+                request.url(),
+                authorization.as_deref(),
+                request.remote_addr().map(|a| a.ip()),
+            )
+        } else {
+            UpdateResponse::error(405, "badagent")
+        };
+
+        let mut http_response =
+            tiny_http::Response::from_string(response.body).with_status_code(response.status);
+        if response.status == 401 {
+            http_response.add_header(
+                tiny_http::Header::from_bytes(
+                    "WWW-Authenticate",
+                    format!("Basic realm=\"{}\"", AUTHENTICATION_REALM),
+                )
+                .expect("Static header is valid"),
+            );
+        }
+
+        if let Err(e) = request.respond(http_response) {
+            eprintln!("Failed to respond to a dyndns2 request: {}", e);
+        }
+    }
+
+    Ok(())
+}
+
+/// Handles one request, given its URL (path and query), its `Authorization` header and the address it came from.
+/// Without `myip`, the address the request came from is published.
+pub fn handle_request(
+    client_maker: &dyn Fn() -> reqwest::blocking::Client,
+    users: &[DynDns2User],
+    domain_controllers: &BTreeMap<String, Box<dyn DomainController>>,
+    url: &str,
+    authorization: Option<&str>,
+    remote_ip: Option<IpAddr>,
+) -> UpdateResponse {
+    let url = match reqwest::Url::parse("http://localhost").and_then(|base| base.join(url)) {
+        Ok(url) => url,
+        Err(_) => return UpdateResponse::error(400, "badrequest"),
+    };
+
+    if url.path() != UPDATE_PATH {
+        return UpdateResponse::error(404, "notfound");
+    }
+
+    let user = match authorization.and_then(|a| authenticate(users, a)) {
+        Some(user) => user,
+        None => return UpdateResponse::error(401, "badauth"),
+    };
+
+    let parameter = |name: &str| {
+        url.query_pairs()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+    };
+
+    let hostnames = parameter("hostname")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|h| !h.is_empty())
+        .map(ToString::to_string)
+        .collect::<Vec<_>>();
+
+    if hostnames.is_empty() {
+        return UpdateResponse::ok("notfqdn");
+    }
+    if hostnames.len() > MAX_HOSTNAMES_PER_REQUEST {
+        return UpdateResponse::ok("numhost");
+    }
+
+    let addresses = match parameter("myip") {
+        Some(myip) => match parse_addresses(&myip) {
+            Ok(addresses) => addresses,
+            Err(e) => {
+                eprintln!("Rejected dyndns2 update from `{}`: {}", user.username, e);
+                return UpdateResponse::error(400, "badrequest");
+            }
+        },
+        None => match remote_ip {
+            Some(ip) => vec![ip],
+            None => return UpdateResponse::error(400, "badrequest"),
+        },
+    };
+
+    let lines = hostnames
+        .iter()
+        .map(|hostname| {
+            update_hostname(client_maker, user, domain_controllers, hostname, &addresses)
+        })
+        .collect::<Vec<_>>();
+
+    UpdateResponse::ok(lines.join("\n"))
+}
+
+/// Finds the user of a `Basic` authorization header, if the password matches
+fn authenticate<'a>(users: &'a [DynDns2User], authorization: &str) -> Option<&'a DynDns2User> {
+    let (scheme, credentials) = authorization.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+
+    let credentials = base64::engine::general_purpose::STANDARD
+        .decode(credentials.trim())
+        .ok()?;
+    let credentials = String::from_utf8(credentials).ok()?;
+    let (username, password) = credentials.split_once(':')?;
+
+    // Every user is checked, and both of their credentials, so that the timing tells nothing about them
+    users.iter().fold(None, |found, user| {
+        let matches =
+            constant_time_eq(&user.username, username) & constant_time_eq(&user.password, password);
+
+        found.or(matches.then_some(user))
+    })
+}
+
+/// Compares the SHA-256 digests of the values without stopping at the first difference, so that the
+/// comparison takes the same time however much of the values match, and whatever their lengths
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    Sha256::digest(a)
+        .iter()
+        .zip(Sha256::digest(b).iter())
+        .fold(0u8, |difference, (x, y)| difference | (x ^ y))
+        == 0
+}
+
+/// `myip` may have one IPv4 and one IPv6 address, separated by a comma
+fn parse_addresses(myip: &str) -> Result<Vec<IpAddr>, String> {
+    let addresses = myip
+        .split(',')
+        .map(str::trim)
+        .filter(|a| !a.is_empty())
+        .map(|a| {
+            a.parse::<IpAddr>()
+                .map_err(|_| format!("`{}` is not an IP address", a))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if addresses.is_empty() {
+        return Err("`myip` is empty".to_string());
+    }
+
+    for family in [IpFamily::V4, IpFamily::V6] {
+        if addresses
+            .iter()
+            .filter(|a| IpFamily::of(a) == family)
+            .count()
+            > 1
+        {
+            return Err(format!("More than one {} address in `{}`", family, myip));
+        }
+    }
+
+    Ok(addresses)
+}
+
+fn normalize_hostname(hostname: &str) -> String {
+    hostname.trim().trim_end_matches('.').to_lowercase()
+}
+
+/// Like resolve_zone, but the apex of a zone is its `@` record, so that routers can update the bare domain
+fn resolve_hostname<'a>(
+    hostname: &str,
+    domain_controllers: &'a BTreeMap<String, Box<dyn DomainController>>,
+) -> Result<ResolvedRecord<'a>, ZoneResolutionError> {
+    match resolve_zone(hostname, domain_controllers) {
+        Err(ZoneResolutionError::NameIsZoneApex(fqdn)) => domain_controllers
+            .iter()
+            .find(|(zone, _)| normalize_hostname(zone) == fqdn)
+            .map(|(zone, domain_controller)| ResolvedRecord {
+                zone,
+                record_name: APEX_RECORD_NAME.to_string(),
+                domain_controller: domain_controller.as_ref(),
+            })
+            .ok_or(ZoneResolutionError::NameIsZoneApex(fqdn)),
+        result => result,
+    }
+}
+
+/// Updates the records of one hostname, and returns its dyndns2 return code
+fn update_hostname(
+    client_maker: &dyn Fn() -> reqwest::blocking::Client,
+    user: &DynDns2User,
+    domain_controllers: &BTreeMap<String, Box<dyn DomainController>>,
+    hostname: &str,
+    addresses: &[IpAddr],
+) -> String {
+    let permitted = user
+        .hostnames
+        .iter()
+        .any(|h| normalize_hostname(h) == normalize_hostname(hostname));
+    if !permitted {
+        eprintln!(
+            "User `{}` may not update `{}`, or it doesn't exist",
+            user.username, hostname
+        );
+        return "nohost".to_string();
+    }
+
+    let resolved = match resolve_hostname(hostname, domain_controllers) {
+        Ok(resolved) => resolved,
+        Err(e) => {
+            eprintln!("Cannot update `{}`: {}", hostname, e);
+            return "nohost".to_string();
+        }
+    };
+
+    let records = match resolved.domain_controller.list_dns_records(client_maker) {
+        Ok(records) => records,
+        Err(e) => {
+            eprintln!("Failed to list the records of `{}`: {}", resolved.zone, e);
+            return "911".to_string();
+        }
+    };
+
+    let mut changed = false;
+    for address in addresses {
+        match sync_ip_record(
+            client_maker,
+            resolved.domain_controller,
+            &records,
+            &resolved.record_name,
+            *address,
+            None,
+        ) {
+            Ok(RecordChange::Unchanged) => {}
+            Ok(RecordChange::Created | RecordChange::Updated { .. }) => changed = true,
+            Err(e) => {
+                eprintln!(
+                    "Failed to update the {} record of `{}`: {}",
+                    IpFamily::of(address).record_type(),
+                    hostname,
+                    e
+                );
+                return "911".to_string();
+            }
+        }
+    }
+
+    let addresses = addresses
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(",");
+
+    if changed {
+        println!(
+            "User `{}` updated `{}` to {}",
+            user.username, hostname, addresses
+        );
+        format!("good {}", addresses)
+    } else {
+        format!("nochg {}", addresses)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        dns_providers::{helpers::build_client, in_memory::InMemory},
+        traits::domain_control::{DnsRecord, DnsRecordType},
+    };
+
+    use super::*;
+
+    fn users() -> Vec<DynDns2User> {
+        vec![DynDns2User {
+            username: "router".to_string(),
+            password: "s3cret:pass".to_string(),
+            hostnames: vec![
+                "home.example.com".to_string(),
+                "nas.example.net".to_string(),
+                "example.com".to_string(),
+            ],
+        }]
+    }
+
+    fn basic(credentials: &str) -> String {
+        format!(
+            "Basic {}",
+            base64::engine::general_purpose::STANDARD.encode(credentials)
+        )
+    }
+
+    fn controllers(zone: &InMemory) -> BTreeMap<String, Box<dyn DomainController>> {
+        BTreeMap::from([(
+            "example.com".to_string(),
+            Box::new(zone.clone()) as Box<dyn DomainController>,
+        )])
+    }
+
+    #[test]
+    fn test_authentication() {
+        let users = users();
+
+        assert_eq!(
+            authenticate(&users, &basic("router:s3cret:pass")).map(|u| &u.username),
+            Some(&"router".to_string())
+        );
+        assert!(authenticate(&users, &basic("router:wrong")).is_none());
+        assert!(authenticate(&users, &basic("nobody:s3cret:pass")).is_none());
+        assert!(authenticate(&users, "Bearer abc").is_none());
+        assert!(authenticate(&users, "Basic !!!").is_none());
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq("s3cret:pass", "s3cret:pass"));
+        assert!(!constant_time_eq("s3cret:pass", "s3cret:pasS"));
+        assert!(!constant_time_eq("s3cret:pass", "s3cret"));
+        assert!(constant_time_eq("", ""));
+    }
+
+    #[test]
+    fn test_parse_addresses() {
+        assert_eq!(
+            parse_addresses("203.0.113.7, 2001:db8::7").unwrap(),
+            vec![
+                "203.0.113.7".parse::<IpAddr>().unwrap(),
+                "2001:db8::7".parse::<IpAddr>().unwrap()
+            ]
+        );
+        assert!(parse_addresses("203.0.113.7,203.0.113.8").is_err());
+        assert!(parse_addresses("not-an-ip").is_err());
+        assert!(parse_addresses("").is_err());
+    }
+
+    #[test]
+    fn test_update_requests() {
+        let zone = InMemory::default();
+        let domain_controllers = controllers(&zone);
+        let client_maker = || build_client(None);
+        let users = users();
+        let authorization = basic("router:s3cret:pass");
+        let remote_ip = Some("198.51.100.1".parse().unwrap());
+
+        let request = |url: &str, authorization: Option<&str>| {
+            handle_request(
+                &client_maker,
+                &users,
+                &domain_controllers,
+                url,
+                authorization,
+                remote_ip,
+            )
+        };
+
+        assert_eq!(
+            request("/nic/update?hostname=home.example.com", None),
+            UpdateResponse::error(401, "badauth")
+        );
+        assert_eq!(
+            request(
+                "/nic/update?hostname=home.example.com",
+                Some(&basic("router:wrong"))
+            ),
+            UpdateResponse::error(401, "badauth")
+        );
+        assert_eq!(
+            request("/other", Some(&authorization)),
+            UpdateResponse::error(404, "notfound")
+        );
+        assert_eq!(
+            request("/nic/update", Some(&authorization)),
+            UpdateResponse::ok("notfqdn")
+        );
+
+        // Not permitted, and permitted but its zone is not configured
+        assert_eq!(
+            request(
+                "/nic/update?hostname=www.example.com,nas.example.net",
+                Some(&authorization)
+            ),
+            UpdateResponse::ok("nohost\nnohost")
+        );
+        assert!(zone.records().is_empty());
+
+        // Without myip, the address of the request is used
+        assert_eq!(
+            request(
+                "/nic/update?hostname=home.example.com",
+                Some(&authorization)
+            ),
+            UpdateResponse::ok("good 198.51.100.1")
+        );
+
+        assert_eq!(
+            request(
+                "/nic/update?hostname=Home.Example.com.&myip=203.0.113.7%2C2001%3Adb8%3A%3A7",
+                Some(&authorization)
+            ),
+            UpdateResponse::ok("good 203.0.113.7,2001:db8::7")
+        );
+        assert_eq!(
+            zone.records(),
+            vec![
+                DnsRecord {
+                    name: "home".to_string(),
+                    record_type: DnsRecordType::A,
+                    value: "203.0.113.7".to_string(),
+                    ttl: None,
+                    priority: None,
+                },
+                DnsRecord {
+                    name: "home".to_string(),
+                    record_type: DnsRecordType::AAAA,
+                    value: "2001:db8::7".to_string(),
+                    ttl: None,
+                    priority: None,
+                },
+            ]
+        );
+
+        assert_eq!(
+            request(
+                "/nic/update?hostname=home.example.com&myip=203.0.113.7",
+                Some(&authorization)
+            ),
+            UpdateResponse::ok("nochg 203.0.113.7")
+        );
+
+        assert_eq!(
+            request(
+                "/nic/update?hostname=home.example.com&myip=999.0.0.1",
+                Some(&authorization)
+            ),
+            UpdateResponse::error(400, "badrequest")
+        );
+    }
+
+    #[test]
+    fn test_zone_apex_update() {
+        let zone = InMemory::default();
+        let domain_controllers = controllers(&zone);
+        let client_maker = || build_client(None);
+
+        assert_eq!(
+            handle_request(
+                &client_maker,
+                &users(),
+                &domain_controllers,
+                "/nic/update?hostname=Example.com.&myip=203.0.113.7",
+                Some(&basic("router:s3cret:pass")),
+                None,
+            ),
+            UpdateResponse::ok("good 203.0.113.7")
+        );
+        assert_eq!(
+            zone.records(),
+            vec![DnsRecord {
+                name: "@".to_string(),
+                record_type: DnsRecordType::A,
+                value: "203.0.113.7".to_string(),
+                ttl: None,
+                priority: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_failed_duplicate_removal_is_a_server_error() {
+        let zone = InMemory::default();
+        let domain_controllers = controllers(&zone);
+        let client_maker = || build_client(None);
+
+        for value in ["203.0.113.7", "203.0.113.8"] {
+            zone.add_dns_record(&client_maker, "home", DnsRecordType::A, value)
+                .unwrap();
+        }
+        zone.fail_removals();
+
+        assert_eq!(
+            handle_request(
+                &client_maker,
+                &users(),
+                &domain_controllers,
+                "/nic/update?hostname=home.example.com&myip=203.0.113.7",
+                Some(&basic("router:s3cret:pass")),
+                None,
+            ),
+            UpdateResponse::ok("911")
+        );
+        assert_eq!(zone.records().len(), 2);
+    }
+}
@@ -0,0 +1,52 @@
+mod logic;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::Config, dns_providers::helpers::build_client,
+    run_options::serve_dyndns2_run_options::ServeDynDns2RunOptions, serve_dyndns2::logic::serve,
+};
+
+/// The `dyndns2_server` section of the config file
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct DynDns2ServerConfig {
+    #[serde(default)]
+    pub users: Vec<DynDns2User>,
+}
+
+/// A user of the dyndns2 server, as configured in the router (or other dyndns client)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DynDns2User {
+    pub username: String,
+    pub password: String,
+    /// The fully qualified names this user may update, e.g., `home.example.com`.
+    /// Their zones must be in the config file.
+    pub hostnames: Vec<String>,
+}
+
+pub fn run(options: ServeDynDns2RunOptions) -> Result<(), Box<dyn std::error::Error>> {
+    options
+        .check()
+        .unwrap_or_else(|e| panic!("Arguments provided are not correct: {}", e));
+
+    println!("Starting in serve-dyndns2 mode with args: {:?}", &options);
+
+    let config = Config::from_file_or_default(&options.config_file_path)?;
+
+    let users = config.dyndns2_server.users.clone();
+    if users.is_empty() {
+        return Err("No users in the `dyndns2_server` section of the config file".into());
+    }
+
+    let domain_controllers = config.into_domain_controllers();
+
+    let proxy = options.proxy.clone();
+    let client_maker = Box::new(|| build_client(proxy.clone()));
+
+    serve(
+        client_maker.as_ref(),
+        options.into_simplified(),
+        &users,
+        domain_controllers,
+    )
+}
@@ -0,0 +1,186 @@
+//! Removal of the records that the tester creates: on failures and panics with a drop guard, and the leftovers
+//! of earlier runs (e.g., killed ones) with `test --cleanup-leftovers`, recognized by their name prefix
+
+use std::collections::BTreeMap;
+
+use crate::traits::domain_control::{DnsRecordType, DomainController};
+
+/// The start of the name of every record the tester creates, so that nothing else is ever taken for one
+pub const TEST_RECORD_PREFIX: &str = "samdnstest-";
+
+/// Removes, when dropped, the tracked records that the test didn't remove itself, so that an error,
+/// a failed check or a panic midway never leaves test records in the zone
+pub struct CreatedRecords<'a> {
+    client_maker: &'a dyn Fn() -> reqwest::blocking::Client,
+    domain_controller: &'a dyn DomainController,
+    records: Vec<(String, DnsRecordType)>,
+}
+
+impl<'a> CreatedRecords<'a> {
+    pub fn new(
+        client_maker: &'a dyn Fn() -> reqwest::blocking::Client,
+        domain_controller: &'a dyn DomainController,
+    ) -> Self {
+        Self {
+            client_maker,
+            domain_controller,
+            records: Vec::new(),
+        }
+    }
+
+    /// To be called before adding, as a failed add may still have created the record
+    pub fn track(&mut self, name: &str, record_type: DnsRecordType) {
+        if !self
+            .records
+            .iter()
+            .any(|r| r.0 == name && r.1 == record_type)
+        {
+            self.records.push((name.to_string(), record_type));
+        }
+    }
+
+    /// The test removed the records of the name and type itself, so there's nothing left to clean up
+    pub fn removed(&mut self, name: &str, record_type: DnsRecordType) {
+        self.records
+            .retain(|r| !(r.0 == name && r.1 == record_type));
+    }
+}
+
+impl Drop for CreatedRecords<'_> {
+    fn drop(&mut self) {
+        for (name, record_type) in self.records.drain(..) {
+            match self.domain_controller.remove_dns_record(
+                self.client_maker,
+                &name,
+                record_type,
+                None,
+            ) {
+                Ok(0) => {}
+                Ok(count) => println!(
+                    "Cleaned up {} test {} record(s) named {}",
+                    count, record_type, name
+                ),
+                Err(e) => eprintln!(
+                    "Could not clean up the test {} record(s) named {}. Remove them with `test --cleanup-leftovers`: {}",
+                    record_type, name, e
+                ),
+            }
+        }
+    }
+}
+
+fn is_test_record_name(name: &str) -> bool {
+    name.to_lowercase().starts_with(TEST_RECORD_PREFIX)
+}
+
+/// Removes the test records that earlier runs left in the zone. Returns how many were removed.
+fn remove_leftovers(
+    client_maker: &dyn Fn() -> reqwest::blocking::Client,
+    domain_controller: &dyn DomainController,
+    domain_name: &str,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let mut leftovers: Vec<(String, DnsRecordType)> = Vec::new();
+    for record in domain_controller.list_dns_records(client_maker)? {
+        if is_test_record_name(&record.name)
+            && !leftovers
+                .iter()
+                .any(|l| l.0 == record.name && l.1 == record.record_type)
+        {
+            leftovers.push((record.name, record.record_type));
+        }
+    }
+
+    let mut removed_count = 0;
+    for (name, record_type) in leftovers {
+        println!(
+            "Removing the leftover test {} record(s) named {} from domain {}",
+            record_type, name, domain_name
+        );
+        removed_count +=
+            domain_controller.remove_dns_record(client_maker, &name, record_type, None)?;
+    }
+
+    Ok(removed_count)
+}
+
+pub fn cleanup_leftovers(
+    client_maker: &dyn Fn() -> reqwest::blocking::Client,
+    domain_controllers: BTreeMap<String, Box<dyn DomainController>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut failures = Vec::new();
+
+    // Every domain is cleaned up, even if another failed
+    for (name, domain_controller) in domain_controllers {
+        match remove_leftovers(client_maker, domain_controller.as_ref(), &name) {
+            Ok(count) => println!(
+                "Removed {} leftover test records from domain {}",
+                count, name
+            ),
+            Err(e) => failures.push(format!("{}: {}", name, e)),
+        }
+    }
+
+    if !failures.is_empty() {
+        return Err(Box::new(std::io::Error::other(format!(
+            "Failed to clean up the leftover test records of: {}",
+            failures.join("; ")
+        ))));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dns_providers::{helpers::build_client, in_memory::InMemory};
+
+    use super::*;
+
+    #[test]
+    fn test_records_are_removed_on_panic() {
+        let zone = InMemory::default();
+        let client_maker = || build_client(None);
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let mut created = CreatedRecords::new(&client_maker, &zone);
+            created.track("samdnstest-abc", DnsRecordType::TXT);
+            zone.add_dns_record(&client_maker, "samdnstest-abc", DnsRecordType::TXT, "1")
+                .unwrap();
+            zone.add_dns_record(&client_maker, "samdnstest-abc", DnsRecordType::TXT, "2")
+                .unwrap();
+            panic!("A check failed midway");
+        }));
+
+        assert!(result.is_err());
+        assert!(zone.records().is_empty());
+    }
+
+    #[test]
+    fn test_remove_leftovers() {
+        let zone = InMemory::default();
+        let client_maker = || build_client(None);
+
+        for (name, record_type) in [
+            ("samdnstest-abc", DnsRecordType::TXT),
+            ("samdnstest-abc", DnsRecordType::TXT),
+            ("SamDnsTest-def.example.com", DnsRecordType::A),
+            ("www", DnsRecordType::A),
+            ("test-abc", DnsRecordType::TXT),
+        ] {
+            zone.add_dns_record(&client_maker, name, record_type, "192.0.2.1")
+                .unwrap();
+        }
+
+        assert_eq!(
+            remove_leftovers(&client_maker, &zone, "example.com").unwrap(),
+            3
+        );
+        assert_eq!(
+            zone.records()
+                .into_iter()
+                .map(|r| r.name)
+                .collect::<Vec<_>>(),
+            vec!["www".to_string(), "test-abc".to_string()]
+        );
+    }
+}
@@ -3,9 +3,15 @@ use crate::{
     run_options::test_domain_controllers_run_options::TestDomainControllersRunOptions,
 };
 
+mod cleanup;
+pub mod report;
 mod tester_inner;
 
 pub fn run(options: TestDomainControllersRunOptions) -> Result<(), Box<dyn std::error::Error>> {
+    options
+        .check()
+        .unwrap_or_else(|e| panic!("Arguments provided are not correct: {}", e));
+
     println!(
         "Starting in domain-controllers test mode with args: {:?}",
         &options
@@ -20,7 +26,30 @@ pub fn run(options: TestDomainControllersRunOptions) -> Result<(), Box<dyn std::
     let proxy = options.proxy.clone();
     let client_maker = Box::new(|| build_client(proxy.clone()));
 
-    tester_inner::run_test(client_maker.as_ref(), domain_controllers)?;
+    if options.cleanup_leftovers {
+        return cleanup::cleanup_leftovers(client_maker.as_ref(), domain_controllers);
+    }
+
+    let report = tester_inner::run_test(client_maker.as_ref(), domain_controllers);
+
+    // Written before failing, as the failures are what the CI and monitoring systems are after
+    if let (Some(format), Some(report_file)) = (options.report_format, &options.report_file) {
+        std::fs::write(report_file, report.render(format)).map_err(|e| {
+            format!(
+                "Could not write the test report to {}: {}",
+                report_file.display(),
+                e
+            )
+        })?;
+        println!("Wrote the test report to {}", report_file.display());
+    }
+
+    let failed_count = report.failed_count();
+    if failed_count > 0 {
+        return Err(format!("{} of {} tests failed", failed_count, report.test_count()).into());
+    }
+
+    println!("All tests have passed successfully.");
 
     Ok(())
 }
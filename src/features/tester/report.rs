@@ -0,0 +1,295 @@
+//! The structured results of `test`, written as JUnit XML, JSON or TAP for CI and monitoring systems
+
+use std::{str::FromStr, time::Duration};
+
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    Junit,
+    Json,
+    Tap,
+}
+
+impl FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "junit" => Ok(ReportFormat::Junit),
+            "json" => Ok(ReportFormat::Json),
+            "tap" => Ok(ReportFormat::Tap),
+            _ => Err(format!("Unknown report format: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum CaseStatus {
+    Passed,
+    Failed { reason: String },
+    Skipped { reason: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TestCase {
+    /// E.g., `singular add/remove` or `MX round trip`
+    pub name: String,
+    pub duration_secs: f64,
+    #[serde(flatten)]
+    pub status: CaseStatus,
+}
+
+impl TestCase {
+    pub fn new(name: &str, duration: Duration, status: CaseStatus) -> Self {
+        Self {
+            name: name.to_string(),
+            duration_secs: duration.as_secs_f64(),
+            status,
+        }
+    }
+}
+
+/// The test cases of one domain of the config file
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ZoneResults {
+    pub zone: String,
+    pub provider: String,
+    pub cases: Vec<TestCase>,
+}
+
+impl ZoneResults {
+    fn count(&self, predicate: impl Fn(&CaseStatus) -> bool) -> usize {
+        self.cases.iter().filter(|c| predicate(&c.status)).count()
+    }
+
+    fn duration_secs(&self) -> f64 {
+        self.cases.iter().map(|c| c.duration_secs).sum()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize)]
+pub struct TestReport {
+    pub zones: Vec<ZoneResults>,
+}
+
+fn is_failed(status: &CaseStatus) -> bool {
+    matches!(status, CaseStatus::Failed { .. })
+}
+
+fn is_skipped(status: &CaseStatus) -> bool {
+    matches!(status, CaseStatus::Skipped { .. })
+}
+
+/// Escapes the text for XML attribute values and contents
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+impl TestReport {
+    pub fn test_count(&self) -> usize {
+        self.zones.iter().map(|z| z.cases.len()).sum()
+    }
+
+    pub fn failed_count(&self) -> usize {
+        self.zones.iter().map(|z| z.count(is_failed)).sum()
+    }
+
+    pub fn render(&self, format: ReportFormat) -> String {
+        match format {
+            ReportFormat::Junit => self.to_junit(),
+            ReportFormat::Json => serde_json::to_string_pretty(self)
+                .expect("Serializing the test report to JSON should never fail"),
+            ReportFormat::Tap => self.to_tap(),
+        }
+    }
+
+    /// One test suite per domain, whose test cases have the domain as class name
+    fn to_junit(&self) -> String {
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+
+        xml += &format!(
+            "<testsuites name=\"sam-dns-tools test\" tests=\"{}\" failures=\"{}\" skipped=\"{}\" time=\"{:.3}\">\n",
+            self.test_count(),
+            self.failed_count(),
+            self.zones
+                .iter()
+                .map(|z| z.count(is_skipped))
+                .sum::<usize>(),
+            self.zones
+                .iter()
+                .map(ZoneResults::duration_secs)
+                .sum::<f64>()
+        );
+
+        for zone in &self.zones {
+            let zone_name = xml_escape(&zone.zone);
+
+            xml += &format!(
+                "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" skipped=\"{}\" time=\"{:.3}\">\n",
+                zone_name,
+                zone.cases.len(),
+                zone.count(is_failed),
+                zone.count(is_skipped),
+                zone.duration_secs()
+            );
+            xml += &format!(
+                "    <properties>\n      <property name=\"provider\" value=\"{}\"/>\n    </properties>\n",
+                xml_escape(&zone.provider)
+            );
+
+            for case in &zone.cases {
+                let opening = format!(
+                    "    <testcase classname=\"{}\" name=\"{}\" time=\"{:.3}\"",
+                    zone_name,
+                    xml_escape(&case.name),
+                    case.duration_secs
+                );
+
+                xml += &match &case.status {
+                    CaseStatus::Passed => format!("{}/>\n", opening),
+                    CaseStatus::Failed { reason } => format!(
+                        "{}>\n      <failure message=\"{}\"/>\n    </testcase>\n",
+                        opening,
+                        xml_escape(reason)
+                    ),
+                    CaseStatus::Skipped { reason } => format!(
+                        "{}>\n      <skipped message=\"{}\"/>\n    </testcase>\n",
+                        opening,
+                        xml_escape(reason)
+                    ),
+                };
+            }
+
+            xml += "  </testsuite>\n";
+        }
+
+        xml += "</testsuites>\n";
+        xml
+    }
+
+    /// TAP version 13, with the failure reasons and durations as YAML diagnostics
+    fn to_tap(&self) -> String {
+        let mut tap = format!("TAP version 13\n1..{}\n", self.test_count());
+
+        let cases = self
+            .zones
+            .iter()
+            .flat_map(|zone| zone.cases.iter().map(move |case| (zone, case)));
+
+        for (index, (zone, case)) in cases.enumerate() {
+            let description = format!("{}: {}", zone.zone, case.name).replace('#', "\\#");
+            let duration_ms = (case.duration_secs * 1000.0).round();
+
+            tap += &match &case.status {
+                CaseStatus::Passed => format!("ok {} - {}\n", index + 1, description),
+                CaseStatus::Skipped { reason } => {
+                    format!("ok {} - {} # SKIP {}\n", index + 1, description, reason)
+                }
+                // A JSON string is a valid YAML string, with the quotes and line breaks escaped
+                CaseStatus::Failed { reason } => format!(
+                    "not ok {} - {}\n  ---\n  message: {}\n  provider: {}\n  duration_ms: {}\n  ...\n",
+                    index + 1,
+                    description,
+                    serde_json::to_string(reason).expect("Serializing a string never fails"),
+                    zone.provider,
+                    duration_ms
+                ),
+            };
+        }
+
+        tap
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report() -> TestReport {
+        TestReport {
+            zones: vec![ZoneResults {
+                zone: "example.com".to_string(),
+                provider: "epik".to_string(),
+                cases: vec![
+                    TestCase::new(
+                        "singular add/remove",
+                        Duration::from_millis(1500),
+                        CaseStatus::Passed,
+                    ),
+                    TestCase::new(
+                        "MX round trip",
+                        Duration::from_millis(250),
+                        CaseStatus::Failed {
+                            reason: "The MX record has the priority 0 instead of <10> & \"more\""
+                                .to_string(),
+                        },
+                    ),
+                    TestCase::new(
+                        "CAA round trip",
+                        Duration::ZERO,
+                        CaseStatus::Skipped {
+                            reason: "not supported by epik".to_string(),
+                        },
+                    ),
+                ],
+            }],
+        }
+    }
+
+    #[test]
+    fn test_junit() {
+        let xml = report().render(ReportFormat::Junit);
+
+        assert!(xml.contains(
+            "<testsuites name=\"sam-dns-tools test\" tests=\"3\" failures=\"1\" skipped=\"1\" time=\"1.750\">"
+        ));
+        assert!(xml.contains(
+            "<testcase classname=\"example.com\" name=\"singular add/remove\" time=\"1.500\"/>"
+        ));
+        assert!(xml.contains(
+            "<failure message=\"The MX record has the priority 0 instead of &lt;10&gt; &amp; &quot;more&quot;\"/>"
+        ));
+        assert!(xml.contains("<skipped message=\"not supported by epik\"/>"));
+        assert!(xml.ends_with("</testsuites>\n"));
+    }
+
+    #[test]
+    fn test_json() {
+        let json: serde_json::Value =
+            serde_json::from_str(&report().render(ReportFormat::Json)).unwrap();
+        let cases = &json["zones"][0]["cases"];
+
+        assert_eq!(json["zones"][0]["provider"], "epik");
+        assert_eq!(cases[0]["status"], "passed");
+        assert_eq!(cases[0]["duration_secs"], 1.5);
+        assert_eq!(cases[1]["status"], "failed");
+        assert!(cases[1]["reason"].as_str().unwrap().starts_with("The MX"));
+        assert_eq!(cases[2]["status"], "skipped");
+    }
+
+    #[test]
+    fn test_tap() {
+        let tap = report().render(ReportFormat::Tap);
+        let lines = tap.lines().collect::<Vec<_>>();
+
+        assert_eq!(lines[0], "TAP version 13");
+        assert_eq!(lines[1], "1..3");
+        assert_eq!(lines[2], "ok 1 - example.com: singular add/remove");
+        assert_eq!(lines[3], "not ok 2 - example.com: MX round trip");
+        assert_eq!(
+            lines[5],
+            "  message: \"The MX record has the priority 0 instead of <10> & \\\"more\\\"\""
+        );
+        assert_eq!(lines[7], "  duration_ms: 250");
+        assert_eq!(
+            lines[9],
+            "ok 3 - example.com: CAA round trip # SKIP not supported by epik"
+        );
+    }
+}
@@ -1,8 +1,35 @@
-use std::collections::BTreeMap;
+use std::{
+    any::Any,
+    collections::BTreeMap,
+    panic::{AssertUnwindSafe, catch_unwind},
+    time::{Duration, Instant},
+};
 
 use rand::Rng;
 
-use crate::{dns_providers::helpers, traits::domain_control::DomainController};
+use crate::{
+    dns_providers::helpers::{self, record_value_matches},
+    traits::domain_control::{DnsRecordType, DomainController, ProviderCapabilities},
+};
+
+use super::{
+    cleanup::{CreatedRecords, TEST_RECORD_PREFIX},
+    report::{CaseStatus, TestCase, TestReport, ZoneResults},
+};
+
+/// The record types of the round trip tests. NS, PTR, SOA and SRV are left out: a made-up NS record
+/// would delegate a part of the zone, and the others need more structure than a test value.
+const ROUND_TRIP_RECORD_TYPES: [DnsRecordType; 6] = [
+    DnsRecordType::A,
+    DnsRecordType::AAAA,
+    DnsRecordType::CNAME,
+    DnsRecordType::MX,
+    DnsRecordType::CAA,
+    DnsRecordType::TXT,
+];
+
+/// Unlike the usual defaults (e.g., 300 or 360), so that a dropped TTL is noticed
+const ROUND_TRIP_TTL: u32 = 3600;
 
 fn random_string(length: usize) -> String {
     use rand::distr::Alphanumeric;
@@ -14,18 +41,99 @@ fn random_string(length: usize) -> String {
         .collect()
 }
 
+/// A random name, recognizable as a test record's by its prefix
+fn test_record_name() -> String {
+    format!("{}{}", TEST_RECORD_PREFIX, random_string(10).to_lowercase())
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    panic
+        .downcast_ref::<&str>()
+        .map(|m| m.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
+
+/// Runs the test case, timing it, and turns its error or panic into a failure, so that the other cases still run
+fn run_case(
+    cases: &mut Vec<TestCase>,
+    domain_name: &str,
+    case_name: &str,
+    test: impl FnOnce() -> Result<(), Box<dyn std::error::Error>>,
+) {
+    let start = Instant::now();
+    let status = match catch_unwind(AssertUnwindSafe(test)) {
+        Ok(Ok(())) => CaseStatus::Passed,
+        Ok(Err(e)) => CaseStatus::Failed {
+            reason: e.to_string(),
+        },
+        Err(panic) => CaseStatus::Failed {
+            reason: format!("Panicked: {}", panic_message(panic.as_ref())),
+        },
+    };
+
+    match &status {
+        CaseStatus::Failed { reason } => {
+            eprintln!("FAIL {}: {}: {}", domain_name, case_name, reason)
+        }
+        _ => println!("PASS {}: {}", domain_name, case_name),
+    }
+
+    cases.push(TestCase::new(case_name, start.elapsed(), status));
+}
+
+/// Runs every test case of every domain, even after failures, and returns their results
 pub fn run_test(
     client_maker: &dyn Fn() -> reqwest::blocking::Client,
     domain_controllers: BTreeMap<String, Box<dyn DomainController>>,
-) -> Result<(), Box<dyn std::error::Error>> {
+) -> TestReport {
+    let mut report = TestReport::default();
+
     for (name, domain_controller) in domain_controllers {
-        test_singular_add_and_delete_record(client_maker, domain_controller.as_ref(), &name)?;
-        test_multiple_add_and_delete_record(client_maker, domain_controller.as_ref(), &name)?;
-    }
+        let domain_controller = domain_controller.as_ref();
+        let mut cases = Vec::new();
+
+        run_case(&mut cases, &name, "singular add/remove", || {
+            test_singular_add_and_delete_record(client_maker, domain_controller, &name)
+        });
+        run_case(&mut cases, &name, "multiple add/remove", || {
+            test_multiple_add_and_delete_record(client_maker, domain_controller, &name)
+        });
 
-    println!("All tests have passed successfully.");
+        let capabilities = domain_controller.capabilities();
+        for record_type in ROUND_TRIP_RECORD_TYPES {
+            let case_name = format!("{} round trip", record_type);
 
-    Ok(())
+            if !capabilities.record_types.contains(&record_type) {
+                let reason = format!("not supported by {}", domain_controller.provider_name());
+                println!("SKIP {}: {}: {}", name, case_name, reason);
+                cases.push(TestCase::new(
+                    &case_name,
+                    Duration::ZERO,
+                    CaseStatus::Skipped { reason },
+                ));
+                continue;
+            }
+
+            run_case(&mut cases, &name, &case_name, || {
+                test_record_round_trip(
+                    client_maker,
+                    domain_controller,
+                    &name,
+                    record_type,
+                    &capabilities,
+                )
+            });
+        }
+
+        report.zones.push(ZoneResults {
+            provider: domain_controller.provider_name().to_string(),
+            zone: name,
+            cases,
+        });
+    }
+
+    report
 }
 
 fn test_singular_add_and_delete_record(
@@ -37,9 +145,13 @@ fn test_singular_add_and_delete_record(
         "Testing domain controller's singular record add/remove for domain: {}",
         domain_name
     );
-    let key = random_string(10).to_lowercase();
+    let key = test_record_name();
     let value = random_string(32);
 
+    // Removed when returning early, or on panic
+    let mut created = CreatedRecords::new(client_maker, domain_controller);
+    created.track(&key, crate::traits::domain_control::DnsRecordType::TXT);
+
     // Add a random record
     domain_controller.add_dns_record(
         client_maker,
@@ -55,19 +167,14 @@ fn test_singular_add_and_delete_record(
         r.name.to_lowercase() == key && helpers::compare_dns_txt_value(&r.value, Some(&value))
     });
 
-    match expected_record {
-        Some(_) => (),
-        None => {
-            eprintln!(
-                "The following record was set but not found in the list: {} {} {}",
-                key,
-                crate::traits::domain_control::DnsRecordType::TXT,
-                value
-            );
-            return Err(Box::new(std::io::Error::other(
-                "Record was not found in the list of recorded provided: {records}",
-            )));
-        }
+    if expected_record.is_none() {
+        return Err(format!(
+            "The TXT record {} with the value {} was set but not found among the {} listed records",
+            key,
+            value,
+            records.len()
+        )
+        .into());
     }
 
     // Remove the record
@@ -77,15 +184,14 @@ fn test_singular_add_and_delete_record(
         crate::traits::domain_control::DnsRecordType::TXT,
         Some(&value),
     )?;
+    created.removed(&key, crate::traits::domain_control::DnsRecordType::TXT);
 
     if removed_count != 1 {
-        eprintln!(
-            "The following record was set but not removed: {} {} {}",
-            key,
-            crate::traits::domain_control::DnsRecordType::TXT,
-            value
-        );
-        return Err(Box::new(std::io::Error::other("Record was not removed")));
+        return Err(format!(
+            "The TXT record {} was not removed: {} records removed instead of 1",
+            key, removed_count
+        )
+        .into());
     }
 
     Ok(())
@@ -101,7 +207,7 @@ fn test_multiple_add_and_delete_record(
         domain_name
     );
     let record_count = rand::rng().random_range::<usize, _>(5..10);
-    let key = random_string(10).to_lowercase();
+    let key = test_record_name();
     let values = (0..record_count)
         .map(|_| random_string(32).to_lowercase())
         .collect::<Vec<_>>();
@@ -111,6 +217,10 @@ fn test_multiple_add_and_delete_record(
         "Values length is not equal to record count"
     );
 
+    // Removed when returning early, or on panic
+    let mut created = CreatedRecords::new(client_maker, domain_controller);
+    created.track(&key, crate::traits::domain_control::DnsRecordType::TXT);
+
     // Add a random records
     for value in &values {
         domain_controller.add_dns_record(
@@ -131,15 +241,14 @@ fn test_multiple_add_and_delete_record(
 
     // The count found should match the one we stored
     if expected_records.len() != record_count {
-        eprintln!(
-            "The following record was set but not found in the list: {} {} {}",
+        return Err(format!(
+            "{} TXT records {} were set but {} were found among the {} listed records",
+            record_count,
             key,
-            crate::traits::domain_control::DnsRecordType::TXT,
-            values.join(", ")
-        );
-        return Err(Box::new(std::io::Error::other(
-            "Record was not found in the list of recorded provided: {records}",
-        )));
+            expected_records.len(),
+            records.len()
+        )
+        .into());
     }
 
     // Make sure every singular value we added exists
@@ -149,15 +258,13 @@ fn test_multiple_add_and_delete_record(
         });
 
         if expected_record.is_none() {
-            eprintln!(
-                "The following record was set but not found in the list: {} {} {}",
+            return Err(format!(
+                "The TXT record {} with the value {} was set but not found among the {} listed records",
                 key,
-                crate::traits::domain_control::DnsRecordType::TXT,
-                value
-            );
-            return Err(Box::new(std::io::Error::other(
-                "Record was not found in the list of records provided: {records}",
-            )));
+                value,
+                records.len()
+            )
+            .into());
         }
     }
 
@@ -168,17 +275,226 @@ fn test_multiple_add_and_delete_record(
         crate::traits::domain_control::DnsRecordType::TXT,
         None,
     )?;
+    created.removed(&key, crate::traits::domain_control::DnsRecordType::TXT);
 
     if removed_count != record_count {
-        eprintln!(
-            "Not all records were successfully removed for key {} {}. Added {} records, removed {}",
-            key,
-            crate::traits::domain_control::DnsRecordType::TXT,
-            record_count,
-            removed_count
-        );
-        return Err(Box::new(std::io::Error::other("Record was not removed")));
+        return Err(format!(
+            "Not all TXT records {} were removed: added {}, removed {}",
+            key, record_count, removed_count
+        )
+        .into());
     }
 
     Ok(())
 }
+
+/// A value of the record type that is harmless to publish: documentation addresses, or the zone itself as target
+fn round_trip_value(record_type: DnsRecordType, domain_name: &str) -> String {
+    let mut rng = rand::rng();
+
+    match record_type {
+        DnsRecordType::A => format!("192.0.2.{}", rng.random_range(1..255)),
+        DnsRecordType::AAAA => format!("2001:db8::{:x}", rng.random_range(1..0xffff)),
+        DnsRecordType::CNAME | DnsRecordType::MX => domain_name.to_string(),
+        DnsRecordType::CAA => "0 issue \"letsencrypt.org\"".to_string(),
+        _ => random_string(32),
+    }
+}
+
+/// Adds a record of the type, with the TTL and the priority if the provider supports them, then finds it in
+/// the list and compares its value, TTL and priority, and removes it
+fn test_record_round_trip(
+    client_maker: &dyn Fn() -> reqwest::blocking::Client,
+    domain_controller: &dyn DomainController,
+    domain_name: &str,
+    record_type: DnsRecordType,
+    capabilities: &ProviderCapabilities,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!(
+        "Testing domain controller's {} record round trip for domain: {}",
+        record_type, domain_name
+    );
+    let key = test_record_name();
+    let value = round_trip_value(record_type, domain_name);
+    let ttl = capabilities.ttl.then_some(ROUND_TRIP_TTL);
+    let priority = (capabilities.priority && record_type == DnsRecordType::MX)
+        .then(|| rand::rng().random_range(1..=50));
+
+    // Removed when returning early, e.g., after a mismatch, or on panic
+    let mut created = CreatedRecords::new(client_maker, domain_controller);
+    created.track(&key, record_type);
+
+    match (priority, ttl) {
+        (Some(priority), ttl) => domain_controller.add_dns_record_with_priority(
+            client_maker,
+            &key,
+            record_type,
+            &value,
+            priority,
+            ttl,
+        )?,
+        (None, Some(ttl)) => domain_controller.add_dns_record_with_ttl(
+            client_maker,
+            &key,
+            record_type,
+            &value,
+            ttl,
+        )?,
+        (None, None) => {
+            domain_controller.add_dns_record(client_maker, &key, record_type, &value)?
+        }
+    }
+
+    let records = domain_controller.list_dns_records(client_maker)?;
+    let found = records
+        .iter()
+        .find(|r| r.name.to_lowercase() == key && r.record_type == record_type);
+
+    let describe = |v: Option<u32>| v.map_or("none".to_string(), |v| v.to_string());
+    let mismatch = match found {
+        None => Some("was set but not found in the list".to_string()),
+        Some(r) if !record_value_matches(record_type, &r.value, &value) => Some(format!(
+            "has the value `{}` instead of `{}`",
+            r.value, value
+        )),
+        Some(r) if ttl.is_some() && r.ttl != ttl => Some(format!(
+            "has the TTL {} instead of {}",
+            describe(r.ttl),
+            describe(ttl)
+        )),
+        Some(r) if priority.is_some() && r.priority != priority => Some(format!(
+            "has the priority {} instead of {}",
+            describe(r.priority),
+            describe(priority)
+        )),
+        Some(_) => None,
+    };
+
+    if let Some(mismatch) = mismatch {
+        return Err(format!("The {} record {} {}", record_type, key, mismatch).into());
+    }
+
+    // By name, in case the provider changed the value's format
+    let removed_count =
+        domain_controller.remove_dns_record(client_maker, &key, record_type, None)?;
+    created.removed(&key, record_type);
+
+    if removed_count != 1 {
+        return Err(format!(
+            "The {} record {} was not removed: {} records removed instead of 1",
+            record_type, key, removed_count
+        )
+        .into());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        dns_providers::{helpers::build_client, in_memory::InMemory},
+        traits::domain_control::DnsRecord,
+    };
+
+    use super::*;
+
+    /// Drops the TTL, like a broken provider implementation would
+    struct TtlDroppingController(InMemory);
+
+    impl DomainController for TtlDroppingController {
+        fn provider_name(&self) -> &'static str {
+            "ttl-dropping"
+        }
+
+        fn add_dns_record(
+            &self,
+            client_maker: &dyn Fn() -> reqwest::blocking::Client,
+            name: &str,
+            record_type: DnsRecordType,
+            value: &str,
+        ) -> Result<(), Box<dyn std::error::Error>> {
+            self.0
+                .add_dns_record(client_maker, name, record_type, value)
+        }
+
+        fn remove_dns_record(
+            &self,
+            client_maker: &dyn Fn() -> reqwest::blocking::Client,
+            name: &str,
+            record_type: DnsRecordType,
+            value: Option<&str>,
+        ) -> Result<usize, Box<dyn std::error::Error>> {
+            self.0
+                .remove_dns_record(client_maker, name, record_type, value)
+        }
+
+        fn list_dns_records(
+            &self,
+            client_maker: &dyn Fn() -> reqwest::blocking::Client,
+        ) -> Result<Vec<DnsRecord>, Box<dyn std::error::Error>> {
+            self.0.list_dns_records(client_maker)
+        }
+    }
+
+    #[test]
+    fn test_round_trips() {
+        let zone = InMemory::default();
+        let client_maker = || build_client(None);
+
+        let mut domain_controllers: BTreeMap<String, Box<dyn DomainController>> = BTreeMap::new();
+        domain_controllers.insert("example.com".to_string(), Box::new(zone.clone()));
+        let report = run_test(&client_maker, domain_controllers);
+        assert_eq!(report.zones[0].provider, "in-memory");
+        assert_eq!(report.test_count(), 8);
+        assert_eq!(report.failed_count(), 0);
+        assert!(zone.records().is_empty());
+
+        // Claiming TTL support without applying it fails, and the record is cleaned up anyway
+        let broken = TtlDroppingController(InMemory::default());
+        let capabilities = ProviderCapabilities {
+            record_types: vec![DnsRecordType::A],
+            ttl: true,
+            priority: false,
+        };
+        let error = test_record_round_trip(
+            &client_maker,
+            &broken,
+            "example.com",
+            DnsRecordType::A,
+            &capabilities,
+        )
+        .unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("has the TTL none instead of 3600")
+        );
+        assert!(broken.0.records().is_empty());
+    }
+
+    #[test]
+    fn test_failures_are_reported() {
+        let mut cases = Vec::new();
+        run_case(&mut cases, "example.com", "erroring", || {
+            Err("The TXT record was not found".into())
+        });
+        run_case(&mut cases, "example.com", "panicking", || {
+            panic!("A check failed: {}", 42)
+        });
+        run_case(&mut cases, "example.com", "passing", || Ok(()));
+
+        assert_eq!(
+            cases.into_iter().map(|c| c.status).collect::<Vec<_>>(),
+            vec![
+                CaseStatus::Failed {
+                    reason: "The TXT record was not found".to_string()
+                },
+                CaseStatus::Failed {
+                    reason: "Panicked: A check failed: 42".to_string()
+                },
+                CaseStatus::Passed,
+            ]
+        );
+    }
+}
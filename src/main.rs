@@ -1,5 +1,7 @@
 use clap::Parser;
-use features::{certbot, dyndns, tester};
+use features::{
+    acme_sh, certbot, dhcp_leases, dns_persist, dyndns, issue, lego_exec, serve_dyndns2, tester,
+};
 
 use crate::run_options::RunOptions;
 
@@ -9,13 +11,26 @@ mod config;
 mod dns_providers;
 mod features;
 mod traits;
+mod utils;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = RunOptions::parse();
 
     match args.command {
-        run_options::RunCommand::Certbot(options) => certbot::run(options),
+        run_options::RunCommand::Certbot(options) => {
+            if let Err(e) = certbot::run(options) {
+                eprintln!("Error: {}", e);
+                std::process::exit(e.exit_code());
+            }
+            Ok(())
+        }
+        run_options::RunCommand::LegoExec(options) => lego_exec::run(options),
+        run_options::RunCommand::AcmeSh(options) => acme_sh::run(options),
+        run_options::RunCommand::DnsPersist(options) => dns_persist::run(options),
+        run_options::RunCommand::Issue(options) => issue::run(options),
         run_options::RunCommand::Dyndns(options) => dyndns::run(options),
+        run_options::RunCommand::ServeDyndns2(options) => serve_dyndns2::run(options),
+        run_options::RunCommand::DhcpLeases(options) => dhcp_leases::run(options),
         run_options::RunCommand::Test(options) => tester::run(options),
     }
 }
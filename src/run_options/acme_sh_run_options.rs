@@ -0,0 +1,79 @@
+use std::str::FromStr;
+
+use clap::Parser;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AcmeShOperation {
+    Add,
+    Rm,
+}
+
+impl FromStr for AcmeShOperation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "add" => Ok(AcmeShOperation::Add),
+            "rm" => Ok(AcmeShOperation::Rm),
+            _ => Err(format!("Unknown acme.sh operation: {}", s)),
+        }
+    }
+}
+
+#[derive(Parser, Clone, Debug, Default)]
+pub struct AcmeShRunOptions {
+    /// The operation, as in acme.sh's dnsapi functions. Possible values: `add` or `rm`
+    /// add: `dns_samdns_add`, set the TXT DNS record
+    /// rm: `dns_samdns_rm`, remove the TXT DNS record
+    pub operation: Option<AcmeShOperation>,
+
+    /// The full domain name of the TXT record, e.g., `_acme-challenge.www.example.com`
+    pub fulldomain: Option<String>,
+
+    /// The TXT record value
+    #[clap(allow_hyphen_values = true)]
+    pub txtvalue: Option<String>,
+
+    /// The path to the config file
+    /// If not provided, the default value is used, config.yaml
+    #[clap(long, default_value_t = super::DEFAULT_CONFIG_FILE_PATH.to_string())]
+    pub config_file_path: String,
+
+    /// Proxy address, such as http, https or socks5, through which the connections to the API will be made
+    /// Example: socks5://example.com:1080
+    /// This helps to circumvent IP whitelisting requirements for some DNS providers
+    #[clap(long)]
+    pub proxy: Option<String>,
+}
+
+impl AcmeShRunOptions {
+    pub fn check(&self) -> Result<(), String> {
+        if self.operation.is_none() {
+            return Err("Operation not provided".to_string());
+        }
+
+        if self.fulldomain.is_none() {
+            return Err("Full domain not provided".to_string());
+        }
+
+        if self.txtvalue.is_none() {
+            return Err("TXT value not provided".to_string());
+        }
+
+        Ok(())
+    }
+
+    pub fn into_simplified(self) -> SimplifiedAcmeShRunOptions {
+        SimplifiedAcmeShRunOptions {
+            operation: self.operation.unwrap(),
+            fulldomain: self.fulldomain.unwrap(),
+            txtvalue: self.txtvalue.unwrap(),
+        }
+    }
+}
+
+pub struct SimplifiedAcmeShRunOptions {
+    pub operation: AcmeShOperation,
+    pub fulldomain: String,
+    pub txtvalue: String,
+}
@@ -2,6 +2,8 @@ use std::str::FromStr;
 
 use clap::Parser;
 
+use crate::dns_providers::helpers::validate_proxy;
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Operation {
     SetRecord,
@@ -20,6 +22,53 @@ impl FromStr for Operation {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(format!("Unknown output format: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CleanupMode {
+    /// Cleanup succeeds even if the record is already gone, so that repeated cleanups are harmless
+    #[default]
+    Idempotent,
+    /// Cleanup fails if no record was removed
+    Strict,
+}
+
+impl FromStr for CleanupMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "idempotent" => Ok(CleanupMode::Idempotent),
+            "strict" => Ok(CleanupMode::Strict),
+            _ => Err(format!("Unknown cleanup mode: {}", s)),
+        }
+    }
+}
+
+/// Exit codes:
+/// 0: success
+/// 2: invalid arguments
+/// 10: configuration error (e.g., missing or invalid config file, or no zone for the domain)
+/// 11: authentication error with the DNS provider (HTTP 401/403)
+/// 12: DNS provider failure
+/// 13: strict cleanup found no record to remove
 #[derive(Parser, Clone, Debug, Default)]
 pub struct CertbotRunOptions {
     /// the domain name, whose DNS records will be updated ($CERTBOT_DOMAIN goes here)
@@ -48,6 +97,18 @@ pub struct CertbotRunOptions {
     /// This helps to circumvent IP whitelisting requirements for some DNS providers
     #[clap(long)]
     pub proxy: Option<String>,
+
+    /// The output format. Possible values: `text` or `json`
+    /// json: print a single JSON object with the zone, record name, value, provider and the records added or removed,
+    /// or the error kind, message and exit code
+    #[clap(long, default_value = "text")]
+    pub output: OutputFormat,
+
+    /// How cleanup treats a record that is already gone. Possible values: `idempotent` or `strict`
+    /// idempotent: succeed anyway
+    /// strict: fail with exit code 13
+    #[clap(long, default_value = "idempotent")]
+    pub cleanup_mode: CleanupMode,
 }
 
 impl CertbotRunOptions {
@@ -63,6 +124,11 @@ impl CertbotRunOptions {
         if self.validation_string.is_none() {
             return Err("Validation string not provided".to_string());
         }
+
+        if let Some(proxy) = &self.proxy {
+            validate_proxy(proxy)?;
+        }
+
         Ok(())
     }
 
@@ -71,6 +137,7 @@ impl CertbotRunOptions {
             domain_name: self.domain_name.unwrap(),
             operation: self.operation.unwrap(),
             validation_string: self.validation_string.unwrap(),
+            cleanup_mode: self.cleanup_mode,
         }
     }
 }
@@ -79,4 +146,5 @@ pub struct SimplifiedCertbotRunOptions {
     pub domain_name: String,
     pub operation: Operation,
     pub validation_string: String,
+    pub cleanup_mode: CleanupMode,
 }
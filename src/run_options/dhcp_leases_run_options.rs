@@ -0,0 +1,49 @@
+use std::path::PathBuf;
+
+use clap::Parser;
+
+#[derive(Parser, Clone, Debug, Default)]
+pub struct DhcpLeasesRunOptions {
+    /// The path to the config file
+    /// If not provided, the default value is used, config.yaml
+    /// The lease files, the zone and the subdomain are in its `dhcp_leases` section
+    #[clap(long, default_value_t = super::DEFAULT_CONFIG_FILE_PATH.to_string())]
+    pub config_file_path: String,
+
+    /// Proxy address, such as http, https or socks5, through which the connections to the API will be made
+    /// Example: socks5://example.com:1080
+    /// This helps to circumvent IP whitelisting requirements for some DNS providers
+    #[clap(long)]
+    pub proxy: Option<String>,
+
+    /// The file where the records created by this tool are remembered. Only these records are ever
+    /// updated or removed; records set by other means are left as they are.
+    #[clap(long, default_value = "dhcp_leases_state.json")]
+    pub state_file: PathBuf,
+
+    /// Remove the records of hosts whose leases expired or disappeared from the lease files
+    #[clap(long)]
+    pub remove_expired: bool,
+}
+
+impl DhcpLeasesRunOptions {
+    pub fn check(&self) -> Result<(), String> {
+        if self.state_file.as_os_str().is_empty() {
+            return Err("State file path is empty".to_string());
+        }
+
+        Ok(())
+    }
+
+    pub fn into_simplified(self) -> SimplifiedDhcpLeasesRunOptions {
+        SimplifiedDhcpLeasesRunOptions {
+            state_file_path: self.state_file,
+            remove_expired: self.remove_expired,
+        }
+    }
+}
+
+pub struct SimplifiedDhcpLeasesRunOptions {
+    pub state_file_path: PathBuf,
+    pub remove_expired: bool,
+}
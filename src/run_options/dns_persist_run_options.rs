@@ -0,0 +1,111 @@
+use std::str::FromStr;
+
+use clap::Parser;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PersistOperation {
+    Set,
+    Audit,
+    Remove,
+}
+
+impl FromStr for PersistOperation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "set" => Ok(PersistOperation::Set),
+            "audit" => Ok(PersistOperation::Audit),
+            "remove" => Ok(PersistOperation::Remove),
+            _ => Err(format!("Unknown operation: {}", s)),
+        }
+    }
+}
+
+#[derive(Parser, Clone, Debug, Default)]
+pub struct DnsPersistRunOptions {
+    /// The domain name to be validated, e.g., example.com or www.example.com.
+    /// The record `_validation-persist.<domain-name>` is managed.
+    /// Note that the zone of this domain must be present in the config file
+    #[clap(long)]
+    pub domain_name: Option<String>,
+
+    /// Operation to be done. Possible values: `set`, `audit` or `remove`
+    /// set: create the persistent validation record (replacing older records of the same issuer and account)
+    /// audit: list the persistent validation records, and report malformed, expired or unexpected ones
+    /// remove: remove the persistent validation records of the issuer (and the account, if provided)
+    #[clap(long)]
+    pub operation: Option<PersistOperation>,
+
+    /// The issuer domain name of the CA, as published in its CAA identities, e.g., letsencrypt.org
+    #[clap(long)]
+    pub issuer_domain_name: Option<String>,
+
+    /// The ACME account URI that is authorized to issue, e.g., https://acme-v02.api.letsencrypt.org/acme/acct/1234
+    /// Required for set
+    #[clap(long)]
+    pub account_uri: Option<String>,
+
+    /// Authorize wildcard certificates as well (`policy=wildcard`)
+    #[clap(long)]
+    pub wildcard: bool,
+
+    /// The time (UNIX timestamp, in seconds) after which the CA must not use the record anymore (`persistUntil`)
+    /// If not provided, the record doesn't expire
+    #[clap(long)]
+    pub persist_until: Option<u64>,
+
+    /// The path to the config file
+    /// If not provided, the default value is used, config.yaml
+    #[clap(long, default_value_t = super::DEFAULT_CONFIG_FILE_PATH.to_string())]
+    pub config_file_path: String,
+
+    /// Proxy address, such as http, https or socks5, through which the connections to the API will be made
+    /// Example: socks5://example.com:1080
+    /// This helps to circumvent IP whitelisting requirements for some DNS providers
+    #[clap(long)]
+    pub proxy: Option<String>,
+}
+
+impl DnsPersistRunOptions {
+    pub fn check(&self) -> Result<(), String> {
+        if self.domain_name.is_none() {
+            return Err("Domain name not provided".to_string());
+        }
+
+        let operation = match &self.operation {
+            Some(operation) => operation,
+            None => return Err("Operation not provided".to_string()),
+        };
+
+        if *operation != PersistOperation::Audit && self.issuer_domain_name.is_none() {
+            return Err("Issuer domain name not provided".to_string());
+        }
+
+        if *operation == PersistOperation::Set && self.account_uri.is_none() {
+            return Err("Account URI not provided".to_string());
+        }
+
+        Ok(())
+    }
+
+    pub fn into_simplified(self) -> SimplifiedDnsPersistRunOptions {
+        SimplifiedDnsPersistRunOptions {
+            domain_name: self.domain_name.unwrap(),
+            operation: self.operation.unwrap(),
+            issuer_domain_name: self.issuer_domain_name,
+            account_uri: self.account_uri,
+            wildcard: self.wildcard,
+            persist_until: self.persist_until,
+        }
+    }
+}
+
+pub struct SimplifiedDnsPersistRunOptions {
+    pub domain_name: String,
+    pub operation: PersistOperation,
+    pub issuer_domain_name: Option<String>,
+    pub account_uri: Option<String>,
+    pub wildcard: bool,
+    pub persist_until: Option<u64>,
+}
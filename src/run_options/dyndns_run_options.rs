@@ -1,7 +1,49 @@
-use clap::Parser;
+use std::{path::PathBuf, str::FromStr, time::Duration};
+
+use clap::{Parser, Subcommand};
+use serde::{Deserialize, Serialize};
+
+use crate::features::dyndns::{
+    daemon::DaemonOptions,
+    history::{ChangeHistory, parse_time},
+    ip_detection::{IpSource, IpSourceKind},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FamilySelection {
+    #[default]
+    V4,
+    V6,
+    Both,
+}
+
+impl FromStr for FamilySelection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "v4" => Ok(FamilySelection::V4),
+            "v6" => Ok(FamilySelection::V6),
+            "both" => Ok(FamilySelection::Both),
+            _ => Err(format!("Unknown IP family: {}", s)),
+        }
+    }
+}
+
+#[derive(Subcommand, Clone, Debug)]
+pub enum DynDnsCommand {
+    /// Show the local history of the record changes (see --history-file), with a summary per record:
+    /// how often it changed, failed updates, and the address held the longest
+    History(DynDnsHistoryRunOptions),
+}
 
 #[derive(Parser, Clone, Debug, Default)]
+#[clap(args_conflicts_with_subcommands = true)]
 pub struct DynDnsRunOptions {
+    #[clap(subcommand)]
+    pub command: Option<DynDnsCommand>,
+
     /// the domain name (as in account), whose DNS records will be updated
     /// DO NOT put the subdomain here for your dyndns without understanding the implications.
     /// This is used to decide which authentication information to use from the config file.
@@ -25,10 +67,138 @@ pub struct DynDnsRunOptions {
     /// This helps to circumvent IP whitelisting requirements for some DNS providers
     #[clap(long)]
     pub proxy: Option<String>,
+
+    /// Proxy address, such as http, https or socks5, through which the public IP address is detected with `--ip-source web`.
+    /// The published address is then the egress address of the proxy.
+    /// Independent of --proxy: if not provided, the detection connects directly, even if --proxy is provided.
+    #[clap(long)]
+    pub ip_detection_proxy: Option<String>,
+
+    /// The IP address families to update. Possible values: `v4`, `v6` or `both`
+    /// v4: the A record, v6: the AAAA record, both: the A and the AAAA records.
+    /// With `both`, a family whose public address cannot be detected is skipped, and the other is still updated.
+    /// If not provided, v4 is used. Jobs have their own `family` in the config file.
+    #[clap(long)]
+    pub family: Option<FamilySelection>,
+
+    /// Where the public IP address is detected from. Possible values: `web`, `interface`, `dns`, `stun` or `gateway`
+    /// web: ask public web services for the address they see
+    /// interface: use the address of a local network interface (see --interface). Private, link-local,
+    /// unique local (ULA) and temporary (privacy) IPv6 addresses are ignored.
+    /// dns: ask DNS servers that answer with the address of the client (OpenDNS and Google by default)
+    /// stun: ask STUN servers for the address mapped by the NAT (Google and Cloudflare by default)
+    /// gateway: ask the home router for its WAN address over PCP, NAT-PMP or UPnP-IGD (IPv6 over PCP only)
+    /// Multiple sources can be separated by commas, e.g., `web,dns`. They're tried in order, or,
+    /// with a quorum in the config file, all their services vote together.
+    /// If not provided, web is used. Jobs have their own `ip_source` in the config file.
+    #[clap(long, value_delimiter = ',')]
+    pub ip_source: Vec<IpSourceKind>,
+
+    /// The name of the network interface to take the address from, e.g., eth0 or ppp0, with `--ip-source interface`.
+    /// If not provided, all interfaces are considered, and the address must be found on only one of them.
+    #[clap(long)]
+    pub interface: Option<String>,
+
+    /// Publish the detected address even if it's in a special-use (bogon) range, e.g., private (RFC 1918),
+    /// CGNAT (100.64.0.0/10) or documentation addresses. Without it, such addresses are refused,
+    /// as they usually come from a wrong answer or a misconfigured route.
+    /// Also applies to the jobs of the config file.
+    #[clap(long)]
+    pub allow_bogon: bool,
+
+    /// Keep running, and check the public IP address every --interval seconds.
+    /// The provider's API is only called when the address changed since it was last published
+    /// (as remembered in --state-file), or when a full reconcile is due (every --reconcile-interval seconds).
+    /// Stops cleanly on SIGTERM or SIGINT.
+    #[clap(long)]
+    pub daemon: bool,
+
+    /// Seconds between checks of the public IP address in daemon mode
+    #[clap(long = "interval", default_value_t = 300)]
+    pub interval_secs: u64,
+
+    /// Seconds between full reconciles in daemon mode, in which the records are checked at the provider even if
+    /// the address didn't change, e.g., to fix records that were changed by someone else
+    #[clap(long = "reconcile-interval", default_value_t = 86400)]
+    pub reconcile_interval_secs: u64,
+
+    /// The maximum number of seconds to wait before retrying after provider errors in daemon mode.
+    /// The wait starts at --interval and doubles with every consecutive failure.
+    #[clap(long = "max-backoff", default_value_t = 3600)]
+    pub max_backoff_secs: u64,
+
+    /// The file where the daemon keeps the last published addresses, so that restarts don't call the provider
+    #[clap(long, default_value = "dyndns_state.json")]
+    pub state_file: PathBuf,
+
+    /// In daemon mode, also check the public IP address as soon as an address is added to or removed from
+    /// this network interface, e.g., ppp0 after a PPPoE reconnect, instead of waiting for the next --interval.
+    /// Uses rtnetlink, so it's only available on Linux. Polling every --interval continues as a fallback.
+    #[clap(long)]
+    pub watch_interface: Option<String>,
+
+    /// Seconds the watched interface must stay without address changes before the check,
+    /// as a reconnect usually removes and adds several addresses in a row
+    #[clap(long = "debounce", default_value_t = 2)]
+    pub debounce_secs: u64,
+
+    /// Keep the history of the record changes in this file (JSON Lines), to which every change is appended:
+    /// when, the old and the new value, where the value was detected and whether the provider accepted it.
+    /// See `dyndns history`. Without it, no history is kept.
+    #[clap(long)]
+    pub history_file: Option<PathBuf>,
 }
 
 impl DynDnsRunOptions {
+    /// Without a domain name and a subdomain, the jobs in the config file are run
+    pub fn jobs_mode(&self) -> bool {
+        self.account_domain_name.is_none() && self.subdomain.is_none()
+    }
+
+    /// The sources from --ip-source, or web if not provided
+    fn ip_sources(&self) -> Vec<IpSourceKind> {
+        if self.ip_source.is_empty() {
+            vec![IpSourceKind::default()]
+        } else {
+            self.ip_source.clone()
+        }
+    }
+
+    /// Where the record changes are appended, if enabled with --history-file
+    pub fn history(&self) -> Option<ChangeHistory> {
+        self.history_file.clone().map(ChangeHistory::new)
+    }
+
     pub fn check(&self) -> Result<(), String> {
+        if self.watch_interface.is_some() && !self.daemon {
+            return Err("--watch-interface can only be used with --daemon".to_string());
+        }
+
+        if self.jobs_mode() {
+            if self.daemon {
+                return Err("--daemon requires --account-domain-name and --subdomain".to_string());
+            }
+
+            // The jobs have their own in the config file, so these would be silently ignored
+            let single_record_flags = [
+                ("--family", self.family.is_some()),
+                ("--ip-source", !self.ip_source.is_empty()),
+                ("--interface", self.interface.is_some()),
+            ]
+            .into_iter()
+            .filter_map(|(flag, provided)| provided.then_some(flag))
+            .collect::<Vec<_>>();
+            if !single_record_flags.is_empty() {
+                return Err(format!(
+                    "{} can only be used with --account-domain-name and --subdomain. \
+                     The jobs of the config file have their own settings",
+                    single_record_flags.join(", ")
+                ));
+            }
+
+            return Ok(());
+        }
+
         if self.account_domain_name.is_none() {
             return Err("Account domain name not provided".to_string());
         }
@@ -39,13 +209,44 @@ impl DynDnsRunOptions {
             );
         }
 
+        if self.ip_detection_proxy.is_some() && !self.ip_sources().contains(&IpSourceKind::Web) {
+            return Err("--ip-detection-proxy can only be used with --ip-source web".to_string());
+        }
+
+        if self.interface.is_some() && !self.ip_sources().contains(&IpSourceKind::Interface) {
+            return Err("--interface can only be used with --ip-source interface".to_string());
+        }
+
+        if self.daemon && self.interval_secs == 0 {
+            return Err("--interval must be at least one second".to_string());
+        }
+
         Ok(())
     }
 
     pub fn into_simplified(self) -> SimplifiedDynDnsRunOptions {
+        let history = self.history();
+        let ip_sources = self
+            .ip_sources()
+            .iter()
+            .map(|kind| IpSource::from_kind(*kind, self.interface.clone()))
+            .collect();
+
         SimplifiedDynDnsRunOptions {
             account_domain_name: self.account_domain_name.unwrap(),
             subdomain: self.subdomain.unwrap(),
+            family: self.family.unwrap_or_default(),
+            allow_bogon: self.allow_bogon,
+            history,
+            ip_sources,
+            daemon: self.daemon.then(|| DaemonOptions {
+                interval: Duration::from_secs(self.interval_secs),
+                reconcile_interval: Duration::from_secs(self.reconcile_interval_secs),
+                max_backoff: Duration::from_secs(self.max_backoff_secs),
+                state_file_path: self.state_file.clone(),
+                watch_interface: self.watch_interface.clone(),
+                debounce: Duration::from_secs(self.debounce_secs),
+            }),
         }
     }
 }
@@ -53,4 +254,75 @@ impl DynDnsRunOptions {
 pub struct SimplifiedDynDnsRunOptions {
     pub account_domain_name: String,
     pub subdomain: String,
+    pub family: FamilySelection,
+    pub allow_bogon: bool,
+    pub ip_sources: Vec<IpSource>,
+    /// Set in daemon mode
+    pub daemon: Option<DaemonOptions>,
+    pub history: Option<ChangeHistory>,
+}
+
+#[derive(Parser, Clone, Debug, Default)]
+pub struct DynDnsHistoryRunOptions {
+    /// The history file written by dyndns, as in its --history-file
+    #[clap(long)]
+    pub history_file: PathBuf,
+
+    /// Only the changes since this time: a duration ago (e.g., `12h` or `7d`), a date (`2026-10-01`),
+    /// a date and time in UTC (`2026-10-01T08:00:00Z`) or seconds since the Unix epoch
+    #[clap(long)]
+    pub since: Option<String>,
+
+    /// Only the changes until this time, in the same formats as --since
+    #[clap(long)]
+    pub until: Option<String>,
+
+    /// Only the changes of this record, e.g., `home.example.com`
+    #[clap(long)]
+    pub record: Option<String>,
+}
+
+impl DynDnsHistoryRunOptions {
+    pub fn check(&self, now: u64) -> Result<(), String> {
+        let since = self
+            .since
+            .as_deref()
+            .map(|v| parse_time(v, now))
+            .transpose()?;
+        let until = self
+            .until
+            .as_deref()
+            .map(|v| parse_time(v, now))
+            .transpose()?;
+
+        if let (Some(since), Some(until)) = (since, until)
+            && since > until
+        {
+            return Err("--since must be before --until".to_string());
+        }
+
+        Ok(())
+    }
+
+    pub fn into_simplified(self, now: u64) -> SimplifiedDynDnsHistoryRunOptions {
+        let parse = |value: Option<String>| value.map(|v| parse_time(&v, now).unwrap());
+
+        SimplifiedDynDnsHistoryRunOptions {
+            history_file_path: self.history_file,
+            since: parse(self.since),
+            until: parse(self.until),
+            record: self.record.map(|r| r.trim_end_matches('.').to_string()),
+            now,
+        }
+    }
+}
+
+pub struct SimplifiedDynDnsHistoryRunOptions {
+    pub history_file_path: PathBuf,
+    /// Seconds since the Unix epoch
+    pub since: Option<u64>,
+    pub until: Option<u64>,
+    pub record: Option<String>,
+    /// The end of the window when --until is not provided
+    pub now: u64,
 }
@@ -0,0 +1,102 @@
+use std::path::PathBuf;
+
+use clap::Parser;
+
+/// The production directory of Let's Encrypt
+pub const LETSENCRYPT_DIRECTORY_URL: &str = "https://acme-v02.api.letsencrypt.org/directory";
+
+#[derive(Parser, Clone, Debug, Default)]
+pub struct IssueRunOptions {
+    /// The domain names to be in the certificate. Can be provided multiple times.
+    /// Wildcards are supported, e.g., `--domain-name example.com --domain-name '*.example.com'`
+    /// Note that the zones of these names must be present in the config file
+    #[clap(long, short = 'd', required = true)]
+    pub domain_name: Vec<String>,
+
+    /// The ACME directory URL of the certificate authority.
+    /// For a local Pebble test server, this is usually https://localhost:14000/dir
+    #[clap(long, default_value_t = LETSENCRYPT_DIRECTORY_URL.to_string())]
+    pub acme_directory_url: String,
+
+    /// Agree to the terms of service of the certificate authority. Required to create an account.
+    #[clap(long)]
+    pub agree_tos: bool,
+
+    /// The contact email of the ACME account (optional)
+    #[clap(long)]
+    pub contact_email: Option<String>,
+
+    /// The path to the account key (PEM, PKCS#8). If the file doesn't exist, a new key is created and saved there.
+    /// The account belonging to an existing key is reused.
+    #[clap(long, default_value = "acme_account_key.pem")]
+    pub account_key_path: PathBuf,
+
+    /// The path where the issued certificate chain (PEM) will be written
+    #[clap(long, default_value = "fullchain.pem")]
+    pub cert_path: PathBuf,
+
+    /// The path where the private key of the certificate (PEM, PKCS#8) will be written
+    #[clap(long, default_value = "privkey.pem")]
+    pub key_path: PathBuf,
+
+    /// An extra root certificate (PEM) to trust for the ACME server's TLS, e.g., Pebble's `pebble.minica.pem`
+    #[clap(long)]
+    pub acme_ca_cert_path: Option<PathBuf>,
+
+    /// Seconds to wait after setting the DNS records, and before asking the CA to validate them
+    #[clap(long, default_value_t = 30)]
+    pub propagation_delay_secs: u64,
+
+    /// Seconds to wait for the CA to validate challenges and to issue the certificate before giving up
+    #[clap(long, default_value_t = 300)]
+    pub poll_timeout_secs: u64,
+
+    /// The path to the config file
+    /// If not provided, the default value is used, config.yaml
+    #[clap(long, default_value_t = super::DEFAULT_CONFIG_FILE_PATH.to_string())]
+    pub config_file_path: String,
+
+    /// Proxy address, such as http, https or socks5, through which the connections to the API will be made
+    /// Example: socks5://example.com:1080
+    /// This helps to circumvent IP whitelisting requirements for some DNS providers
+    /// Note: The connections to the ACME server do not use this proxy
+    #[clap(long)]
+    pub proxy: Option<String>,
+}
+
+impl IssueRunOptions {
+    pub fn check(&self) -> Result<(), String> {
+        if self.domain_name.is_empty() {
+            return Err("No domain names provided".to_string());
+        }
+
+        if !self.agree_tos {
+            return Err(
+                "The terms of service of the certificate authority must be agreed to with --agree-tos"
+                    .to_string(),
+            );
+        }
+
+        Ok(())
+    }
+
+    pub fn into_simplified(self) -> SimplifiedIssueRunOptions {
+        SimplifiedIssueRunOptions {
+            domain_names: self.domain_name,
+            contact_email: self.contact_email,
+            cert_path: self.cert_path,
+            key_path: self.key_path,
+            propagation_delay: std::time::Duration::from_secs(self.propagation_delay_secs),
+            poll_timeout: std::time::Duration::from_secs(self.poll_timeout_secs),
+        }
+    }
+}
+
+pub struct SimplifiedIssueRunOptions {
+    pub domain_names: Vec<String>,
+    pub contact_email: Option<String>,
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+    pub propagation_delay: std::time::Duration,
+    pub poll_timeout: std::time::Duration,
+}
@@ -0,0 +1,136 @@
+use std::str::FromStr;
+
+use clap::Parser;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LegoOperation {
+    Present,
+    Cleanup,
+}
+
+impl FromStr for LegoOperation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "present" => Ok(LegoOperation::Present),
+            "cleanup" => Ok(LegoOperation::Cleanup),
+            _ => Err(format!("Unknown lego operation: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum LegoExecMode {
+    /// lego computes the record name and value: `<fqdn> <value>`
+    #[default]
+    Default,
+    /// lego passes the raw challenge data: `-- <domain> <token> <key-authorization>`
+    Raw,
+}
+
+impl FromStr for LegoExecMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "" | "default" => Ok(LegoExecMode::Default),
+            "raw" => Ok(LegoExecMode::Raw),
+            _ => Err(format!("Unknown lego exec mode: {}", s)),
+        }
+    }
+}
+
+#[derive(Parser, Clone, Debug, Default)]
+pub struct LegoExecRunOptions {
+    /// The operation lego requests. Possible values: `present` or `cleanup`
+    /// present: set the TXT DNS record of the challenge
+    /// cleanup: remove the TXT DNS record of the challenge
+    pub operation: Option<LegoOperation>,
+
+    /// The arguments lego passes after the operation.
+    /// In the default mode: `<fqdn> <value>`, e.g., `_acme-challenge.example.com. abcdef`
+    /// In the raw mode: `-- <domain> <token> <key-authorization>`
+    #[clap(allow_hyphen_values = true)]
+    pub args: Vec<String>,
+
+    /// The exec mode, as lego's `EXEC_MODE` environment variable. Possible values: `default` or `raw`
+    #[clap(long, env = "EXEC_MODE", default_value = "default")]
+    pub exec_mode: LegoExecMode,
+
+    /// The path to the config file
+    /// If not provided, the default value is used, config.yaml
+    #[clap(long, default_value_t = super::DEFAULT_CONFIG_FILE_PATH.to_string())]
+    pub config_file_path: String,
+
+    /// Proxy address, such as http, https or socks5, through which the connections to the API will be made
+    /// Example: socks5://example.com:1080
+    /// This helps to circumvent IP whitelisting requirements for some DNS providers
+    #[clap(long)]
+    pub proxy: Option<String>,
+}
+
+impl LegoExecRunOptions {
+    pub fn check(&self) -> Result<(), String> {
+        if self.operation.is_none() {
+            return Err("Operation not provided".to_string());
+        }
+
+        let expected_args_count = match self.exec_mode {
+            LegoExecMode::Default => 2,
+            LegoExecMode::Raw => 3,
+        };
+
+        if self.args.len() != expected_args_count {
+            return Err(format!(
+                "Expected {} arguments after the operation in {:?} exec mode, but found {}: {:?}",
+                expected_args_count,
+                self.exec_mode,
+                self.args.len(),
+                self.args
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn into_simplified(self) -> SimplifiedLegoExecRunOptions {
+        let mut args = self.args.into_iter();
+        let mut next_arg = || args.next().unwrap();
+
+        let request = match self.exec_mode {
+            LegoExecMode::Default => LegoRequest::Default {
+                fqdn: next_arg(),
+                value: next_arg(),
+            },
+            LegoExecMode::Raw => LegoRequest::Raw {
+                domain: next_arg(),
+                token: next_arg(),
+                key_authorization: next_arg(),
+            },
+        };
+
+        SimplifiedLegoExecRunOptions {
+            operation: self.operation.unwrap(),
+            request,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LegoRequest {
+    Default {
+        fqdn: String,
+        value: String,
+    },
+    Raw {
+        domain: String,
+        token: String,
+        key_authorization: String,
+    },
+}
+
+pub struct SimplifiedLegoExecRunOptions {
+    pub operation: LegoOperation,
+    pub request: LegoRequest,
+}
@@ -1,7 +1,13 @@
 use clap::{Parser, Subcommand};
 
+pub mod acme_sh_run_options;
 pub mod certbot_run_options;
+pub mod dhcp_leases_run_options;
+pub mod dns_persist_run_options;
 pub mod dyndns_run_options;
+pub mod issue_run_options;
+pub mod lego_exec_run_options;
+pub mod serve_dyndns2_run_options;
 pub mod test_domain_controllers_run_options;
 
 const DEFAULT_CONFIG_FILE_PATH: &str = "config.yaml";
@@ -17,10 +23,35 @@ pub enum RunCommand {
     /// Run the certbot mode to do the DNS-01 test.
     Certbot(certbot_run_options::CertbotRunOptions),
 
+    /// Run as lego's `exec` DNS provider (`EXEC_PATH`), to do the DNS-01 test.
+    /// Supports both the default and the `EXEC_MODE=RAW` calling conventions.
+    LegoExec(lego_exec_run_options::LegoExecRunOptions),
+
+    /// Run as an acme.sh dnsapi backend (`dns_samdns_add`/`dns_samdns_rm`), to do the DNS-01 test.
+    /// See the script dns_samdns.sh
+    AcmeSh(acme_sh_run_options::AcmeShRunOptions),
+
+    /// Manage the persistent DNS validation records (dns-persist-01) in `_validation-persist.<domain>`.
+    /// Unlike DNS-01, these records are set once and stay, naming the CA and the ACME account that may issue.
+    DnsPersist(dns_persist_run_options::DnsPersistRunOptions),
+
+    /// Issue a certificate end-to-end with the built-in ACME (RFC 8555) client,
+    /// solving the DNS-01 challenges through the domain controllers in the config file.
+    /// No certbot or hook scripts are needed.
+    Issue(issue_run_options::IssueRunOptions),
+
     /// Run the dyndns mode to update the DNS record of a specific subdomain
     /// from the routable IP address of the machine where this program is running.
     Dyndns(dyndns_run_options::DynDnsRunOptions),
 
+    /// Run an HTTP server that speaks the dyndns2 protocol (`/nic/update`), so that routers and other
+    /// dyndns clients can push their address. The users and the hostnames each may update are in the config file.
+    ServeDyndns2(serve_dyndns2_run_options::ServeDynDns2RunOptions),
+
+    /// Publish the hosts of DHCP lease files (dnsmasq, ISC dhcpd or Kea) as A/AAAA records under a subdomain,
+    /// e.g., `nas.lan.example.com`. Only the records created by this command are ever updated or removed.
+    DhcpLeases(dhcp_leases_run_options::DhcpLeasesRunOptions),
+
     /// Test domain controllers. These tests ensure that the configuration is correct
     /// and that all the implementations do what they're expected to do. If the tests
     /// work, then all features will work.
@@ -0,0 +1,46 @@
+use std::net::SocketAddr;
+
+use clap::Parser;
+
+#[derive(Parser, Clone, Debug, Default)]
+pub struct ServeDynDns2RunOptions {
+    /// The address and port the HTTP server listens on. Routers send their updates to
+    /// `http://<address>:<port>/nic/update?hostname=...&myip=...`
+    #[clap(long, default_value = "0.0.0.0:8245")]
+    pub listen: String,
+
+    /// The path to the config file
+    /// If not provided, the default value is used, config.yaml
+    /// The users of the server and their permitted hostnames are in its `dyndns2_server` section
+    #[clap(long, default_value_t = super::DEFAULT_CONFIG_FILE_PATH.to_string())]
+    pub config_file_path: String,
+
+    /// Proxy address, such as http, https or socks5, through which the connections to the API will be made
+    /// Example: socks5://example.com:1080
+    /// This helps to circumvent IP whitelisting requirements for some DNS providers
+    #[clap(long)]
+    pub proxy: Option<String>,
+}
+
+impl ServeDynDns2RunOptions {
+    pub fn check(&self) -> Result<(), String> {
+        if self.listen.parse::<SocketAddr>().is_err() {
+            return Err(format!(
+                "Listen address `{}` is not a valid address and port",
+                self.listen
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn into_simplified(self) -> SimplifiedServeDynDns2RunOptions {
+        SimplifiedServeDynDns2RunOptions {
+            listen: self.listen.parse().unwrap(),
+        }
+    }
+}
+
+pub struct SimplifiedServeDynDns2RunOptions {
+    pub listen: SocketAddr,
+}
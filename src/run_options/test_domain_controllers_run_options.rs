@@ -1,5 +1,9 @@
+use std::path::PathBuf;
+
 use clap::Parser;
 
+use crate::features::tester::report::ReportFormat;
+
 #[derive(Parser, Clone, Debug, Default)]
 pub struct TestDomainControllersRunOptions {
     /// The path to the config file
@@ -12,4 +16,34 @@ pub struct TestDomainControllersRunOptions {
     /// This helps to circumvent IP whitelisting requirements for some DNS providers
     #[clap(long)]
     pub proxy: Option<String>,
+
+    /// Instead of testing, remove the test records (named `samdnstest-...`) that earlier runs left behind,
+    /// e.g., when they were killed before they could clean up
+    #[clap(long)]
+    pub cleanup_leftovers: bool,
+
+    /// Also write the results (per domain and test case, with durations and failure reasons) to --report-file,
+    /// for CI and monitoring systems. Possible values: junit (JUnit XML), json, tap
+    #[clap(long)]
+    pub report_format: Option<ReportFormat>,
+
+    /// The file the report is written to, even when tests fail. Requires --report-format
+    #[clap(long)]
+    pub report_file: Option<PathBuf>,
+}
+
+impl TestDomainControllersRunOptions {
+    pub fn check(&self) -> Result<(), String> {
+        match (&self.report_format, &self.report_file) {
+            (Some(_), None) => return Err("--report-format requires --report-file".to_string()),
+            (None, Some(_)) => return Err("--report-file requires --report-format".to_string()),
+            _ => {}
+        }
+
+        if self.cleanup_leftovers && self.report_format.is_some() {
+            return Err("--cleanup-leftovers doesn't produce a report".to_string());
+        }
+
+        Ok(())
+    }
 }
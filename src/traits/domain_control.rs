@@ -41,6 +41,7 @@ impl FromStr for DnsRecordType {
         match s.to_lowercase().as_str() {
             "a" => Ok(DnsRecordType::A),
             "aaaa" => Ok(DnsRecordType::AAAA),
+            "caa" => Ok(DnsRecordType::CAA),
             "cname" => Ok(DnsRecordType::CNAME),
             "mx" => Ok(DnsRecordType::MX),
             "ns" => Ok(DnsRecordType::NS),
@@ -57,10 +58,45 @@ impl FromStr for DnsRecordType {
 pub struct DnsRecord {
     pub name: String,
     pub record_type: DnsRecordType,
+    /// For MX and SRV records, without the priority, e.g., `mail.example.com` (see `priority`)
     pub value: String,
+    /// In seconds. None if the provider doesn't report it.
+    pub ttl: Option<u32>,
+    /// The priority of MX and SRV records. None for other types, or if the provider doesn't report it.
+    pub priority: Option<u32>,
+}
+
+/// What a provider supports, e.g., so that the tester only checks what applies
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProviderCapabilities {
+    /// The record types that can be added, listed and removed
+    pub record_types: Vec<DnsRecordType>,
+    /// The TTL of add_dns_record_with_ttl is applied, and reported when listing
+    pub ttl: bool,
+    /// add_dns_record_with_priority is supported, and the priority is reported when listing
+    pub priority: bool,
+}
+
+impl Default for ProviderCapabilities {
+    /// TXT records only, as needed by the DNS-01 challenges
+    fn default() -> Self {
+        Self {
+            record_types: vec![DnsRecordType::TXT],
+            ttl: false,
+            priority: false,
+        }
+    }
 }
 
 pub trait DomainController {
+    /// A short name of the DNS provider, e.g., `epik`, for logs and reports
+    fn provider_name(&self) -> &'static str;
+
+    /// What the provider supports. Providers that don't say support TXT records only.
+    fn capabilities(&self) -> ProviderCapabilities {
+        ProviderCapabilities::default()
+    }
+
     /// Add a DNS record to the domain provided
     fn add_dns_record(
         &self,
@@ -70,6 +106,40 @@ pub trait DomainController {
         value: &str,
     ) -> Result<(), Box<dyn std::error::Error>>;
 
+    /// Add a DNS record with the provided TTL (in seconds), instead of the provider's default.
+    /// Providers that cannot set the TTL add the record with their default TTL.
+    fn add_dns_record_with_ttl(
+        &self,
+        client_maker: &dyn Fn() -> reqwest::blocking::Client,
+        name: &str,
+        record_type: DnsRecordType,
+        value: &str,
+        _ttl: u32,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.add_dns_record(client_maker, name, record_type, value)
+    }
+
+    /// Add a record that has a priority, i.e., MX and SRV records, such as the preference of a mail server.
+    /// The TTL, if provided, is used instead of the provider's default.
+    /// Providers without priorities (see capabilities) return an error.
+    fn add_dns_record_with_priority(
+        &self,
+        _client_maker: &dyn Fn() -> reqwest::blocking::Client,
+        name: &str,
+        record_type: DnsRecordType,
+        _value: &str,
+        _priority: u32,
+        _ttl: Option<u32>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        Err(format!(
+            "{} does not support record priorities, needed by the {} record {}",
+            self.provider_name(),
+            record_type,
+            name
+        )
+        .into())
+    }
+
     /// Remove a DNS record from the domain provided with the provided name (subdomain) and value.
     /// If value is None, all records with the provided name will be removed.
     /// Returns the number of records removed